@@ -0,0 +1,55 @@
+//! Piecewise-linear approximations of the squashing functions, which only need the comparisons
+//! and truncations that ReLU already uses.
+
+use ndarray::Array1;
+
+use crate::{layer::relu6::clip, message::IO, offline::OfflineMaterial, truncation, Com};
+
+/// Computes min(max(x / 4 + 1/2, 0), 1), which approximates the logistic sigmoid around 0.
+pub(crate) fn hard_sigmoid_locally(x: Com) -> Com {
+    (x / Com::from_num(4) + Com::from_num(0.5)).clamp(Com::ZERO, Com::from_num(1))
+}
+
+/// Computes min(max(x, -1), 1), which approximates tanh around 0.
+///
+/// Like the functions themselves, hard_tanh(x) = 2 · hard_sigmoid(2x) - 1.
+pub(crate) fn hard_tanh_locally(x: Com) -> Com {
+    x.clamp(Com::from_num(-1), Com::from_num(1))
+}
+
+/// The secure counterpart of `hard_sigmoid_locally`. Takes as many rounds as a ReLU.
+pub(crate) async fn hard_sigmoid<const PARTY: bool>(
+    x_share: &Array1<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<Com>> {
+    // Dividing by 4 is a local truncation, and only one party should add the (public) offset
+    let mut y_share = truncation::truncate::<PARTY, _>(x_share, 2);
+    if PARTY {
+        y_share += Com::from_num(0.5);
+    }
+
+    clip::<PARTY>(&y_share, Com::from_num(1), material, (sender, receiver)).await
+}
+
+/// The secure counterpart of `hard_tanh_locally`, computed as clip(x + 1, 0, 2) - 1. Takes as
+/// many rounds as a ReLU.
+pub(crate) async fn hard_tanh<const PARTY: bool>(
+    x_share: &Array1<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<Com>> {
+    let y_share = if PARTY {
+        x_share + Com::from_num(1)
+    } else {
+        x_share.clone()
+    };
+
+    let clipped_share =
+        clip::<PARTY>(&y_share, Com::from_num(2), material, (sender, receiver)).await?;
+    Ok(if PARTY {
+        clipped_share - Com::from_num(1)
+    } else {
+        clipped_share
+    })
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{command, Parser};
+use image::io::Reader as ImageReader;
+use log::warn;
+use ndarray::{ArrayD, IxDyn};
+
+use neuronveil::calibration::{self, FloatModel};
+use neuronveil::preprocessing::preprocess_image;
+use neuronveil::quantization::Rounding;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The model, with real-valued tensors
+    model: PathBuf,
+
+    /// The samples, either a JSON array of inputs or a directory of images, which are preprocessed
+    /// as the header of the model says
+    samples: PathBuf,
+
+    /// Where to write the quantized model
+    #[arg(short, long, default_value = "model.json")]
+    output: PathBuf,
+
+    /// The integer bits to keep free beyond the ranges of the samples
+    #[arg(long, default_value_t = calibration::DEFAULT_HEADROOM_BITS)]
+    headroom_bits: u32,
+
+    /// How to round the weights to the fixed-point format
+    #[arg(long, value_enum, default_value_t = Rounding::Nearest)]
+    rounding: Rounding,
+}
+
+fn read_samples(path: &Path, model: &FloatModel) -> anyhow::Result<Vec<ArrayD<f64>>> {
+    let input_shape = model.header.input_shape.as_deref();
+
+    if path.is_dir() {
+        let mut paths = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                let image = ImageReader::open(path)?
+                    .decode()
+                    .with_context(|| format!("Failed to load {}", path.display()))?;
+                let input = preprocess_image(image, &model.header.preprocessing, input_shape)?;
+                Ok(input.mapv(f64::from))
+            })
+            .collect()
+    } else {
+        let samples: serde_json::Value = neuronveil::binary::read(path)?;
+        calibration::samples_from_json(&samples)?
+            .into_iter()
+            .map(|sample| match input_shape {
+                Some(input_shape) => Ok(sample.into_shape(IxDyn(input_shape))?),
+                None => Ok(sample),
+            })
+            .collect()
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    flexi_logger::Logger::try_with_env()
+        .unwrap()
+        .start()
+        .unwrap();
+
+    let args = Args::parse();
+
+    let model: FloatModel = neuronveil::binary::read(&args.model)?;
+    let samples = read_samples(&args.samples, &model)?;
+
+    let calibration = model.calibrate(&samples, args.headroom_bits)?;
+    println!("{}", calibration);
+
+    let (quantized, report) = model.quantize(&calibration, args.rounding)?;
+    println!("{}", report);
+    if report.saturated() > 0 {
+        warn!("Some weights are out of the range of the fixed-point format");
+    }
+
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &quantized)?;
+
+    Ok(())
+}
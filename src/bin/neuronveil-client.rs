@@ -41,7 +41,7 @@ async fn main() -> anyhow::Result<()> {
             tokio::io::copy(&mut stream, &mut buffer).await.unwrap();
 
             // Parse it
-            let message: Message = serde_json::from_slice(&buffer).unwrap();
+            let message = Message::from_bytes(&buffer).unwrap();
             debug!("Received a message: {:?}", message);
 
             // Process it
@@ -56,7 +56,7 @@ async fn main() -> anyhow::Result<()> {
         while let Some(message) = outcoming_receiver.recv().await {
             let mut stream = connection_handle.open_send_stream().await.unwrap(); // TODO handle errors!
 
-            let buffer = serde_json::to_vec(&message).unwrap().try_into().unwrap();
+            let buffer = message.to_bytes().unwrap().try_into().unwrap();
             debug!("Attempting to send a message!");
 
             stream.send(buffer).await.expect("stream should be open");
@@ -64,14 +64,18 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    let header =
+        neuronveil::client::receive_header((&outcoming_sender, &mut incoming_receiver)).await?;
+    debug!("Received the model header: {:?}", header);
+
     let output = neuronveil::client::infer(
         (&outcoming_sender, &mut incoming_receiver),
-        array![1f32, 1f32, -1f32, -1f32],
+        array![1f32, 1f32, -1f32, -1f32].into_dyn(),
         system_random.as_ref(),
     )
     .await?;
 
-    println!("Output: {:#}", output);
+    println!("Output: {:#}", header.postprocess(output));
 
     // FIXME this shouldn't be needed
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{command, Parser, ValueEnum};
+
+use neuronveil::{binary, model::Model};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Binary,
+}
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The model to convert, in either format
+    input: PathBuf,
+
+    /// Where to write the converted model
+    output: PathBuf,
+
+    /// Defaults to JSON for .json outputs, and to the binary format otherwise
+    #[arg(long, value_enum)]
+    to: Option<Format>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let model: Model = binary::read(&args.input)?;
+
+    let format = args.to.unwrap_or_else(|| {
+        match args
+            .output
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => Format::Json,
+            _ => Format::Binary,
+        }
+    });
+    let bytes = match format {
+        Format::Json => serde_json::to_vec_pretty(&model)?,
+        Format::Binary => binary::to_vec(&model)?,
+    };
+    std::fs::write(&args.output, bytes)
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{command, Parser};
+use ring::rand::SystemRandom;
+
+use neuronveil::inspection::{self, Traffic};
+use neuronveil::model::Model;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The model to inspect
+    #[arg(default_value = "model.json")]
+    model: PathBuf,
+
+    /// The shape of the input, e.g. 1,8,8; defaults to the one the model declares
+    #[arg(long, value_delimiter = ',')]
+    input_shape: Option<Vec<usize>>,
+
+    /// Estimate the costs of keeping the weights on the server
+    #[arg(long)]
+    private_weights: bool,
+}
+
+fn format_traffic(traffic: &Traffic) -> String {
+    format!("{} B / {} rounds", traffic.bytes, traffic.rounds)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let model: Model = neuronveil::binary::read(&args.model)?;
+    let input_shape = args
+        .input_shape
+        .or_else(|| model.header.input_shape.clone())
+        .context("The model declares no input shape, pass --input-shape")?;
+
+    let rng = SystemRandom::new();
+    let summary = inspection::inspect(&model, &input_shape, args.private_weights, &rng).await?;
+
+    println!(
+        "{:<16} {:<22} {:<16} {:>10} {:>24} {:>24}",
+        "Node", "Type", "Output shape", "Parameters", "Online", "Offline"
+    );
+    for node in &summary.nodes {
+        println!(
+            "{:<16} {:<22} {:<16} {:>10} {:>24} {:>24}",
+            node.name,
+            node.kind,
+            format!("{:?}", node.output_shape),
+            node.parameters,
+            format_traffic(&node.online),
+            format_traffic(&node.offline)
+        );
+    }
+
+    println!();
+    println!("Input shape: {:?}", input_shape);
+    println!(
+        "Parameters: {}",
+        summary
+            .nodes
+            .iter()
+            .map(|node| node.parameters)
+            .sum::<usize>()
+    );
+    println!("Model share: {} B", summary.model_share_bytes);
+    println!("Online: {}", format_traffic(&summary.online()));
+    println!("Offline: {}", format_traffic(&summary.offline()));
+    println!("NOTE multiplication triplets are not included in the offline costs");
+
+    Ok(())
+}
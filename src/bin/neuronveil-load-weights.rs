@@ -0,0 +1,61 @@
+use std::{fs::File, io::BufReader, io::BufWriter, path::PathBuf};
+
+use anyhow::{bail, Context};
+use clap::{command, Parser};
+use log::warn;
+
+use neuronveil::quantization::Rounding;
+use neuronveil::weights::{self, NpzFile, SafeTensorsFile, TensorSource};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The architecture, a model whose tensors are references such as {"tensor": "fc1.weight"}
+    architecture: PathBuf,
+
+    /// The weights, a .npz or .safetensors file
+    weights: PathBuf,
+
+    /// Where to write the model
+    #[arg(short, long, default_value = "model.json")]
+    output: PathBuf,
+
+    /// How to round the weights to the fixed-point format
+    #[arg(long, value_enum, default_value_t = Rounding::Nearest)]
+    rounding: Rounding,
+}
+
+fn main() -> anyhow::Result<()> {
+    flexi_logger::Logger::try_with_env()
+        .unwrap()
+        .start()
+        .unwrap();
+
+    let args = Args::parse();
+
+    let file = File::open(&args.architecture)
+        .with_context(|| format!("Failed to open {}", args.architecture.display()))?;
+    let architecture = serde_json::from_reader(BufReader::new(file))?;
+
+    let mut source: Box<dyn TensorSource> = match args
+        .weights
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("npz") => Box::new(NpzFile::open(&args.weights)?),
+        Some("safetensors") => Box::new(SafeTensorsFile::open(&args.weights)?),
+        _ => bail!("The weights should be a .npz or .safetensors file"),
+    };
+    let (model, report) = weights::load(architecture, source.as_mut(), args.rounding)?;
+
+    println!("{}", report);
+    if report.saturated() > 0 {
+        warn!("Some weights are out of the range of the fixed-point format");
+    }
+
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &model)?;
+
+    Ok(())
+}
@@ -1,14 +1,18 @@
 use anyhow::Context;
 use clap::{command, Parser};
-use image::{imageops::FilterType, io::Reader as ImageReader, GrayImage};
-use log::debug;
-use ndarray::{array, Array1};
-use neuronveil::{message::Message, model::Model, Com};
+use image::{io::Reader as ImageReader, DynamicImage};
+use log::{debug, warn};
+use ndarray::ArrayD;
+use neuronveil::{
+    message::Message,
+    model::{Model, OnOverflow},
+    model_header::ModelHeader,
+    preprocessing::{preprocess_image, Preprocessing},
+    Com,
+};
 use ring::rand::{SecureRandom, SystemRandom};
 use s2n_quic::{client::Connect, Client};
-use std::{
-    error::Error, fs::File, io::BufReader, net::SocketAddr, path::Path, sync::Arc, time::Duration,
-};
+use std::{error::Error, net::SocketAddr, path::Path, sync::Arc, time::Duration};
 use tokio::sync::mpsc;
 
 #[derive(Parser)]
@@ -27,27 +31,42 @@ struct Args {
     /// Server name per the QUIC protocol
     #[arg(long, default_value = "localhost")]
     server_name: String,
-}
 
-fn load_image<P: AsRef<Path>>(filepath: P) -> anyhow::Result<Array1<f32>> {
-    // Read the image
-    let raw_image = ImageReader::open(filepath)?.decode()?;
+    /// When inferring locally, check the activations for overflows, and fail or saturate
+    #[arg(long, value_enum)]
+    on_overflow: Option<OnOverflow>,
+}
 
-    // Resize the image to the model's input size and convert init to a greyscale buffer
-    let image: GrayImage = raw_image
-        .resize_exact(8, 8, FilterType::Nearest)
-        .grayscale()
-        .into();
+/// Prepares the image as the model's header says.
+///
+/// Models which declare no preprocessing are assumed to take the 8x8 digits, with values in
+/// [0, 16), flattened, as this client always did before models had headers.
+fn load_input(image: DynamicImage, header: &ModelHeader) -> anyhow::Result<ArrayD<f32>> {
+    let (preprocessing, input_shape) = if header.preprocessing.is_empty() {
+        (
+            vec![
+                Preprocessing::Resize {
+                    width: 8,
+                    height: 8,
+                },
+                Preprocessing::Grayscale,
+                Preprocessing::Scale { factor: 1.0 / 16.0 },
+            ],
+            header.input_shape.clone().or(Some(vec![64])),
+        )
+    } else {
+        (header.preprocessing.clone(), header.input_shape.clone())
+    };
 
-    // Convert the image to an array with values in range [0, 16)
-    Ok(Array1::from_iter(image.pixels().map(|v| v.0[0] as f32)) / 16.0)
+    preprocess_image(image, &preprocessing, input_shape.as_deref())
+        .context("Failed to preprocess the input image")
 }
 
 async fn infer_online(
-    input: Array1<f32>,
+    image: DynamicImage,
     server: SocketAddr,
     server_name: String,
-) -> anyhow::Result<Array1<f32>> {
+) -> anyhow::Result<(ArrayD<f32>, ModelHeader)> {
     // Connect to the server
     debug!("Attempting to connect to {}", server);
     let client = Client::builder()
@@ -74,7 +93,7 @@ async fn infer_online(
             tokio::io::copy(&mut stream, &mut buffer).await.unwrap();
 
             // Parse it
-            let message: Message = serde_json::from_slice(&buffer).unwrap();
+            let message = Message::from_bytes(&buffer).unwrap();
             debug!("Received a message: {:?}", message);
 
             // Process it
@@ -89,7 +108,7 @@ async fn infer_online(
         while let Some(message) = outcoming_receiver.recv().await {
             let mut stream = connection_handle.open_send_stream().await.unwrap(); // TODO handle errors!
 
-            let buffer = serde_json::to_vec(&message).unwrap().try_into().unwrap();
+            let buffer = message.to_bytes().unwrap().try_into().unwrap();
             debug!("Attempting to send a message!");
 
             stream.send(buffer).await.expect("stream should be open");
@@ -97,6 +116,10 @@ async fn infer_online(
         }
     });
 
+    let header =
+        neuronveil::client::receive_header((&outcoming_sender, &mut incoming_receiver)).await?;
+    let input = load_input(image, &header)?;
+
     let output = neuronveil::client::infer(
         (&outcoming_sender, &mut incoming_receiver),
         input,
@@ -107,7 +130,7 @@ async fn infer_online(
     // FIXME this shouldn't be needed
     tokio::time::sleep(Duration::from_millis(100)).await;
 
-    Ok(output)
+    Ok((output, header))
 }
 
 #[tokio::main]
@@ -120,33 +143,50 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
-    let input = load_image(args.image).context("Failed to load the input image")?;
-
-    let output = if let Some(model) = args.model {
-        // Convert the input from float to Com
-        let input_com = input.mapv(Com::from_num);
+    let image = ImageReader::open(args.image)?
+        .decode()
+        .context("Failed to load the input image")?;
 
+    let (output, header) = if let Some(model) = args.model {
         // Read the model file
-        let model_file = File::open(model)?;
-        let reader = BufReader::new(model_file);
-        let model: Model = serde_json::from_reader(reader)?;
+        let model: Model = neuronveil::binary::read(model)?;
+
+        // Convert the input from float to Com
+        let input_com = load_input(image, &model.header)?.mapv(Com::from_num);
 
         // Infer locally
-        let output_com = model.infer_locally(input_com);
+        let output_com = match args.on_overflow {
+            Some(on_overflow) => {
+                let checked = model.infer_checked(input_com, on_overflow)?;
+                for node in checked.unchecked {
+                    warn!("Node '{}' was not checked for overflows", node);
+                }
+                for overflow in checked.saturated {
+                    warn!("Saturated: {}", overflow);
+                }
+                checked.output
+            }
+            None => model.infer_locally(input_com)?,
+        };
 
         // Convert the output from Com to float
-        output_com.mapv(Com::to_num::<f32>)
+        (output_com.mapv(Com::to_num::<f32>), model.header)
     } else {
         // Infer online, without knowing the model
-        infer_online(input, args.server, args.server_name)
+        infer_online(image, args.server, args.server_name)
             .await
             .context("Online inference failed")?
     };
 
-    println!(
-        "Output: {:#}",
-        neuronveil::utils::softmax(&output.view()).unwrap()
-    );
+    let output = header.postprocess(output);
+    let probabilities = neuronveil::utils::softmax(&output.view()).unwrap();
+    if header.labels.is_empty() {
+        println!("Output: {:#}", probabilities);
+    } else {
+        for (label, probability) in header.labels.iter().zip(&probabilities) {
+            println!("{}: {:.3}", label, probability);
+        }
+    }
 
     // // FIXME this shouldn't be needed
     // tokio::time::sleep(Duration::from_millis(100)).await;
@@ -0,0 +1,36 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use anyhow::Context;
+use clap::{command, Parser};
+
+use neuronveil::onnx;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The ONNX model to import
+    input: PathBuf,
+
+    /// Where to write the neuronveil model
+    #[arg(short, long, default_value = "model.json")]
+    output: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let bytes = std::fs::read(&args.input)
+        .with_context(|| format!("Failed to read {}", args.input.display()))?;
+    let model = onnx::import(&bytes)?;
+
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &model)?;
+    println!(
+        "Imported {} nodes into {}",
+        model.nodes.len(),
+        args.output.display()
+    );
+
+    Ok(())
+}
@@ -1,5 +1,7 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::path::Path;
 
+use anyhow::Context;
+use clap::{command, Parser};
 use flexi_logger;
 use log::debug;
 use ring::rand::SecureRandom;
@@ -12,6 +14,18 @@ use neuronveil::model::Model;
 use neuronveil::split::Split;
 use tokio::task;
 
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The model to serve
+    #[arg(default_value = "model.json")]
+    model: std::path::PathBuf,
+
+    /// Keep the weights on the server and never send the client a share of them
+    #[arg(long)]
+    private_weights: bool,
+}
+
 // thread_local! {
 //     static SYSTEM_RANDOM: RefCell<Option<SystemRandom>> = RefCell::new(None); // NOTE a Cell/RefCell might be needed
 // }
@@ -24,10 +38,16 @@ async fn main() -> anyhow::Result<()> {
         .start()
         .unwrap();
 
+    let args = Args::parse();
+
     debug!("Reading the model");
-    let file = File::open("model.json")?;
-    let reader = BufReader::new(file);
-    let model: Model = serde_json::from_reader(reader)?;
+    let model: Model = neuronveil::binary::read(args.model)?;
+    if args.private_weights {
+        // Refuse models with layers which would leak their weights before accepting anyone
+        model
+            .split_private_weights(&SystemRandom::new())
+            .context("The model's weights cannot be kept private")?;
+    }
 
     debug!("Starting the server");
     let mut server = Server::builder()
@@ -42,9 +62,13 @@ async fn main() -> anyhow::Result<()> {
         let local_model = model.clone();
         local
             .run_until(async move {
-                tokio::task::spawn_local(handle_connection(connection, local_model))
-                    .await
-                    .unwrap();
+                tokio::task::spawn_local(handle_connection(
+                    connection,
+                    local_model,
+                    args.private_weights,
+                ))
+                .await
+                .unwrap();
             })
             .await;
     }
@@ -52,7 +76,7 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn handle_connection(connection: Connection, model: Model) {
+async fn handle_connection(connection: Connection, model: Model, private_weights: bool) {
     debug!("Initialising the task-local(!) CSPRNG");
     let system_random = SystemRandom::new();
     system_random.fill(&mut [0u8; 4]).unwrap();
@@ -71,7 +95,7 @@ async fn handle_connection(connection: Connection, model: Model) {
             tokio::io::copy(&mut stream, &mut buffer).await.unwrap();
 
             // Parse it
-            let message: Message = serde_json::from_slice(&buffer).unwrap();
+            let message = Message::from_bytes(&buffer).unwrap();
             debug!("Received a message: {:?}", message);
 
             // Process it
@@ -86,7 +110,7 @@ async fn handle_connection(connection: Connection, model: Model) {
         while let Some(message) = outcoming_receiver.recv().await {
             let mut stream = connection_handle.open_send_stream().await.unwrap(); // TODO handle errors!
 
-            let buffer = serde_json::to_vec(&message).unwrap().try_into().unwrap();
+            let buffer = message.to_bytes().unwrap().try_into().unwrap();
 
             stream.send(buffer).await.expect("stream should be open");
             stream.close().await.unwrap();
@@ -95,12 +119,19 @@ async fn handle_connection(connection: Connection, model: Model) {
 
     // Split the model into shares
     // TODO This should be done in advance
-    let model_shares = model.split(&system_random);
+    let model_shares = if private_weights {
+        model
+            .split_private_weights(&system_random)
+            .expect("the model should have been checked on startup")
+    } else {
+        model.split(&system_random)
+    };
 
     // Start infering
     debug!("Starting the inference");
     neuronveil::server::infer(
         (&outcoming_sender, &mut incoming_receiver),
+        &model.header,
         model_shares,
         &system_random,
     )
@@ -0,0 +1,105 @@
+//! A compact binary format for models and messages: a magic number, a version and MessagePack.
+//!
+//! Structs are written positionally, as arrays without their field names, and tensors as their
+//! shape and elements. Version 1 wrote structs as maps, which is still read.
+//!
+//! JSON stays supported wherever the binary format is read, told apart by the magic number.
+
+use std::path::Path;
+
+use anyhow::{bail, Context as _};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Starts every file and message in the binary format.
+pub const MAGIC: &[u8; 4] = b"NVL\x00";
+
+/// The latest version of the binary format.
+pub const VERSION: u8 = 2;
+
+/// Whether the bytes are in the binary format rather than JSON.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn to_vec<T: Serialize>(value: &T) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::from(*MAGIC);
+    bytes.push(VERSION);
+    // Internally tagged enums put their tag first and flattened fields stay maps, so both read back
+    rmp_serde::encode::write(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    if !is_binary(bytes) {
+        bail!("The data does not start with the magic number of the binary format");
+    }
+    match bytes.get(MAGIC.len()) {
+        Some(&version) if version <= VERSION => {}
+        Some(&version) => bail!(
+            "The binary format version {} is newer than the supported version {}",
+            version,
+            VERSION
+        ),
+        None => bail!("The data ends before the version of the binary format"),
+    }
+
+    rmp_serde::from_slice(&bytes[MAGIC.len() + 1..]).context("Invalid binary data")
+}
+
+/// Reads either the binary format or JSON.
+pub fn from_slice_or_json<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    if is_binary(bytes) {
+        from_slice(bytes)
+    } else {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Reads a file in either the binary format or JSON.
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>) -> anyhow::Result<T> {
+    let path = path.as_ref();
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    from_slice_or_json(&bytes).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+#[test]
+fn test_binary_round_trip() {
+    use crate::{message::Message, model::Model, split::Split, Com};
+
+    let json = std::fs::read("models/multiple_layers.json").unwrap();
+    let model: Model = serde_json::from_slice(&json).unwrap();
+
+    let bytes = to_vec(&model).unwrap();
+    // The JSON of the model is indented, so compare with it written compactly
+    let compact_json = serde_json::to_vec(&model).unwrap();
+    assert!(bytes.len() * 2 < compact_json.len());
+    let decoded: Model = from_slice_or_json(&bytes).unwrap();
+    let input = ndarray::arr1(&[1.0, 2.0]).mapv(Com::from_num).into_dyn();
+    assert_eq!(
+        decoded.infer_locally(input.clone()).unwrap(),
+        model.infer_locally(input).unwrap()
+    );
+
+    // Messages, e.g. a model share
+    let rng = ring::rand::SystemRandom::new();
+    let message = Message::ModelShare(model.split(&rng).1);
+    let decoded: Message = from_slice(&to_vec(&message).unwrap()).unwrap();
+    assert!(matches!(decoded, Message::ModelShare(_)));
+    let header = Message::ModelHeader(model.header.clone());
+    let decoded: Message = from_slice(&to_vec(&header).unwrap()).unwrap();
+    assert!(matches!(decoded, Message::ModelHeader(h) if h == model.header));
+
+    // Version 1 wrote structs as maps
+    let mut named = Vec::from(*MAGIC);
+    named.push(1);
+    rmp_serde::encode::write_named(&mut named, &model).unwrap();
+    let decoded: Model = from_slice(&named).unwrap();
+    assert_eq!(decoded.nodes.len(), model.nodes.len());
+    assert!(bytes.len() < named.len());
+
+    let mut newer = bytes.clone();
+    newer[MAGIC.len()] = VERSION + 1;
+    assert!(from_slice::<Model>(&newer).is_err());
+    assert!(from_slice::<Model>(&json).is_err());
+}
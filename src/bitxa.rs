@@ -1,14 +1,20 @@
 use crate::bit;
 use crate::com;
 use crate::message::IO;
-use crate::multiplication_triplet_share::MultiplicationTripletShare;
+use crate::multiplication_triplet_share::{cross_terms, BilinearOperation};
+use crate::paillier::{PaillierPrivateKey, PaillierPublicKey};
 use crate::reconstruct::Reconstruct;
 use crate::reconstruct::ReconstructOnline;
+#[cfg(test)]
+use crate::split::Split as _;
 use crate::Com;
-use anyhow::Context as _;
-use log::debug;
+use anyhow::{bail, Context as _};
+use ndarray::s;
 use ndarray::Array1;
+use ndarray::ArrayD;
 use ndarray::Ix1;
+use ndarray::IxDyn;
+use ndarray::Zip;
 use ring::rand::SecureRandom;
 use serde::Deserialize;
 use serde::Serialize;
@@ -39,83 +45,192 @@ pub struct CapitalDeltas {
     pub y: Array1<bool>,
 }
 
+/// The preprocessing material of BitXA, which does not depend on the inputs.
+///
+/// Every element is independent, so a key for n elements may be cut into keys for fewer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BitXAKey {
+    pub delta_x_share: Array1<Com>,
+    pub delta_y_share: Array1<bool>,
+    /// A share of δy as an integer, i.e. 0 or 1 in raw bits
+    pub arithmatic_delta_y_share: Array1<Com>,
+    /// A share of δx · δy
+    pub delta_z_share: Array1<Com>,
+}
+
+impl BitXAKey {
+    /// A key whose masks are all 0, which is only good for counting how many elements are needed.
+    pub(crate) fn zeros(n: usize) -> Self {
+        BitXAKey {
+            delta_x_share: Array1::zeros(n),
+            delta_y_share: Array1::from_elem(n, false),
+            arithmatic_delta_y_share: Array1::zeros(n),
+            delta_z_share: Array1::zeros(n),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.delta_x_share.len()
+    }
+
+    /// Cuts out the key of elements `start..end`.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> Self {
+        BitXAKey {
+            delta_x_share: self.delta_x_share.slice(s![start..end]).to_owned(),
+            delta_y_share: self.delta_y_share.slice(s![start..end]).to_owned(),
+            arithmatic_delta_y_share: self
+                .arithmatic_delta_y_share
+                .slice(s![start..end])
+                .to_owned(),
+            delta_z_share: self.delta_z_share.slice(s![start..end]).to_owned(),
+        }
+    }
+}
+
+/// Generates our share of the keys of `n` elements without a dealer, so neither party learns the
+/// masks.
+///
+/// Each party samples its own shares of δx and δy. As an integer, δy = δy₀ + δy₁ - 2·δy₀·δy₁,
+/// and δz = δx · δy is the sum of the local products δxᵢ · δyᵢ and of the cross terms, so the
+/// products of the shares are computed with Paillier in two rounds, see [`cross_terms`].
+///
+/// # Parameters
+/// - `n`: the number of elements
+/// - `keys`: our Paillier key pair and the public key of the other party
+/// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+/// - `rng`: A secure random number generator.
+pub(crate) async fn generate_bitxa_key<const PARTY: bool>(
+    n: usize,
+    keys: (&PaillierPrivateKey, &PaillierPublicKey),
+    (sender, receiver): IO<'_>,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<BitXAKey> {
+    let delta_x_share = com::sample(n, rng);
+    let delta_y_share = bit::sample(n, rng);
+
+    // δy₀ · δy₁ is the cross term of a product whose first operand is the server's bits and
+    // whose second is the client's
+    let bits = delta_y_share.mapv(|b| Com::from_bits(b as i32)).into_dyn();
+    let zeros = ArrayD::zeros(IxDyn(&[n]));
+    let (a_share, b_share) = if PARTY {
+        (&bits, &zeros)
+    } else {
+        (&zeros, &bits)
+    };
+    let bits_product_share = cross_terms(
+        &BilinearOperation::Hadamard,
+        a_share,
+        b_share,
+        keys,
+        (sender, receiver),
+        rng,
+    )
+    .await
+    .context("Failed to multiply the shares of δy")?;
+    let arithmatic_delta_y_share = Zip::from(&bits)
+        .and(&bits_product_share)
+        .map_collect(|&bit, &product| bit - product * 2)
+        .into_dimensionality::<Ix1>()?;
+
+    let delta_z_cross_terms_share = cross_terms(
+        &BilinearOperation::Hadamard,
+        &delta_x_share.clone().into_dyn(),
+        &arithmatic_delta_y_share.clone().into_dyn(),
+        keys,
+        (sender, receiver),
+        rng,
+    )
+    .await
+    .context("Failed to multiply the shares of δx and δy")?
+    .into_dimensionality::<Ix1>()?;
+    let delta_z_share = Zip::from(&arithmatic_delta_y_share)
+        .and(&delta_x_share)
+        .and(&delta_z_cross_terms_share)
+        .map_collect(|&delta_y, &delta_x, &cross_term| {
+            multiply_integer(delta_y, delta_x) + cross_term
+        });
+
+    Ok(BitXAKey {
+        delta_x_share,
+        delta_y_share,
+        arithmatic_delta_y_share,
+        delta_z_share,
+    })
+}
+
+/// Deals keys of `n` elements locally, as a trusted dealer would, to test protocols without
+/// running the offline phase.
+#[cfg(test)]
+pub(crate) fn generate_bitxa_keys(n: usize, rng: &dyn SecureRandom) -> (BitXAKey, BitXAKey) {
+    let delta_x = com::sample(n, rng);
+    let delta_y = bit::sample(n, rng);
+    let arithmatic_delta_y = delta_y.mapv(|b| Com::from_bits(b as i32));
+    let delta_z = Zip::from(&delta_x)
+        .and(&delta_y)
+        .map_collect(|&x, &y| if y { x } else { Com::ZERO });
+
+    let delta_x_shares = delta_x.split(rng);
+    let delta_y_shares = delta_y.split(rng);
+    let arithmatic_delta_y_shares = arithmatic_delta_y.split(rng);
+    let delta_z_shares = delta_z.split(rng);
+
+    (
+        BitXAKey {
+            delta_x_share: delta_x_shares.0,
+            delta_y_share: delta_y_shares.0,
+            arithmatic_delta_y_share: arithmatic_delta_y_shares.0,
+            delta_z_share: delta_z_shares.0,
+        },
+        BitXAKey {
+            delta_x_share: delta_x_shares.1,
+            delta_y_share: delta_y_shares.1,
+            arithmatic_delta_y_share: arithmatic_delta_y_shares.1,
+            delta_z_share: delta_z_shares.1,
+        },
+    )
+}
+
+/// Multiplies a share of an integer by a public fixed-point number in the ring, which, unlike
+/// fixed-point multiplication, is linear.
+fn multiply_integer(integer_share: Com, x: Com) -> Com {
+    Com::from_bits(integer_share.0.to_bits().wrapping_mul(x.0.to_bits()))
+}
+
 /// Directly multiply a bit by an integer.
 ///
 /// This is a vectorised implementation of Algorithm no. 1 from
 /// [FssNN: Communication-Efficient Secure Neural Network Training via Function Secret Sharing](https://eprint.iacr.org/2023/073.pdf).
+/// The products of the masks are generated in the offline phase, see [`generate_bitxa_key`], so
+/// only a single online round is required.
 ///
 /// # Arguments
 ///
 /// - `x_share`: Arithmatic values share
 /// - `y_share`: Boolean values share
+/// - `key`: Our share of the masks, of as many elements as x, which shall not be re-used
 /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
-/// - `rng`: A secure random number generator for secure computation.
 ///
 /// # Returns
 ///
 /// A share of the product of x and y
-// FIXME this function is long
 pub async fn bitxa<const PARTY: bool>(
     x_share: &Array1<Com>,
     y_share: &Array1<bool>,
+    key: BitXAKey,
     (sender, receiver): IO<'_>,
-    rng: &dyn SecureRandom,
 ) -> anyhow::Result<Array1<Com>> {
-    let masked_boolean_delta_y_share = bit::sample(x_share.len(), rng);
-
-    let masked_arithmatic_delta_y_share = masked_boolean_delta_y_share.mapv(|b| {
-        if b {
-            Com::from_num(1)
-        } else {
-            Com::from_num(0)
-        }
-    });
-    let (e_share, f_share) = if PARTY {
-        (
-            Array1::zeros(x_share.len()),
-            masked_arithmatic_delta_y_share,
-        )
-    } else {
-        (
-            masked_arithmatic_delta_y_share,
-            Array1::zeros(x_share.len()),
-        )
-    };
-
-    let mt = MultiplicationTripletShare::<Ix1, Ix1>::new(x_share.len()); // TODO these should be generated in advance!
-    let ef_share = mt
-        .hadamard_product::<PARTY>(&e_share, &f_share, (sender, receiver))
-        .await
-        .context("Failed to compute Hadamard product")?;
-
-    let arithmatic_delta_y_share = &e_share + &f_share - (ef_share * 2);
-
-    // FIXME BUG this shouldn't be necessary
-    let delta_x_share = com::sample(x_share.len(), rng) / 256;
-    debug!("delta x share: {:#}", delta_x_share);
-    debug!(
-        "delta y share: {:#} ({:#} respectively)",
-        arithmatic_delta_y_share, masked_boolean_delta_y_share
-    );
-
-    let mt2 = MultiplicationTripletShare::<Ix1, Ix1>::new(x_share.len()); // TODO these should be generated in advance!
-
-    let delta_z_share = mt2
-        .hadamard_product::<PARTY>(
-            &delta_x_share,
-            &arithmatic_delta_y_share,
-            (sender, receiver),
-        )
-        .await
-        .context("Failed to compute Hadamard product")?;
-
-    debug!("delta z share: {:#}", delta_z_share);
+    if key.len() != x_share.len() {
+        bail!(
+            "The BitXA key is of {} elements rather than {}",
+            key.len(),
+            x_share.len()
+        );
+    }
 
-    // NOTE the online stage starts here
     // struct Δx and Δy
     let our_capital_delta_shares = BitXAInteraction {
-        capital_delta_x_share: x_share + &delta_x_share,
-        capital_delta_y_share: y_share ^ &masked_boolean_delta_y_share,
+        capital_delta_x_share: x_share + &key.delta_x_share,
+        capital_delta_y_share: y_share ^ &key.delta_y_share,
     };
     let capital_deltas = our_capital_delta_shares
         .reconstruct_mutually((sender, receiver))
@@ -123,20 +238,92 @@ pub async fn bitxa<const PARTY: bool>(
         .context("Failed to reconstruct Δx and Δy")?;
 
     // This is akin to Δ′y
-    let arithmatic_capital_delta_y = capital_deltas.y.mapv(|b| {
-        if b {
-            Com::from_num(1)
-        } else {
-            Com::from_num(0)
-        }
-    });
+    let arithmatic_capital_delta_y = capital_deltas.y.mapv(i32::from);
 
-    // Complete the computation
-    // TODO merge adjust_product calls
-    let t = &arithmatic_capital_delta_y * &capital_deltas.x;
-    let without_bt = &delta_z_share * (&arithmatic_capital_delta_y * 2) - &delta_z_share
-        + &arithmatic_delta_y_share * (&capital_deltas.x - &t * 2)
-        - &arithmatic_capital_delta_y * &delta_x_share;
+    // Complete the computation:
+    // xy = t + δ′y(Δx - 2t) - Δ′y·δx + δz(2Δ′y - 1) where t = Δx·Δ′y
+    let t = Zip::from(&capital_deltas.x)
+        .and(&arithmatic_capital_delta_y)
+        .map_collect(|&x, &y| x * y);
+    let without_t = Zip::from(&key.arithmatic_delta_y_share)
+        .and(&key.delta_x_share)
+        .and(&key.delta_z_share)
+        .and(&capital_deltas.x)
+        .and(&t)
+        .and(&arithmatic_capital_delta_y)
+        .map_collect(
+            |&delta_y, &delta_x, &delta_z, &capital_delta_x, &t, &capital_delta_y| {
+                multiply_integer(delta_y, capital_delta_x - t * 2) - delta_x * capital_delta_y
+                    + delta_z * (2 * capital_delta_y - 1)
+            },
+        );
+
+    Ok(if PARTY { t + without_t } else { without_t })
+}
+
+#[tokio::test]
+async fn test_bitxa_takes_one_online_round() {
+    use crate::testing;
+    use ndarray::array;
+
+    let rng = ring::rand::SystemRandom::new();
+    let x = array![3, -2, 5, 0, 7].mapv(Com::from_num);
+    let y = array![true, true, false, false, true];
+    let x_shares = x.split(&rng);
+    let y_shares = y.split(&rng);
+
+    // The keys are dealt in the offline phase
+    let keys = generate_bitxa_keys(x.len(), &rng);
+
+    let (mut server, mut client, log) = testing::connect();
+    let (server_output_share, client_output_share) = tokio::join!(
+        bitxa::<true>(&x_shares.0, &y_shares.0, keys.0, server.io()),
+        bitxa::<false>(&x_shares.1, &y_shares.1, keys.1, client.io()),
+    );
+    let output =
+        Array1::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+    assert_eq!(output, array![3, -2, 0, 0, 7].mapv(Com::from_num));
+
+    // The only round trip is the reconstruction of Δx and Δy
+    assert_eq!(log.count(true, "BitXAInteraction"), 1);
+    assert_eq!(log.count(false, "BitXAInteraction"), 1);
+    assert_eq!(log.len(), 2);
+}
+
+#[tokio::test]
+async fn test_generated_bitxa_key_is_consistent() {
+    use crate::multiplication_triplet_share::exchange_paillier_keys;
+    use crate::testing;
+
+    let rng = ring::rand::SystemRandom::new();
+    let n = 6;
+
+    let (mut server, mut client, log) = testing::connect();
+    let (server_keys, client_keys) = tokio::join!(
+        exchange_paillier_keys(server.io(), &rng),
+        exchange_paillier_keys(client.io(), &rng),
+    );
+    let (server_keys, client_keys) = (server_keys.unwrap(), client_keys.unwrap());
+    let (server_key, client_key) = tokio::join!(
+        generate_bitxa_key::<true>(n, (&server_keys.0, &server_keys.1), server.io(), &rng),
+        generate_bitxa_key::<false>(n, (&client_keys.0, &client_keys.1), client.io(), &rng),
+    );
+    let (server_key, client_key) = (server_key.unwrap(), client_key.unwrap());
+
+    let delta_x = &server_key.delta_x_share + &client_key.delta_x_share;
+    let delta_y = &server_key.delta_y_share ^ &client_key.delta_y_share;
+    assert_eq!(
+        &server_key.arithmatic_delta_y_share + &client_key.arithmatic_delta_y_share,
+        delta_y.mapv(|b| Com::from_bits(b as i32))
+    );
+    assert_eq!(
+        &server_key.delta_z_share + &client_key.delta_z_share,
+        Zip::from(&delta_x)
+            .and(&delta_y)
+            .map_collect(|&x, &y| if y { x } else { Com::ZERO })
+    );
 
-    Ok(if PARTY { t + without_bt } else { without_bt })
+    // Two products of the shares, each an operand and a product each way
+    assert_eq!(log.count(true, "EncryptedOperand"), 2);
+    assert_eq!(log.count(true, "EncryptedProduct"), 2);
 }
@@ -0,0 +1,829 @@
+//! Chooses how to quantize a real-valued model by evaluating it in floating-point over samples.
+//!
+//! `Com` has few fractional bits, so most weights and activations would lose their precision if
+//! quantized as they are. Instead, every tensor is scaled by a power of two, chosen from the ranges
+//! seen over the samples s.t. the least precise tensor keeps as many significant bits as possible
+//! without overflowing. The supported layers commute with scaling, so the quantized model computes
+//! the real outputs, scaled. Its header tells clients to scale their inputs and to unscale the
+//! outputs.
+//!
+//! The tensors of the model are read as real numbers, whether or not it declares a `format`.
+
+use std::{collections::HashMap, fmt};
+
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, Array2, Array3, Array4, ArrayD, ArrayView1, Axis, IxDyn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    com,
+    convolution::{
+        convolve, depthwise_convolve, gather_windows, into_feature_map, pad, pooling_window,
+    },
+    layer::{check_biases, Layer},
+    model::{sort_topologically, Model, Node, Operation, SerializedModel, INPUT},
+    model_header::ModelHeader,
+    preprocessing::Preprocessing,
+    quantization::{
+        json_tensor, map_json_tensors, quantize_json, FixedPointFormat, QuantizationReport,
+        Rounding,
+    },
+    shape_mismatch_error::ShapeMismatchError,
+    tensor,
+};
+
+/// The default number of integer bits kept free by the scales.
+///
+/// They make room for values beyond the ranges seen over the samples, and for the secure
+/// multiplications: their products carry twice the fractional bits before truncation, and
+/// truncating a share fails with a probability which halves with every free bit.
+pub const DEFAULT_HEADROOM_BITS: u32 = 8;
+
+fn default_stride() -> usize {
+    1
+}
+
+fn default_epsilon() -> f32 {
+    1e-3
+}
+
+fn default_cap() -> f32 {
+    6.0
+}
+
+/// A layer with real-valued parameters, in the format of `Layer`. Only the layers which commute
+/// with scaling are supported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum FloatLayer {
+    DenseLayer {
+        weights: Array2<f64>,
+        biases: Array1<f64>,
+    },
+    Conv2DLayer {
+        kernels: Array4<f64>,
+        biases: Array1<f64>,
+        #[serde(default = "default_stride")]
+        stride: usize,
+        #[serde(default)]
+        padding: usize,
+    },
+    DepthwiseConv2DLayer {
+        kernels: Array4<f64>,
+        biases: Array1<f64>,
+        #[serde(default = "default_stride")]
+        stride: usize,
+        #[serde(default)]
+        padding: usize,
+    },
+    MaxPool2DLayer {
+        pool_size: usize,
+        #[serde(default)]
+        stride: Option<usize>,
+    },
+    AvgPool2DLayer {
+        pool_size: usize,
+        #[serde(default)]
+        stride: Option<usize>,
+    },
+    GlobalAvgPoolLayer {},
+    BatchNormLayer {
+        mean: Array1<f64>,
+        variance: Array1<f64>,
+        gamma: Array1<f64>,
+        beta: Array1<f64>,
+        #[serde(default = "default_epsilon")]
+        epsilon: f32,
+    },
+    ReLULayer {},
+    LeakyReLULayer {
+        slope: f32,
+    },
+    ReLU6Layer {
+        #[serde(default = "default_cap")]
+        cap: f32,
+    },
+    Upsample2DLayer {
+        scale: usize,
+    },
+    FlattenLayer {},
+}
+
+impl FloatLayer {
+    pub fn infer(&self, input: ArrayD<f64>) -> anyhow::Result<ArrayD<f64>> {
+        Ok(match self {
+            FloatLayer::DenseLayer { weights, biases } => {
+                check_biases(biases.len(), weights.ncols())?;
+                let input = tensor::into_vector(input, weights.nrows())?;
+                (&input.dot(weights) + biases).into_dyn()
+            }
+            FloatLayer::Conv2DLayer {
+                kernels,
+                biases,
+                stride,
+                padding,
+            } => {
+                let (output_channels, input_channels, kernel_height, kernel_width) = kernels.dim();
+                check_biases(biases.len(), output_channels)?;
+                let input = into_feature_map(
+                    input,
+                    input_channels,
+                    (kernel_height, kernel_width),
+                    *padding,
+                )?;
+
+                let output = convolve(
+                    &pad(&input.view(), *padding).view(),
+                    &kernels.view(),
+                    *stride,
+                );
+                add_biases(output, biases)
+            }
+            FloatLayer::DepthwiseConv2DLayer {
+                kernels,
+                biases,
+                stride,
+                padding,
+            } => {
+                let (channels, multiplier, kernel_height, kernel_width) = kernels.dim();
+                check_biases(biases.len(), channels * multiplier)?;
+                let input =
+                    into_feature_map(input, channels, (kernel_height, kernel_width), *padding)?;
+
+                let output = depthwise_convolve(
+                    &pad(&input.view(), *padding).view(),
+                    &kernels.view(),
+                    *stride,
+                );
+                add_biases(output, biases)
+            }
+            FloatLayer::MaxPool2DLayer { pool_size, stride } => {
+                pool(input, *pool_size, *stride, |window| {
+                    window.fold(f64::NEG_INFINITY, |maximum, &x| maximum.max(x))
+                })?
+            }
+            FloatLayer::AvgPool2DLayer { pool_size, stride } => {
+                pool(input, *pool_size, *stride, |window| window.mean().unwrap())?
+            }
+            FloatLayer::GlobalAvgPoolLayer {} => {
+                let channels = input.shape().first().copied().unwrap_or_default();
+                let input = into_feature_map(input, channels, (1, 1), 0)?;
+
+                let (_, height, width) = input.dim();
+                input
+                    .into_shape((channels, height * width))?
+                    .mean_axis(Axis(1))
+                    .unwrap()
+                    .into_dyn()
+            }
+            FloatLayer::BatchNormLayer {
+                mean,
+                variance,
+                gamma,
+                beta,
+                epsilon,
+            } => {
+                // The features are along the first axis
+                let shape = input.shape().to_vec();
+                let mut expected = vec![None; shape.len().max(1)];
+                expected[0] = Some(mean.len());
+                ShapeMismatchError::check(&shape, &expected)?;
+
+                let len = input.len();
+                let mut output =
+                    tensor::flatten(input).into_shape((mean.len(), len / mean.len()))?;
+                for (feature, mut values) in output.outer_iter_mut().enumerate() {
+                    let scale = gamma[feature] / (variance[feature] + *epsilon as f64).sqrt();
+                    values.mapv_inplace(|x| (x - mean[feature]) * scale + beta[feature]);
+                }
+                output.into_shape(IxDyn(&shape))?
+            }
+            FloatLayer::ReLULayer {} => input.mapv(|x| x.max(0.0)),
+            FloatLayer::LeakyReLULayer { slope } => {
+                input.mapv(|x| if x > 0.0 { x } else { *slope as f64 * x })
+            }
+            FloatLayer::ReLU6Layer { cap } => input.mapv(|x| x.clamp(0.0, *cap as f64)),
+            FloatLayer::Upsample2DLayer { scale } => {
+                let channels = input.shape().first().copied().unwrap_or_default();
+                let input = into_feature_map(input, channels, (1, 1), 0)?;
+
+                let (_, height, width) = input.dim();
+                Array3::from_shape_fn((channels, height * scale, width * scale), |(c, i, j)| {
+                    input[[c, i / scale, j / scale]]
+                })
+                .into_dyn()
+            }
+            FloatLayer::FlattenLayer {} => tensor::flatten(input).into_dyn(),
+        })
+    }
+
+    /// The floating-point equivalent of a quantized layer, if it is supported.
+    pub fn from_layer(layer: &Layer) -> Option<FloatLayer> {
+        // Tensors of Com are serialized as their bits
+        let unit = (1u64 << com::frac_bits()) as f64;
+        let mut value = serde_json::to_value(layer).ok()?;
+        map_json_tensors(&mut value, "", &mut |_, tensor| {
+            Ok(serde_json::to_value(tensor / unit)?)
+        })
+        .ok()?;
+        serde_json::from_value(value).ok()
+    }
+
+    /// The range of the tensor which multiplies the input, for the linear layers.
+    fn weights(&self) -> Option<Range> {
+        match self {
+            FloatLayer::DenseLayer { weights, .. } => Some(Range::of(weights)),
+            FloatLayer::Conv2DLayer { kernels, .. }
+            | FloatLayer::DepthwiseConv2DLayer { kernels, .. } => Some(Range::of(kernels)),
+            _ => None,
+        }
+    }
+
+    /// The layer which maps inputs scaled by 2^`input_exponent` to outputs scaled by
+    /// 2^`exponent`. Only the linear layers may change the scale.
+    fn scaled(&self, input_exponent: i32, exponent: i32) -> FloatLayer {
+        let scale = 2f64.powi(exponent);
+        let weight_scale = 2f64.powi(exponent - input_exponent);
+
+        match self.clone() {
+            FloatLayer::DenseLayer { weights, biases } => FloatLayer::DenseLayer {
+                weights: weights * weight_scale,
+                biases: biases * scale,
+            },
+            FloatLayer::Conv2DLayer {
+                kernels,
+                biases,
+                stride,
+                padding,
+            } => FloatLayer::Conv2DLayer {
+                kernels: kernels * weight_scale,
+                biases: biases * scale,
+                stride,
+                padding,
+            },
+            FloatLayer::DepthwiseConv2DLayer {
+                kernels,
+                biases,
+                stride,
+                padding,
+            } => FloatLayer::DepthwiseConv2DLayer {
+                kernels: kernels * weight_scale,
+                biases: biases * scale,
+                stride,
+                padding,
+            },
+            // γ · (s·x - s·μ) / √(σ² + ε) + s·β = s · (γ · (x - μ) / √(σ² + ε) + β)
+            FloatLayer::BatchNormLayer {
+                mean,
+                variance,
+                gamma,
+                beta,
+                epsilon,
+            } => FloatLayer::BatchNormLayer {
+                mean: mean * scale,
+                variance,
+                gamma,
+                beta: beta * scale,
+                epsilon,
+            },
+            FloatLayer::ReLU6Layer { cap } => FloatLayer::ReLU6Layer {
+                cap: cap * scale as f32,
+            },
+            layer => layer,
+        }
+    }
+}
+
+/// Adds one bias per channel of a (channels, height, width) feature map.
+fn add_biases(x: Array3<f64>, biases: &Array1<f64>) -> ArrayD<f64> {
+    (x + &biases.view().insert_axis(Axis(1)).insert_axis(Axis(2))).into_dyn()
+}
+
+/// Reduces every (pool size, pool size) window of a (channels, height, width) feature map.
+fn pool(
+    input: ArrayD<f64>,
+    pool_size: usize,
+    stride: Option<usize>,
+    reduce: impl Fn(ArrayView1<f64>) -> f64,
+) -> anyhow::Result<ArrayD<f64>> {
+    let (pool_size, stride) = pooling_window(pool_size, stride)?;
+    let channels = input.shape().first().copied().unwrap_or_default();
+    let input = into_feature_map(input, channels, (pool_size, pool_size), 0)?;
+
+    let (windows, (output_height, output_width)) =
+        gather_windows(&input.view(), (pool_size, pool_size), stride);
+    Ok(tensor::unflatten(
+        windows.map_axis(Axis(1), reduce),
+        &[channels, output_height, output_width],
+    ))
+}
+
+/// A model with real-valued tensors, in the format of `Model`, evaluated in floating-point.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "SerializedModel")]
+pub struct FloatModel {
+    pub nodes: Vec<Node<FloatLayer>>,
+    pub output: String,
+    pub header: ModelHeader,
+}
+
+impl TryFrom<SerializedModel> for FloatModel {
+    type Error = anyhow::Error;
+
+    fn try_from(mut model: SerializedModel) -> anyhow::Result<Self> {
+        // Bring nested arrays into ndarray's format
+        for value in [&mut model.layers, &mut model.nodes].into_iter().flatten() {
+            map_json_tensors(value, "", &mut |_, tensor| {
+                Ok(serde_json::to_value(tensor)?)
+            })?;
+        }
+
+        let header = std::mem::take(&mut model.header);
+        let (nodes, output) = model.graph::<Value>()?;
+        let nodes = nodes
+            .into_iter()
+            .map(|node| {
+                let operation = match node.operation {
+                    Operation::Layer(layer) => {
+                        Operation::Layer(serde_json::from_value(layer).with_context(|| {
+                            format!("Node '{}' cannot be calibrated", node.name)
+                        })?)
+                    }
+                    Operation::Add => Operation::Add,
+                    Operation::Concat { axis } => Operation::Concat { axis },
+                };
+                Ok(Node {
+                    name: node.name,
+                    inputs: node.inputs,
+                    operation,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(FloatModel {
+            nodes: sort_topologically(nodes)?,
+            output,
+            header,
+        })
+    }
+}
+
+impl FloatModel {
+    /// Evaluates every node.
+    ///
+    /// # Returns
+    ///
+    /// The output of every node, and of `INPUT`
+    pub fn activations(&self, input: ArrayD<f64>) -> anyhow::Result<HashMap<String, ArrayD<f64>>> {
+        let mut activations = HashMap::from([(INPUT.to_owned(), input)]);
+
+        for node in &self.nodes {
+            // The graph is sorted, so every input has been evaluated
+            let inputs: Vec<ArrayD<f64>> = node
+                .inputs
+                .iter()
+                .map(|input| activations[input].clone())
+                .collect();
+            let output = match &node.operation {
+                Operation::Layer(layer) => layer.infer(inputs.into_iter().next().unwrap()),
+                operation => operation.combine(inputs),
+            }
+            .with_context(|| format!("Failed to evaluate node '{}'", node.name))?;
+            activations.insert(node.name.clone(), output);
+        }
+
+        Ok(activations)
+    }
+
+    pub fn infer(&self, input: ArrayD<f64>) -> anyhow::Result<ArrayD<f64>> {
+        self.activations(input)?
+            .remove(&self.output)
+            .with_context(|| format!("The output node '{}' does not exist", self.output))
+    }
+
+    /// Records the range of every tensor over the samples, and chooses the scales.
+    ///
+    /// Every scale must leave `headroom_bits` of `Com`'s integer bits free. Within that, the
+    /// scales are chosen s.t. the least precise tensor keeps as many significant bits as possible,
+    /// and then every tensor as many as the others allow.
+    pub fn calibrate(
+        &self,
+        samples: &[ArrayD<f64>],
+        headroom_bits: u32,
+    ) -> anyhow::Result<Calibration> {
+        if samples.is_empty() {
+            bail!("Calibration needs at least one sample");
+        }
+
+        let mut ranges: HashMap<String, Range> = HashMap::new();
+        for sample in samples {
+            for (name, activation) in self.activations(sample.clone())? {
+                ranges.entry(name).or_default().include(&activation);
+            }
+        }
+        let weights: HashMap<&str, Range> = self
+            .nodes
+            .iter()
+            .filter_map(|node| match &node.operation {
+                Operation::Layer(layer) => layer.weights().map(|range| (node.name.as_str(), range)),
+                _ => None,
+            })
+            .collect();
+        for (name, range) in ranges
+            .iter()
+            .map(|(name, range)| (name.as_str(), range))
+            .chain(weights.iter().map(|(name, range)| (*name, range)))
+        {
+            if !range.magnitude().is_finite() {
+                bail!("Node '{}' has values which are not finite", name);
+            }
+        }
+
+        // Variable 0 is zero, 1 is the exponent of the input, and the rest those of the nodes. The
+        // exponent of a tensor is the difference of two variables: an activation's is relative to
+        // zero, and a weight's to the exponent of the input of its layer.
+        let mut variables = HashMap::from([(INPUT, 1)]);
+        let mut tensors = vec![(0, 1, ranges[INPUT].magnitude_bits())];
+        let mut equalities = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let variable = i + 2;
+            variables.insert(node.name.as_str(), variable);
+            let inputs: Vec<usize> = node
+                .inputs
+                .iter()
+                .map(|input| variables[input.as_str()])
+                .collect();
+
+            tensors.push((0, variable, ranges[&node.name].magnitude_bits()));
+            match weights.get(node.name.as_str()) {
+                Some(weights) => tensors.push((inputs[0], variable, weights.magnitude_bits())),
+                // Everything else commutes with scaling if its inputs and output share the scale
+                None => equalities.extend(inputs.into_iter().map(|input| (input, variable))),
+            }
+        }
+
+        let format = FixedPointFormat::of_com();
+        let (integer_bits, fractional_bits) =
+            (format.integer_bits as i32, format.fractional_bits as i32);
+        let headroom_bits = headroom_bits as i32;
+        // A tensor of magnitude 2^(b - 1) to 2^b, scaled by 2^e, takes b + e integer bits besides
+        // the sign and the headroom, and keeps b + e + F significant bits
+        let solve = |significant_bits: Option<i32>| {
+            let mut constraints = Constraints::new(self.nodes.len() + 2);
+            for &(from, to, bits) in &tensors {
+                // All-zero tensors are bounded like ones of a single unit in the last place
+                let upper_bound =
+                    integer_bits - 1 - headroom_bits - bits.unwrap_or(1 - fractional_bits);
+                constraints.at_most(from, to, upper_bound);
+                if let (Some(significant_bits), Some(bits)) = (significant_bits, bits) {
+                    constraints.at_most(to, from, bits + fractional_bits - significant_bits);
+                }
+            }
+            for &(a, b) in &equalities {
+                constraints.equal(a, b);
+            }
+            constraints.solve()
+        };
+        let exponents = (-64..=integer_bits + fractional_bits)
+            .rev()
+            .find_map(|significant_bits| solve(Some(significant_bits)))
+            .context("No scales fit the model into the range of Com")?;
+
+        let significant_bits = tensors
+            .iter()
+            .filter_map(|&(from, to, bits)| {
+                bits.map(|bits| bits + exponents[to] - exponents[from] + fractional_bits)
+            })
+            .min()
+            .unwrap_or_default();
+        let nodes = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let exponent = exponents[i + 2];
+                let weights = weights.get(node.name.as_str()).copied();
+                NodeCalibration {
+                    name: node.name.clone(),
+                    activations: ranges[&node.name],
+                    weights,
+                    exponent,
+                    weight_exponent: weights
+                        .map(|_| exponent - exponents[variables[node.inputs[0].as_str()]]),
+                }
+            })
+            .collect();
+
+        Ok(Calibration {
+            samples: samples.len(),
+            input: ranges[INPUT],
+            input_exponent: exponents[1],
+            nodes,
+            headroom_bits: headroom_bits as u32,
+            significant_bits,
+        })
+    }
+
+    /// Scales the tensors as calibrated, and quantizes them.
+    pub fn quantize(
+        &self,
+        calibration: &Calibration,
+        rounding: Rounding,
+    ) -> anyhow::Result<(Model, QuantizationReport)> {
+        let mut exponents = HashMap::from([(INPUT, calibration.input_exponent)]);
+        exponents.extend(
+            calibration
+                .nodes
+                .iter()
+                .map(|node| (node.name.as_str(), node.exponent)),
+        );
+
+        let mut report = QuantizationReport::default();
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let operation = match &node.operation {
+                Operation::Layer(layer) => {
+                    let layer = layer.scaled(
+                        exponents[node.inputs[0].as_str()],
+                        exponents[node.name.as_str()],
+                    );
+                    let mut value = serde_json::to_value(layer)?;
+                    quantize_json(&mut value, &node.name, rounding, &mut report)?;
+                    Operation::Layer(serde_json::from_value(value)?)
+                }
+                Operation::Add => Operation::Add,
+                Operation::Concat { axis } => Operation::Concat { axis: *axis },
+            };
+            nodes.push(Node {
+                name: node.name.clone(),
+                inputs: node.inputs.clone(),
+                operation,
+            });
+        }
+
+        let mut header = self.header.clone();
+        if calibration.input_exponent != 0 {
+            header.preprocessing.push(Preprocessing::Scale {
+                factor: 2f32.powi(calibration.input_exponent),
+            });
+        }
+        header.output_scale *= 2f32.powi(exponents[self.output.as_str()]);
+
+        let mut model = Model::new(nodes, self.output.clone())?;
+        if let Some(input_shape) = &header.input_shape {
+            model.validate(input_shape)?;
+        }
+        model.header = header;
+
+        Ok((model, report))
+    }
+}
+
+/// Reads samples from a JSON array of tensors, in either format which `quantize_json` accepts.
+pub fn samples_from_json(value: &Value) -> anyhow::Result<Vec<ArrayD<f64>>> {
+    let samples = value.as_array().context("The samples should be an array")?;
+    samples
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            json_tensor(sample).with_context(|| format!("Sample {} is not a tensor", i))?
+        })
+        .collect()
+}
+
+/// Difference constraints, i.e. x[to] - x[from] ≤ bound, on integer variables of which the first
+/// is zero.
+struct Constraints {
+    variables: usize,
+    edges: Vec<(usize, usize, i32)>,
+}
+
+impl Constraints {
+    fn new(variables: usize) -> Self {
+        Constraints {
+            variables,
+            edges: vec![],
+        }
+    }
+
+    fn at_most(&mut self, from: usize, to: usize, bound: i32) {
+        self.edges.push((from, to, bound));
+    }
+
+    fn equal(&mut self, a: usize, b: usize) {
+        self.at_most(a, b, 0);
+        self.at_most(b, a, 0);
+    }
+
+    /// Finds the solution in which every variable is as large as possible, as the shortest paths
+    /// from the first variable (Bellman-Ford).
+    ///
+    /// # Returns
+    ///
+    /// The solution, or `None` if the constraints contradict each other
+    fn solve(&self) -> Option<Vec<i32>> {
+        let mut x = vec![i32::MAX; self.variables];
+        x[0] = 0;
+
+        // Without contradictions, every shortest path is found within |variables| - 1 rounds
+        for _ in 0..self.variables {
+            let mut relaxed = false;
+            for &(from, to, bound) in &self.edges {
+                if x[from] != i32::MAX && x[from] + bound < x[to] {
+                    x[to] = x[from] + bound;
+                    relaxed = true;
+                }
+            }
+            if !relaxed {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+}
+
+/// The range of the values of a tensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for Range {
+    fn default() -> Self {
+        Range {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Range {
+    fn of<'a>(values: impl IntoIterator<Item = &'a f64>) -> Self {
+        let mut range = Range::default();
+        range.include(values);
+        range
+    }
+
+    fn include<'a>(&mut self, values: impl IntoIterator<Item = &'a f64>) {
+        for &x in values {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+    }
+
+    /// The largest absolute value, or zero if there are no values.
+    pub fn magnitude(&self) -> f64 {
+        if self.min > self.max {
+            0.0
+        } else {
+            self.min.abs().max(self.max.abs())
+        }
+    }
+
+    /// The b s.t. the magnitude is in [2^(b - 1), 2^b), or `None` if it is zero.
+    fn magnitude_bits(&self) -> Option<i32> {
+        let magnitude = self.magnitude();
+        (magnitude > 0.0).then(|| magnitude.log2().floor() as i32 + 1)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.min, self.max)
+    }
+}
+
+/// The ranges of a node over the samples, and the scales chosen for it.
+#[derive(Debug, Clone)]
+pub struct NodeCalibration {
+    pub name: String,
+    pub activations: Range,
+    /// The range of the weights, for the linear layers
+    pub weights: Option<Range>,
+    /// The output is scaled by 2^exponent
+    pub exponent: i32,
+    /// The weights are scaled by 2^weight_exponent
+    pub weight_exponent: Option<i32>,
+}
+
+/// The ranges of the tensors of a model over some samples, and the scales chosen for them.
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    pub samples: usize,
+    pub input: Range,
+    /// The input is scaled by 2^input_exponent
+    pub input_exponent: i32,
+    pub nodes: Vec<NodeCalibration>,
+    pub headroom_bits: u32,
+    /// The fewest significant bits which any tensor keeps, scaled
+    pub significant_bits: i32,
+}
+
+impl Calibration {
+    /// The fixed-point format with the most fractional bits in which every tensor fits unscaled,
+    /// with the same headroom.
+    pub fn recommended_format(&self) -> FixedPointFormat {
+        let format = FixedPointFormat::of_com();
+        let width = (format.integer_bits + format.fractional_bits) as i32;
+
+        let bits = self
+            .nodes
+            .iter()
+            .flat_map(|node| [Some(node.activations), node.weights])
+            .flatten()
+            .chain([self.input])
+            .filter_map(|range| range.magnitude_bits())
+            .max()
+            .unwrap_or_default();
+        let integer_bits = (bits + 1 + self.headroom_bits as i32).clamp(1, width);
+
+        FixedPointFormat {
+            integer_bits: integer_bits as u32,
+            fractional_bits: (width - integer_bits) as u32,
+        }
+    }
+}
+
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {}, scaled by 2^{}",
+            INPUT, self.input, self.input_exponent
+        )?;
+        for node in &self.nodes {
+            write!(
+                f,
+                "{}: {}, scaled by 2^{}",
+                node.name, node.activations, node.exponent
+            )?;
+            if let (Some(weights), Some(weight_exponent)) = (node.weights, node.weight_exponent) {
+                write!(f, "; weights {}, scaled by 2^{}", weights, weight_exponent)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "Over {} samples, every tensor keeps at least {} significant bits",
+            self.samples, self.significant_bits
+        )?;
+        write!(
+            f,
+            "Unscaled, the tensors would fit {} (this build computes in {})",
+            self.recommended_format(),
+            FixedPointFormat::of_com()
+        )
+    }
+}
+
+#[test]
+fn test_calibrated_model_matches_float_model() {
+    use crate::Com;
+
+    let model: FloatModel = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "DenseLayer", "weights": [[0.5, -0.25], [0.125, 1.0]], "biases": [0.1, -0.2]},
+            {"type": "ReLULayer"},
+            {"type": "DenseLayer", "weights": [[1.5], [-0.75]], "biases": [0.05]}
+        ]}"#,
+    )
+    .unwrap();
+    let samples =
+        samples_from_json(&serde_json::json!([[1.0, 0.5], [0.2, -0.3], [-1.0, 1.0]])).unwrap();
+
+    let calibration = model.calibrate(&samples, DEFAULT_HEADROOM_BITS).unwrap();
+    assert_eq!(
+        calibration.input,
+        Range {
+            min: -1.0,
+            max: 1.0
+        }
+    );
+    // Unscaled, weights such as 0.125 would not survive Com's two fractional bits
+    assert!(calibration.significant_bits >= 8);
+    // The ReLU shares the scale of its input
+    assert_eq!(calibration.nodes[0].exponent, calibration.nodes[1].exponent);
+
+    let (quantized, report) = model.quantize(&calibration, Rounding::Nearest).unwrap();
+    assert_eq!(report.saturated(), 0);
+    let factor = 2f32.powi(calibration.input_exponent);
+    assert_eq!(
+        quantized.header.preprocessing,
+        [Preprocessing::Scale { factor }]
+    );
+
+    for sample in samples {
+        let expected = model.infer(sample.clone()).unwrap();
+        let input = (sample * factor as f64).mapv(Com::from_num);
+        let output = quantized.header.postprocess(
+            quantized
+                .infer_locally(input)
+                .unwrap()
+                .mapv(Com::to_num::<f32>),
+        );
+        assert!((output[0] as f64 - expected[0]).abs() < 0.01);
+    }
+}
@@ -1,37 +1,57 @@
 use anyhow::{bail, Context as _};
-use ndarray::Array1;
+use ndarray::ArrayD;
 use ring::rand::SecureRandom;
 
 use crate::{
     message::{Message, IO},
     model::ModelShare,
+    model_header::ModelHeader,
+    offline::OfflinePlan,
     reconstruct::Reconstruct as _,
     split::Split,
     unexpected_message_error::UnexpectedMessageError,
     Com,
 };
 
+/// Receives the header of the model, which the server sends before anything else.
+///
+/// The header describes how to preprocess the input, and names the elements of the output.
+///
+/// # Errors
+/// Returns an error if the first message is not a header, or if the model is in a format this
+/// build cannot evaluate.
+pub async fn receive_header((_, receiver): IO<'_>) -> anyhow::Result<ModelHeader> {
+    if let Some(Message::ModelHeader(header)) = receiver.recv().await {
+        header.check()?;
+        Ok(header)
+    } else {
+        bail!(UnexpectedMessageError {});
+    }
+}
+
 /// Performs client-side inference of a privacy-preserving neural network.
 ///
-/// This function takes an input array of floats, representing the input to the neural network,
+/// The header should have been received with `receive_header` beforehand.
+///
+/// This function takes an input tensor of floats, representing the input to the neural network,
 /// and returns the inferred output. The inference is performed securely and privately using
 /// secure multi-party computation techniques, considering a semi-honest adversary.
 ///
 /// # Parameters
 /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
-/// - `input`: The input vector.
+/// - `input`: The input tensor, of any shape the model accepts.
 /// - `rng`: A secure random number generator for secure computation.
 ///
 /// # Returns
-/// Array of floats representing the inferred output of the neural network.
+/// Tensor of floats representing the inferred output of the neural network.
 ///
 /// # Errors
 /// Returns an error if communication with the server fails or unexpected messages are received.
 pub async fn infer(
     (sender, receiver): IO<'_>,
-    input: Array1<f32>,
+    input: ArrayD<f32>,
     rng: &dyn SecureRandom,
-) -> anyhow::Result<Array1<f32>> {
+) -> anyhow::Result<ArrayD<f32>> {
     // Convert the input from float to Com
     let input_com = input.mapv(Com::from_num);
 
@@ -54,16 +74,16 @@ pub async fn infer(
 /// - `input_shares`: The already-splitted input shares.
 ///
 /// # Returns
-/// The raw inferred output vector.
+/// The raw inferred output tensor.
 ///
 /// # Errors
 /// Returns an error if communication with the server fails or unexpected messages are encountered.
 pub async fn infer_raw(
     (sender, receiver): IO<'_>,
-    input_shares: (Array1<Com>, Array1<Com>),
+    input_shares: (ArrayD<Com>, ArrayD<Com>),
     rng: &dyn SecureRandom,
-) -> anyhow::Result<Array1<Com>> {
-    // Send the server an input share
+) -> anyhow::Result<ArrayD<Com>> {
+    // Send the server an input share, whose shape tells it how much correlated randomness to deal
     sender.send(Message::InputShare(input_shares.1)).await?;
 
     // Wait for the model share
@@ -82,9 +102,15 @@ pub async fn infer_raw(
         bail!(UnexpectedMessageError {});
     }
 
+    // Generate the correlated randomness, which does not depend on the input
+    let mut material = model_share
+        .prepare::<false>(OfflinePlan::default(), (sender, receiver), rng)
+        .await
+        .context("Failed to run the offline phase")?;
+
     // Infer the model
     let our_output_share = model_share
-        .infer::<false>(input_shares.0, (sender, receiver), rng)
+        .infer::<false>(input_shares.0, &mut material, (sender, receiver))
         .await
         .context("Failed to iterate over the model's layers")?;
 
@@ -97,7 +123,7 @@ pub async fn infer_raw(
     }
 
     // Verify the message is indeed an output share
-    let their_output_share: Array1<Com>;
+    let their_output_share: ArrayD<Com>;
     if let Message::OutputShare(contents) = output_share_message {
         their_output_share = contents;
     } else {
@@ -105,7 +131,7 @@ pub async fn infer_raw(
     }
 
     // Reconstruct the output
-    Ok(Array1::<Com>::reconstruct((
+    Ok(ArrayD::<Com>::reconstruct((
         &our_output_share,
         &their_output_share,
     )))
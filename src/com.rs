@@ -8,6 +8,11 @@ use ring::rand::{self, SecureRandom};
 // pub type Com = Wrapping<FixedI16<4>>;
 pub type Com = Wrapping<FixedI32<2>>;
 
+/// The number of fractional bits of `Com`.
+pub(crate) fn frac_bits() -> u32 {
+    Com::from_num(1).0.to_bits().trailing_zeros()
+}
+
 pub(crate) fn sample<Sh: ShapeBuilder>(shape: Sh, rng: &dyn SecureRandom) -> Array<Com, Sh::Dim> {
     // TODO implement RandomlyConstructable to avoid copying
     Array::from_shape_simple_fn(shape, || {
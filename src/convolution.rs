@@ -0,0 +1,248 @@
+use std::ops::{AddAssign, Mul};
+
+use anyhow::bail;
+use ndarray::{s, Array2, Array3, ArrayD, ArrayView3, ArrayView4, Ix3};
+use num_traits::Zero;
+
+use crate::{shape_mismatch_error::ShapeMismatchError, Com};
+
+/// Converts a tensor into a (channels, height, width) feature map, checking that it is large
+/// enough for a (kernel height, kernel width) window after padding.
+pub(crate) fn into_feature_map<A>(
+    x: ArrayD<A>,
+    channels: usize,
+    kernel_size: (usize, usize),
+    padding: usize,
+) -> anyhow::Result<Array3<A>> {
+    feature_map_size(x.shape(), Some(channels), kernel_size, padding)?;
+    Ok(x.into_dimensionality::<Ix3>().unwrap())
+}
+
+/// Performs the checks of `into_feature_map` on a shape alone, where `None` means any number of
+/// channels.
+///
+/// # Returns
+///
+/// The (channels, height, width) of the feature map
+pub(crate) fn feature_map_size(
+    shape: &[usize],
+    channels: Option<usize>,
+    (kernel_height, kernel_width): (usize, usize),
+    padding: usize,
+) -> anyhow::Result<(usize, usize, usize)> {
+    ShapeMismatchError::check(shape, &[channels, None, None])?;
+
+    let (channels, height, width) = (shape[0], shape[1], shape[2]);
+    if height + 2 * padding < kernel_height || width + 2 * padding < kernel_width {
+        bail!(
+            "The feature map ({}x{}) is smaller than the window ({}x{})",
+            height,
+            width,
+            kernel_height,
+            kernel_width
+        );
+    }
+
+    Ok((channels, height, width))
+}
+
+/// Checks that a stride is positive, as a zero stride would never move the window.
+pub(crate) fn check_stride(stride: usize) -> anyhow::Result<()> {
+    if stride == 0 {
+        bail!("The stride should be positive");
+    }
+    Ok(())
+}
+
+/// Pads the spatial dimensions of a (channels, height, width) tensor with zeros.
+///
+/// Padding is linear, so each party may pad its own share.
+pub(crate) fn pad<A: Clone + Zero>(input: &ArrayView3<A>, padding: usize) -> Array3<A> {
+    let (channels, height, width) = input.dim();
+    let mut padded = Array3::zeros((channels, height + 2 * padding, width + 2 * padding));
+    padded
+        .slice_mut(s![.., padding..padding + height, padding..padding + width])
+        .assign(input);
+    padded
+}
+
+/// Calculates the (height, width) of the output of a convolution over an already-padded input.
+pub(crate) fn output_size(
+    (height, width): (usize, usize),
+    (kernel_height, kernel_width): (usize, usize),
+    stride: usize,
+) -> (usize, usize) {
+    (
+        (height - kernel_height) / stride + 1,
+        (width - kernel_width) / stride + 1,
+    )
+}
+
+/// The pool size and the stride of a pooling, where the stride defaults to the pool size.
+///
+/// # Errors
+///
+/// Fails if either is 0, as an empty window or a zero stride would panic.
+pub(crate) fn pooling_window(
+    pool_size: usize,
+    stride: Option<usize>,
+) -> anyhow::Result<(usize, usize)> {
+    if pool_size == 0 {
+        bail!("The pool size should be positive");
+    }
+    let stride = stride.unwrap_or(pool_size);
+    check_stride(stride)?;
+    Ok((pool_size, stride))
+}
+
+/// The shape of the output of pooling a (channels, height, width) feature map with (pool size,
+/// pool size) windows.
+pub(crate) fn pooling_output_shape(
+    input_shape: &[usize],
+    pool_size: usize,
+    stride: Option<usize>,
+) -> anyhow::Result<Vec<usize>> {
+    let (pool_size, stride) = pooling_window(pool_size, stride)?;
+    let (channels, height, width) = feature_map_size(input_shape, None, (pool_size, pool_size), 0)?;
+
+    let (output_height, output_width) =
+        output_size((height, width), (pool_size, pool_size), stride);
+    Ok(vec![channels, output_height, output_width])
+}
+
+/// Cross-correlates an already-padded input of shape (input channels, height, width) with kernels
+/// of shape (output channels, input channels, kernel height, kernel width), like most frameworks.
+pub(crate) fn convolve<A: Clone + Zero + Mul<Output = A>>(
+    input: &ArrayView3<A>,
+    kernels: &ArrayView4<A>,
+    stride: usize,
+) -> Array3<A> {
+    let (output_channels, _, kernel_height, kernel_width) = kernels.dim();
+    let (_, height, width) = input.dim();
+    let (output_height, output_width) =
+        output_size((height, width), (kernel_height, kernel_width), stride);
+
+    Array3::from_shape_fn(
+        (output_channels, output_height, output_width),
+        |(o, i, j)| {
+            let window = input.slice(s![
+                ..,
+                i * stride..i * stride + kernel_height,
+                j * stride..j * stride + kernel_width
+            ]);
+            (&window * &kernels.slice(s![o, .., .., ..])).sum()
+        },
+    )
+}
+
+/// Cross-correlates every channel of an already-padded input of shape (channels, height, width)
+/// with its own kernels, of shape (channels, depth multiplier, kernel height, kernel width).
+///
+/// Output channel c · (depth multiplier) + m is input channel c convolved with kernel (c, m), like
+/// Keras.
+pub(crate) fn depthwise_convolve<A: Clone + Zero + Mul<Output = A>>(
+    input: &ArrayView3<A>,
+    kernels: &ArrayView4<A>,
+    stride: usize,
+) -> Array3<A> {
+    let (channels, multiplier, kernel_height, kernel_width) = kernels.dim();
+    let (_, height, width) = input.dim();
+    let (output_height, output_width) =
+        output_size((height, width), (kernel_height, kernel_width), stride);
+
+    Array3::from_shape_fn(
+        (channels * multiplier, output_height, output_width),
+        |(o, i, j)| {
+            let (c, m) = (o / multiplier, o % multiplier);
+            let window = input.slice(s![
+                c,
+                i * stride..i * stride + kernel_height,
+                j * stride..j * stride + kernel_width
+            ]);
+            (&window * &kernels.slice(s![c, m, .., ..])).sum()
+        },
+    )
+}
+
+/// Calculates the (height, width) of the output of a transposed convolution, before cropping.
+/// An empty axis stays empty.
+pub(crate) fn transposed_output_size(
+    (height, width): (usize, usize),
+    (kernel_height, kernel_width): (usize, usize),
+    stride: usize,
+) -> (usize, usize) {
+    let axis =
+        |size: usize, kernel_size| size.checked_sub(1).map_or(0, |n| n * stride + kernel_size);
+    (axis(height, kernel_height), axis(width, kernel_width))
+}
+
+/// Computes the transpose (the gradient) of a convolution of an input of shape (input channels,
+/// height, width) with kernels of shape (input channels, output channels, kernel height, kernel
+/// width), like PyTorch. Every input element scatters its kernels, scaled, into the output.
+pub(crate) fn transposed_convolve<A: Copy + Zero + Mul<Output = A> + AddAssign>(
+    input: &ArrayView3<A>,
+    kernels: &ArrayView4<A>,
+    stride: usize,
+) -> Array3<A> {
+    let (_, output_channels, kernel_height, kernel_width) = kernels.dim();
+    let (_, height, width) = input.dim();
+    let (output_height, output_width) =
+        transposed_output_size((height, width), (kernel_height, kernel_width), stride);
+
+    let mut output = Array3::zeros((output_channels, output_height, output_width));
+    for ((c, i, j), &x) in input.indexed_iter() {
+        let mut window = output.slice_mut(s![
+            ..,
+            i * stride..i * stride + kernel_height,
+            j * stride..j * stride + kernel_width
+        ]);
+        window += &kernels.slice(s![c, .., .., ..]).mapv(|k| k * x);
+    }
+
+    output
+}
+
+/// Reverts `pad`, i.e. removes `padding` rows and columns from every side.
+pub(crate) fn crop(input: &ArrayView3<Com>, padding: usize) -> Array3<Com> {
+    let (_, height, width) = input.dim();
+    input
+        .slice(s![.., padding..height - padding, padding..width - padding])
+        .to_owned()
+}
+
+/// Gathers every (window height, window width) window of a (channels, height, width) feature map
+/// as a row, ordered by channel, then by the position of the window.
+///
+/// # Returns
+///
+/// The windows, and the (height, width) of the grid of windows
+pub(crate) fn gather_windows<A: Copy>(
+    input: &ArrayView3<A>,
+    (window_height, window_width): (usize, usize),
+    stride: usize,
+) -> (Array2<A>, (usize, usize)) {
+    let (channels, height, width) = input.dim();
+    let (output_height, output_width) =
+        output_size((height, width), (window_height, window_width), stride);
+
+    let windows = Array2::from_shape_fn(
+        (
+            channels * output_height * output_width,
+            window_height * window_width,
+        ),
+        |(row, k)| {
+            let (c, position) = (
+                row / (output_height * output_width),
+                row % (output_height * output_width),
+            );
+            let (i, j) = (position / output_width, position % output_width);
+            input[[
+                c,
+                i * stride + k / window_width,
+                j * stride + k % window_width,
+            ]]
+        },
+    );
+
+    (windows, (output_height, output_width))
+}
@@ -0,0 +1,145 @@
+use anyhow::Context as _;
+use ndarray::{concatenate, s, Array1, Axis};
+
+use crate::{bitxa, layer::relu::drelu::drelu, message::IO, offline::OfflineMaterial, Com};
+
+/// Tests whether each shared value equals the respective public value.
+///
+/// This is a zero test built on top of the signed comparison: x = k iff both x - k ≥ 0 and
+/// k - x ≥ 0. Since at least one of them always holds, x = k iff both DReLUs agree.
+///
+/// That is, unless x - k wraps around to `Com::MIN`, whose negation is itself, so neither holds
+/// and both DReLUs agree that x = k, falsely. Therefore, x - k must lie within
+/// (`Com::MIN`, `Com::MAX`], e.g. both x and k within half the range of `Com`,
+/// [`Com::MIN` / 2, `Com::MAX` / 2], which indices and classes always are.
+///
+/// The DReLU keys of 2n elements are taken from `material`.
+///
+/// # Returns
+///
+/// A boolean share of x = k
+pub(crate) async fn equal<const PARTY: bool>(
+    x_share: &Array1<Com>,
+    k: &Array1<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<bool>> {
+    let n = x_share.len();
+
+    // Only one party should subtract the (public) k
+    let difference_share = if PARTY { x_share - k } else { x_share.clone() };
+    let batch_share = concatenate![Axis(0), difference_share, -&difference_share];
+
+    let drelu_output_share = drelu::<PARTY>(&batch_share, material, (sender, receiver))
+        .await
+        .context("Failed to evaluate DReLU")?;
+
+    Ok(&drelu_output_share.slice(s![..n]) ^ &drelu_output_share.slice(s![n..]) ^ PARTY)
+}
+
+/// Encodes shared indices as one-hot vectors of arithmetic shares.
+///
+/// # Arguments
+///
+/// - `index_share`: Arithmetic shares of the indices
+/// - `classes`: The number of possible indices
+/// - `material`: The material of the offline phase, of which DReLU and BitXA keys are taken
+/// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+///
+/// # Returns
+///
+/// A share of the one-hot vectors of all the indices, concatenated
+pub(crate) async fn one_hot<const PARTY: bool>(
+    index_share: &Array1<Com>,
+    classes: usize,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<Com>> {
+    // Compare every index against every class at once
+    let repeated_index_share = Array1::from_iter(
+        index_share
+            .iter()
+            .flat_map(|x| std::iter::repeat(*x).take(classes)),
+    );
+    let k = Array1::from_iter(
+        (0..index_share.len()).flat_map(|_| (0..classes).map(|k| Com::from_num(k))),
+    );
+    let equality_share = equal::<PARTY>(&repeated_index_share, &k, material, (sender, receiver))
+        .await
+        .context("Failed to compare the indices")?;
+
+    // Convert the boolean shares into arithmetic ones by multiplying them by 1
+    let ones_share = Array1::from_elem(
+        equality_share.len(),
+        if PARTY { Com::from_num(1) } else { Com::ZERO },
+    );
+    let key = material.take_bitxa_key(ones_share.len())?;
+    bitxa::<PARTY>(&ones_share, &equality_share, key, (sender, receiver))
+        .await
+        .context("Failed to evaluate BitXA")
+}
+
+#[tokio::test]
+async fn test_equality_and_one_hot() {
+    use ndarray::array;
+
+    use crate::{offline::OfflinePlan, reconstruct::Reconstruct, split::Split, testing};
+
+    let rng = ring::rand::SystemRandom::new();
+    // Equal, and unequal by positive and negative differences, down to one unit in the last place
+    // and up to the extremes of the valid range, whose differences are Com::MIN + 1 and Com::MAX
+    let half_min = Com::from_bits(i32::MIN / 2);
+    let half_max = Com::from_bits(i32::MAX / 2);
+    let x = array![3.0, 3.0, -2.0, -2.0, 0.0, 5.25, -7.0, 0.0]
+        .mapv(Com::from_num)
+        .into_iter()
+        .chain([half_min, half_max])
+        .collect::<Array1<_>>();
+    let k = array![3.0, 4.0, -2.0, 1.0, 0.0, 5.0, -6.75, -0.25]
+        .mapv(Com::from_num)
+        .into_iter()
+        .chain([half_max, half_min])
+        .collect::<Array1<_>>();
+    assert_eq!(half_min - half_max, Com::MIN + Com::from_bits(1));
+    assert_eq!(half_max - half_min, Com::MAX);
+    let x_shares = x.split(&rng);
+    let mut materials = OfflineMaterial::deal(
+        &OfflinePlan {
+            drelu_elements: 2 * x.len(),
+            ..Default::default()
+        },
+        &rng,
+    );
+    let (mut server, mut client, _) = testing::connect();
+    let (server_output_share, client_output_share) = tokio::join!(
+        equal::<true>(&x_shares.0, &k, &mut materials.0, server.io()),
+        equal::<false>(&x_shares.1, &k, &mut materials.1, client.io()),
+    );
+    assert_eq!(
+        server_output_share.unwrap() ^ client_output_share.unwrap(),
+        array![true, false, true, false, true, false, false, false, false, false]
+    );
+
+    let indices = array![0.0, 2.0, 3.0].mapv(Com::from_num);
+    let index_shares = indices.split(&rng);
+    let mut materials = OfflineMaterial::deal(
+        &OfflinePlan {
+            bitxa_elements: 3 * 4,
+            drelu_elements: 2 * 3 * 4,
+            ..Default::default()
+        },
+        &rng,
+    );
+    let (mut server, mut client, _) = testing::connect();
+    let (server_output_share, client_output_share) = tokio::join!(
+        one_hot::<true>(&index_shares.0, 4, &mut materials.0, server.io()),
+        one_hot::<false>(&index_shares.1, 4, &mut materials.1, client.io()),
+    );
+    let one_hot =
+        Array1::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+    for (vector, index) in one_hot.exact_chunks(4).into_iter().zip([0, 2, 3]) {
+        assert_eq!(vector.iter().filter(|&&x| x == Com::from_num(1)).count(), 1);
+        assert_eq!(vector.iter().filter(|&&x| x == Com::ZERO).count(), 3);
+        assert_eq!(vector[index], Com::from_num(1));
+    }
+}
@@ -0,0 +1,300 @@
+//! Summarises a model and estimates the cost of evaluating it securely.
+//!
+//! Costs are measured rather than modelled: every layer's protocols are run in memory on zeros,
+//! and the messages they exchange are serialized as on the wire. Messages which do not depend on
+//! the input, e.g. the keys of DReLU, count towards the offline phase.
+//!
+//! NOTE the keys of DReLU are dealt by the server rather than generated by a protocol, see
+//! `offline`, so their cost is a single message.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use ndarray::{ArrayD, IxDyn};
+use ring::rand::SecureRandom;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    layer::{Layer, LayerShare},
+    message::Message,
+    model::{Model, ModelShare, Node, Operation, INPUT},
+    offline::{self, OfflinePlan},
+    split::Split,
+};
+
+/// The messages exchanged during one phase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub bytes: usize,
+    pub messages: usize,
+    /// The most messages sent by either party, as the parties mostly send theirs simultaneously
+    pub rounds: usize,
+}
+
+impl Traffic {
+    fn add(&mut self, other: &Traffic) {
+        self.bytes += other.bytes;
+        self.messages += other.messages;
+        self.rounds += other.rounds;
+    }
+}
+
+/// A summary of a node of a model.
+#[derive(Debug, Clone)]
+pub struct NodeSummary {
+    pub name: String,
+    /// The type of the layer, or the operation
+    pub kind: String,
+    pub output_shape: Vec<usize>,
+    pub parameters: usize,
+    pub online: Traffic,
+    pub offline: Traffic,
+}
+
+/// A summary of a model.
+#[derive(Debug, Clone)]
+pub struct ModelSummary {
+    pub nodes: Vec<NodeSummary>,
+    /// The client's share of the model, which is sent before the first inference
+    pub model_share_bytes: usize,
+}
+
+impl ModelSummary {
+    pub fn online(&self) -> Traffic {
+        let mut total = Traffic::default();
+        for node in &self.nodes {
+            total.add(&node.online);
+        }
+        total
+    }
+
+    pub fn offline(&self) -> Traffic {
+        let mut total = Traffic::default();
+        for node in &self.nodes {
+            total.add(&node.offline);
+        }
+        total
+    }
+}
+
+/// Whether a message is independent of the input, i.e. could be sent in advance.
+fn is_offline(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::ModelHeader(_)
+            | Message::ModelShare(_)
+            | Message::OfflinePlan(_)
+            | Message::PaillierPublicKey(_)
+            | Message::EncryptedOperand(_)
+            | Message::EncryptedProduct(_)
+            | Message::DReLUKey(_)
+    )
+}
+
+/// Summarises every node of a model, given the shape of its input.
+pub async fn inspect(
+    model: &Model,
+    input_shape: &[usize],
+    private_weights: bool,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<ModelSummary> {
+    let shapes = model.shapes(input_shape)?;
+
+    let mut nodes = Vec::with_capacity(model.nodes.len());
+    for node in &model.nodes {
+        let summary = match &node.operation {
+            Operation::Layer(layer) => {
+                let layer_shares = if private_weights {
+                    layer
+                        .split_private_weights(rng)
+                        .with_context(|| format!("Failed to split node '{}'", node.name))?
+                } else {
+                    layer.split(rng)
+                };
+                let (online, offline) = measure(layer_shares, &shapes[&node.inputs[0]], rng)
+                    .await
+                    .with_context(|| format!("Failed to evaluate node '{}'", node.name))?;
+                let (kind, parameters) = describe(layer)?;
+
+                NodeSummary {
+                    name: node.name.clone(),
+                    kind,
+                    output_shape: shapes[&node.name].clone(),
+                    parameters,
+                    online,
+                    offline,
+                }
+            }
+            // Combining shares is local
+            operation => NodeSummary {
+                name: node.name.clone(),
+                kind: match operation {
+                    Operation::Add => "Add".to_owned(),
+                    _ => "Concat".to_owned(),
+                },
+                output_shape: shapes[&node.name].clone(),
+                parameters: 0,
+                online: Traffic::default(),
+                offline: Traffic::default(),
+            },
+        };
+        nodes.push(summary);
+    }
+
+    let model_shares = if private_weights {
+        model.split_private_weights(rng)?
+    } else {
+        model.split(rng)
+    };
+    let model_share_bytes = Message::ModelShare(model_shares.1).to_bytes()?.len();
+
+    Ok(ModelSummary {
+        nodes,
+        model_share_bytes,
+    })
+}
+
+/// The type of a layer and its number of parameters, i.e. the elements of all its tensors.
+fn describe(layer: &Layer) -> anyhow::Result<(String, usize)> {
+    fn count_parameters(value: &Value) -> usize {
+        match value {
+            // ndarray's format
+            Value::Object(object) if object.contains_key("dim") => object
+                .get("data")
+                .and_then(Value::as_array)
+                .map_or(0, Vec::len),
+            Value::Object(object) => object.values().map(count_parameters).sum(),
+            Value::Array(elements) => elements.iter().map(count_parameters).sum(),
+            _ => 0,
+        }
+    }
+
+    let value = serde_json::to_value(layer)?;
+    let kind = value["type"].as_str().unwrap_or_default().to_owned();
+    Ok((kind, count_parameters(&value)))
+}
+
+/// Runs a layer's offline and online protocols in memory on zeros.
+///
+/// # Returns
+///
+/// The online and the offline traffic
+async fn measure(
+    (server_layer_share, client_layer_share): (LayerShare, LayerShare),
+    input_shape: &[usize],
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<(Traffic, Traffic)> {
+    let log = Arc::new(Mutex::new(vec![]));
+    let (server_sender, server_outbox) = mpsc::channel(1024);
+    let (client_sender, client_outbox) = mpsc::channel(1024);
+    let (to_client, mut client_receiver) = mpsc::channel(1024);
+    let (to_server, mut server_receiver) = mpsc::channel(1024);
+    tokio::spawn(relay(true, server_outbox, to_client, log.clone()));
+    tokio::spawn(relay(false, client_outbox, to_server, log.clone()));
+
+    let model_share = |layer_share| ModelShare {
+        nodes: vec![Node {
+            name: "layer".to_owned(),
+            inputs: vec![INPUT.to_owned()],
+            operation: Operation::Layer(layer_share),
+        }],
+        output: "layer".to_owned(),
+    };
+    let (server_model_share, client_model_share) = (
+        model_share(server_layer_share),
+        model_share(client_layer_share),
+    );
+
+    let plan = offline::plan((&server_model_share, &client_model_share), input_shape).await?;
+    let (server_material, client_material) = tokio::join!(
+        server_model_share.prepare::<true>(plan, (&server_sender, &mut server_receiver), rng),
+        client_model_share.prepare::<false>(
+            OfflinePlan::default(),
+            (&client_sender, &mut client_receiver),
+            rng
+        ),
+    );
+    let (mut server_material, mut client_material) = (server_material?, client_material?);
+
+    let input_share = ArrayD::zeros(IxDyn(input_shape));
+    let (server_output_share, client_output_share) = tokio::join!(
+        server_model_share.infer::<true>(
+            input_share.clone(),
+            &mut server_material,
+            (&server_sender, &mut server_receiver)
+        ),
+        client_model_share.infer::<false>(
+            input_share,
+            &mut client_material,
+            (&client_sender, &mut client_receiver)
+        ),
+    );
+    server_output_share?;
+    client_output_share?;
+
+    let log = log.lock().unwrap();
+    let traffic = |offline: bool| {
+        let messages = log.iter().filter(|entry| entry.2 == offline);
+        let sent_by = |party: bool| messages.clone().filter(|entry| entry.0 == party).count();
+        Traffic {
+            bytes: messages.clone().map(|entry| entry.1).sum(),
+            messages: messages.clone().count(),
+            rounds: sent_by(true).max(sent_by(false)),
+        }
+    };
+    Ok((traffic(false), traffic(true)))
+}
+
+/// Forwards messages from one party to the other, logging their sender, their size and whether
+/// they are offline.
+async fn relay(
+    party: bool,
+    mut outbox: mpsc::Receiver<Message>,
+    inbox: mpsc::Sender<Message>,
+    log: Arc<Mutex<Vec<(bool, usize, bool)>>>,
+) {
+    while let Some(message) = outbox.recv().await {
+        let size = message.to_bytes().map_or(0, |bytes| bytes.len());
+        log.lock()
+            .unwrap()
+            .push((party, size, is_offline(&message)));
+
+        if inbox.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_inspect_counts_relu_rounds() {
+    let model: Model = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "DenseLayer",
+             "weights": {"v": 1, "dim": [2, 3], "data": [0, 0, 0, 0, 0, 0]},
+             "biases": {"v": 1, "dim": [3], "data": [0, 0, 0]}},
+            {"type": "ReLULayer"},
+            {"type": "FlattenLayer"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let rng = ring::rand::SystemRandom::new();
+    let summary = inspect(&model, &[2], false, &rng).await.unwrap();
+
+    let dense = &summary.nodes[0];
+    assert_eq!((dense.kind.as_str(), dense.parameters), ("DenseLayer", 9));
+    assert_eq!(dense.output_shape, [3]);
+    assert!(dense.online.bytes > 0);
+
+    // DReLU and BitXA take one round each online. Offline, besides the plan, the server deals the
+    // DReLU keys, and the BitXA keys take the Paillier keys and two products of shares each way
+    let relu = &summary.nodes[1];
+    assert_eq!(relu.online.rounds, 2);
+    assert_eq!(relu.offline.messages, 1 + 1 + 2 + 2 * 4);
+
+    // Only the plan of the offline phase, which is empty
+    let flatten = &summary.nodes[2];
+    assert_eq!(flatten.online.messages, 0);
+    assert_eq!(flatten.offline.messages, 1);
+}
@@ -0,0 +1,26 @@
+use std::error::Error;
+use std::fmt;
+
+/// A node of a model whose inputs don't fit it, or whose parameters don't fit together.
+#[derive(Debug)]
+pub struct InvalidNodeError {
+    pub node: String,
+    pub input_shapes: Vec<Vec<usize>>,
+    pub source: anyhow::Error,
+}
+
+impl Error for InvalidNodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl fmt::Display for InvalidNodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The node '{}' with input shape(s) {:?} is invalid: {:#}",
+            self.node, self.input_shapes, self.source
+        )
+    }
+}
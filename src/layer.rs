@@ -1,25 +1,212 @@
+pub mod attention;
+pub mod avg_pool;
+pub mod batch_norm;
+pub mod conv2d_layer;
+pub mod conv_transpose2d_layer;
 pub mod dense_layer;
+pub mod depthwise_conv2d_layer;
+pub mod embedding;
+pub mod flatten;
+pub mod leaky_relu;
+pub mod max_pool;
+pub mod one_hot;
+pub mod recurrent;
 pub mod relu;
+pub mod relu6;
+pub mod upsample;
 
-use crate::{message::IO, split::Split, Com};
-use dense_layer::{DenseLayer, DenseLayerShare};
-use ndarray::Array1;
+use crate::{message::IO, offline::OfflineMaterial, split::Split, Com};
+use anyhow::bail;
+use attention::{AttentionLayer, AttentionLayerShare};
+use avg_pool::{AvgPool2DLayer, AvgPool2DLayerShare, GlobalAvgPoolLayer, GlobalAvgPoolLayerShare};
+use batch_norm::{BatchNormLayer, BatchNormLayerShare};
+use conv2d_layer::{Conv2DLayer, Conv2DLayerShare};
+use conv_transpose2d_layer::{ConvTranspose2DLayer, ConvTranspose2DLayerShare};
+use dense_layer::{DenseLayer, DenseLayerShare, PrivateDenseLayerShare};
+use depthwise_conv2d_layer::{
+    DepthwiseConv2DLayer, DepthwiseConv2DLayerShare, SeparableConv2DLayer,
+    SeparableConv2DLayerShare,
+};
+use embedding::{EmbeddingLayer, EmbeddingLayerShare};
+use flatten::{FlattenLayer, FlattenLayerShare};
+use leaky_relu::{LeakyReLULayer, LeakyReLULayerShare};
+use max_pool::{MaxPool2DLayer, MaxPool2DLayerShare};
+use ndarray::{Array1, ArrayD};
+use one_hot::{OneHotLayer, OneHotLayerShare};
+use recurrent::{GRULayer, GRULayerShare, RNNLayer, RNNLayerShare};
 use relu::{ReLULayer, ReLULayerShare};
+use relu6::{ReLU6Layer, ReLU6LayerShare};
 use ring::rand::SecureRandom;
 use serde::{Deserialize, Serialize};
+use upsample::{Upsample2DLayer, Upsample2DLayerShare};
 
-#[derive(Deserialize, Debug, Clone)]
+/// Checks that there is one bias per output.
+pub(crate) fn check_biases(biases: usize, outputs: usize) -> anyhow::Result<()> {
+    if biases != outputs {
+        anyhow::bail!("There are {} biases for {} outputs", biases, outputs);
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum Layer {
     DenseLayer(DenseLayer),
+    Conv2DLayer(Conv2DLayer),
+    DepthwiseConv2DLayer(DepthwiseConv2DLayer),
+    SeparableConv2DLayer(SeparableConv2DLayer),
+    ConvTranspose2DLayer(ConvTranspose2DLayer),
+    Upsample2DLayer(Upsample2DLayer),
+    MaxPool2DLayer(MaxPool2DLayer),
+    AvgPool2DLayer(AvgPool2DLayer),
+    GlobalAvgPoolLayer(GlobalAvgPoolLayer),
+    BatchNormLayer(BatchNormLayer),
     ReLULayer(ReLULayer), // TODO ReLULayer shouldn't be a type, just use a union like a union here
+    LeakyReLULayer(LeakyReLULayer),
+    ReLU6Layer(ReLU6Layer),
+    OneHotLayer(OneHotLayer),
+    EmbeddingLayer(EmbeddingLayer),
+    RNNLayer(RNNLayer),
+    GRULayer(GRULayer),
+    AttentionLayer(AttentionLayer),
+    FlattenLayer(FlattenLayer),
 }
 
 impl Layer {
-    pub fn infer_locally(&self, input: Array1<Com>) -> Array1<Com> {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
         match self {
             Layer::DenseLayer(dense_layer) => dense_layer.infer_locally(input),
+            Layer::Conv2DLayer(conv2d_layer) => conv2d_layer.infer_locally(input),
+            Layer::DepthwiseConv2DLayer(depthwise_conv2d_layer) => {
+                depthwise_conv2d_layer.infer_locally(input)
+            }
+            Layer::SeparableConv2DLayer(separable_conv2d_layer) => {
+                separable_conv2d_layer.infer_locally(input)
+            }
+            Layer::ConvTranspose2DLayer(conv_transpose2d_layer) => {
+                conv_transpose2d_layer.infer_locally(input)
+            }
+            Layer::Upsample2DLayer(upsample_layer) => upsample_layer.infer_locally(input),
+            Layer::MaxPool2DLayer(max_pool_layer) => max_pool_layer.infer_locally(input),
+            Layer::AvgPool2DLayer(avg_pool_layer) => avg_pool_layer.infer_locally(input),
+            Layer::GlobalAvgPoolLayer(global_avg_pool_layer) => {
+                global_avg_pool_layer.infer_locally(input)
+            }
+            Layer::BatchNormLayer(batch_norm_layer) => batch_norm_layer.infer_locally(input),
             Layer::ReLULayer(relu_layer) => relu_layer.infer_locally(input),
+            Layer::LeakyReLULayer(leaky_relu_layer) => leaky_relu_layer.infer_locally(input),
+            Layer::ReLU6Layer(relu6_layer) => relu6_layer.infer_locally(input),
+            Layer::OneHotLayer(one_hot_layer) => one_hot_layer.infer_locally(input),
+            Layer::EmbeddingLayer(embedding_layer) => embedding_layer.infer_locally(input),
+            Layer::RNNLayer(rnn_layer) => rnn_layer.infer_locally(input),
+            Layer::GRULayer(gru_layer) => gru_layer.infer_locally(input),
+            Layer::AttentionLayer(attention_layer) => attention_layer.infer_locally(input),
+            Layer::FlattenLayer(flatten_layer) => flatten_layer.infer_locally(input),
+        }
+    }
+
+    /// Computes the shape of the output for an input of the given shape, checking that the input
+    /// and the parameters of the layer fit together.
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        match self {
+            Layer::DenseLayer(dense_layer) => dense_layer.output_shape(input_shape),
+            Layer::Conv2DLayer(conv2d_layer) => conv2d_layer.output_shape(input_shape),
+            Layer::DepthwiseConv2DLayer(depthwise_conv2d_layer) => {
+                depthwise_conv2d_layer.output_shape(input_shape)
+            }
+            Layer::SeparableConv2DLayer(separable_conv2d_layer) => {
+                separable_conv2d_layer.output_shape(input_shape)
+            }
+            Layer::ConvTranspose2DLayer(conv_transpose2d_layer) => {
+                conv_transpose2d_layer.output_shape(input_shape)
+            }
+            Layer::Upsample2DLayer(upsample_layer) => upsample_layer.output_shape(input_shape),
+            Layer::MaxPool2DLayer(max_pool_layer) => max_pool_layer.output_shape(input_shape),
+            Layer::AvgPool2DLayer(avg_pool_layer) => avg_pool_layer.output_shape(input_shape),
+            Layer::GlobalAvgPoolLayer(global_avg_pool_layer) => {
+                global_avg_pool_layer.output_shape(input_shape)
+            }
+            Layer::BatchNormLayer(batch_norm_layer) => batch_norm_layer.output_shape(input_shape),
+            // Element-wise
+            Layer::ReLULayer(_) | Layer::LeakyReLULayer(_) | Layer::ReLU6Layer(_) => {
+                Ok(input_shape.to_vec())
+            }
+            Layer::OneHotLayer(one_hot_layer) => one_hot_layer.output_shape(input_shape),
+            Layer::EmbeddingLayer(embedding_layer) => embedding_layer.output_shape(input_shape),
+            Layer::RNNLayer(rnn_layer) => rnn_layer.output_shape(input_shape),
+            Layer::GRULayer(gru_layer) => gru_layer.output_shape(input_shape),
+            Layer::AttentionLayer(attention_layer) => attention_layer.output_shape(input_shape),
+            Layer::FlattenLayer(_) => Ok(vec![input_shape.iter().product()]),
+        }
+    }
+
+    /// Like `split`, but layers keep their weights on the server instead of sharing them.
+    ///
+    /// Layers without weights are split as usual. Only dense layers can keep their weights
+    /// private so far, so any other layer with weights is an error.
+    pub fn split_private_weights(
+        &self,
+        rng: &dyn SecureRandom,
+    ) -> anyhow::Result<(LayerShare, LayerShare)> {
+        match self {
+            Layer::DenseLayer(dense_layer) => {
+                let shares = dense_layer.split_private_weights();
+                Ok((
+                    LayerShare::PrivateDenseLayerShare(shares.0),
+                    LayerShare::PrivateDenseLayerShare(shares.1),
+                ))
+            }
+            Layer::Conv2DLayer(_)
+            | Layer::DepthwiseConv2DLayer(_)
+            | Layer::SeparableConv2DLayer(_)
+            | Layer::ConvTranspose2DLayer(_)
+            | Layer::BatchNormLayer(_)
+            | Layer::EmbeddingLayer(_)
+            | Layer::RNNLayer(_)
+            | Layer::GRULayer(_)
+            | Layer::AttentionLayer(_) => {
+                bail!("Only dense layers can keep their weights private, sharing them would leak them")
+            }
+            Layer::Upsample2DLayer(_)
+            | Layer::MaxPool2DLayer(_)
+            | Layer::AvgPool2DLayer(_)
+            | Layer::GlobalAvgPoolLayer(_)
+            | Layer::ReLULayer(_)
+            | Layer::LeakyReLULayer(_)
+            | Layer::ReLU6Layer(_)
+            | Layer::OneHotLayer(_)
+            | Layer::FlattenLayer(_) => Ok(self.split(rng)),
+        }
+    }
+
+    /// Folds a batch normalisation into this layer, if it is linear and has as many outputs as
+    /// the batch normalisation has features.
+    ///
+    /// # Returns
+    ///
+    /// Whether the batch normalisation was folded
+    pub(crate) fn fold_batch_norm(&mut self, batch_norm_layer: &BatchNormLayer) -> bool {
+        let (scale, shift) = batch_norm_layer.scale_and_shift();
+        self.fold_affine(&scale, &shift)
+    }
+
+    /// Folds a following affine transformation, x · scale + shift per output (channel), into this
+    /// layer, if it is linear and has as many outputs as the transformation.
+    ///
+    /// # Returns
+    ///
+    /// Whether the transformation was folded
+    pub(crate) fn fold_affine(&mut self, scale: &Array1<f64>, shift: &Array1<f64>) -> bool {
+        match self {
+            Layer::DenseLayer(dense_layer) if dense_layer.output_size() == scale.len() => {
+                dense_layer.fold(scale, shift);
+                true
+            }
+            Layer::Conv2DLayer(conv2d_layer) if conv2d_layer.output_channels() == scale.len() => {
+                conv2d_layer.fold(scale, shift);
+                true
+            }
+            _ => false,
         }
     }
 }
@@ -28,27 +215,127 @@ impl Layer {
 #[serde(tag = "type")]
 pub enum LayerShare {
     DenseLayerShare(DenseLayerShare),
+    PrivateDenseLayerShare(PrivateDenseLayerShare),
+    Conv2DLayerShare(Conv2DLayerShare),
+    DepthwiseConv2DLayerShare(DepthwiseConv2DLayerShare),
+    SeparableConv2DLayerShare(SeparableConv2DLayerShare),
+    ConvTranspose2DLayerShare(ConvTranspose2DLayerShare),
+    Upsample2DLayerShare(Upsample2DLayerShare),
+    MaxPool2DLayerShare(MaxPool2DLayerShare),
+    AvgPool2DLayerShare(AvgPool2DLayerShare),
+    GlobalAvgPoolLayerShare(GlobalAvgPoolLayerShare),
+    BatchNormLayerShare(BatchNormLayerShare),
     ReLULayerShare(ReLULayerShare),
+    LeakyReLULayerShare(LeakyReLULayerShare),
+    ReLU6LayerShare(ReLU6LayerShare),
+    OneHotLayerShare(OneHotLayerShare),
+    EmbeddingLayerShare(EmbeddingLayerShare),
+    RNNLayerShare(RNNLayerShare),
+    GRULayerShare(GRULayerShare),
+    AttentionLayerShare(AttentionLayerShare),
+    FlattenLayerShare(FlattenLayerShare),
 }
 
 impl LayerShare {
     pub async fn infer<const PARTY: bool>(
         &self,
-        input_share: Array1<Com>,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
         (sender, receiver): IO<'_>,
-        rng: &dyn SecureRandom,
-    ) -> anyhow::Result<Array1<Com>> {
+    ) -> anyhow::Result<ArrayD<Com>> {
         match self {
             LayerShare::DenseLayerShare(dense_layer_share) => {
                 dense_layer_share
-                    .infer::<PARTY>(input_share, (sender, receiver))
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::PrivateDenseLayerShare(private_dense_layer_share) => {
+                private_dense_layer_share
+                    .infer(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::Conv2DLayerShare(conv2d_layer_share) => {
+                conv2d_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::DepthwiseConv2DLayerShare(depthwise_conv2d_layer_share) => {
+                depthwise_conv2d_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::SeparableConv2DLayerShare(separable_conv2d_layer_share) => {
+                separable_conv2d_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::ConvTranspose2DLayerShare(conv_transpose2d_layer_share) => {
+                conv_transpose2d_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::Upsample2DLayerShare(upsample_layer_share) => {
+                upsample_layer_share.infer(input_share)
+            }
+            LayerShare::MaxPool2DLayerShare(max_pool_layer_share) => {
+                max_pool_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::AvgPool2DLayerShare(avg_pool_layer_share) => {
+                avg_pool_layer_share.infer::<PARTY>(input_share)
+            }
+            LayerShare::GlobalAvgPoolLayerShare(global_avg_pool_layer_share) => {
+                global_avg_pool_layer_share.infer::<PARTY>(input_share)
+            }
+            LayerShare::BatchNormLayerShare(batch_norm_layer_share) => {
+                batch_norm_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
                     .await
             }
             LayerShare::ReLULayerShare(relu_layer_share) => {
                 relu_layer_share
-                    .infer::<PARTY>(input_share, (sender, receiver), rng)
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::LeakyReLULayerShare(leaky_relu_layer_share) => {
+                leaky_relu_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::ReLU6LayerShare(relu6_layer_share) => {
+                relu6_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::OneHotLayerShare(one_hot_layer_share) => {
+                one_hot_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::EmbeddingLayerShare(embedding_layer_share) => {
+                embedding_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
                     .await
             }
+            LayerShare::RNNLayerShare(rnn_layer_share) => {
+                rnn_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::GRULayerShare(gru_layer_share) => {
+                gru_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::AttentionLayerShare(attention_layer_share) => {
+                attention_layer_share
+                    .infer::<PARTY>(input_share, material, (sender, receiver))
+                    .await
+            }
+            LayerShare::FlattenLayerShare(flatten_layer_share) => {
+                flatten_layer_share.infer(input_share)
+            }
         }
     }
 }
@@ -65,6 +352,69 @@ impl Split for Layer {
                     LayerShare::DenseLayerShare(shares.1),
                 )
             }
+            Layer::Conv2DLayer(conv2d_layer) => {
+                let shares = Conv2DLayer::split(conv2d_layer, rng);
+                (
+                    LayerShare::Conv2DLayerShare(shares.0),
+                    LayerShare::Conv2DLayerShare(shares.1),
+                )
+            }
+            Layer::DepthwiseConv2DLayer(depthwise_conv2d_layer) => {
+                let shares = DepthwiseConv2DLayer::split(depthwise_conv2d_layer, rng);
+                (
+                    LayerShare::DepthwiseConv2DLayerShare(shares.0),
+                    LayerShare::DepthwiseConv2DLayerShare(shares.1),
+                )
+            }
+            Layer::SeparableConv2DLayer(separable_conv2d_layer) => {
+                let shares = SeparableConv2DLayer::split(separable_conv2d_layer, rng);
+                (
+                    LayerShare::SeparableConv2DLayerShare(shares.0),
+                    LayerShare::SeparableConv2DLayerShare(shares.1),
+                )
+            }
+            Layer::ConvTranspose2DLayer(conv_transpose2d_layer) => {
+                let shares = ConvTranspose2DLayer::split(conv_transpose2d_layer, rng);
+                (
+                    LayerShare::ConvTranspose2DLayerShare(shares.0),
+                    LayerShare::ConvTranspose2DLayerShare(shares.1),
+                )
+            }
+            Layer::Upsample2DLayer(upsample_layer) => {
+                let shares = Upsample2DLayer::split(upsample_layer, rng);
+                (
+                    LayerShare::Upsample2DLayerShare(shares.0),
+                    LayerShare::Upsample2DLayerShare(shares.1),
+                )
+            }
+            Layer::MaxPool2DLayer(max_pool_layer) => {
+                let shares = MaxPool2DLayer::split(max_pool_layer, rng);
+                (
+                    LayerShare::MaxPool2DLayerShare(shares.0),
+                    LayerShare::MaxPool2DLayerShare(shares.1),
+                )
+            }
+            Layer::AvgPool2DLayer(avg_pool_layer) => {
+                let shares = AvgPool2DLayer::split(avg_pool_layer, rng);
+                (
+                    LayerShare::AvgPool2DLayerShare(shares.0),
+                    LayerShare::AvgPool2DLayerShare(shares.1),
+                )
+            }
+            Layer::GlobalAvgPoolLayer(global_avg_pool_layer) => {
+                let shares = GlobalAvgPoolLayer::split(global_avg_pool_layer, rng);
+                (
+                    LayerShare::GlobalAvgPoolLayerShare(shares.0),
+                    LayerShare::GlobalAvgPoolLayerShare(shares.1),
+                )
+            }
+            Layer::BatchNormLayer(batch_norm_layer) => {
+                let shares = BatchNormLayer::split(batch_norm_layer, rng);
+                (
+                    LayerShare::BatchNormLayerShare(shares.0),
+                    LayerShare::BatchNormLayerShare(shares.1),
+                )
+            }
             Layer::ReLULayer(relu_layer) => {
                 let shares = ReLULayer::split(relu_layer, rng);
                 (
@@ -72,6 +422,62 @@ impl Split for Layer {
                     LayerShare::ReLULayerShare(shares.1),
                 )
             }
+            Layer::LeakyReLULayer(leaky_relu_layer) => {
+                let shares = LeakyReLULayer::split(leaky_relu_layer, rng);
+                (
+                    LayerShare::LeakyReLULayerShare(shares.0),
+                    LayerShare::LeakyReLULayerShare(shares.1),
+                )
+            }
+            Layer::ReLU6Layer(relu6_layer) => {
+                let shares = ReLU6Layer::split(relu6_layer, rng);
+                (
+                    LayerShare::ReLU6LayerShare(shares.0),
+                    LayerShare::ReLU6LayerShare(shares.1),
+                )
+            }
+            Layer::OneHotLayer(one_hot_layer) => {
+                let shares = OneHotLayer::split(one_hot_layer, rng);
+                (
+                    LayerShare::OneHotLayerShare(shares.0),
+                    LayerShare::OneHotLayerShare(shares.1),
+                )
+            }
+            Layer::EmbeddingLayer(embedding_layer) => {
+                let shares = EmbeddingLayer::split(embedding_layer, rng);
+                (
+                    LayerShare::EmbeddingLayerShare(shares.0),
+                    LayerShare::EmbeddingLayerShare(shares.1),
+                )
+            }
+            Layer::RNNLayer(rnn_layer) => {
+                let shares = RNNLayer::split(rnn_layer, rng);
+                (
+                    LayerShare::RNNLayerShare(shares.0),
+                    LayerShare::RNNLayerShare(shares.1),
+                )
+            }
+            Layer::GRULayer(gru_layer) => {
+                let shares = GRULayer::split(gru_layer, rng);
+                (
+                    LayerShare::GRULayerShare(shares.0),
+                    LayerShare::GRULayerShare(shares.1),
+                )
+            }
+            Layer::AttentionLayer(attention_layer) => {
+                let shares = AttentionLayer::split(attention_layer, rng);
+                (
+                    LayerShare::AttentionLayerShare(shares.0),
+                    LayerShare::AttentionLayerShare(shares.1),
+                )
+            }
+            Layer::FlattenLayer(flatten_layer) => {
+                let shares = FlattenLayer::split(flatten_layer, rng);
+                (
+                    LayerShare::FlattenLayerShare(shares.0),
+                    LayerShare::FlattenLayerShare(shares.1),
+                )
+            }
         }
     }
 }
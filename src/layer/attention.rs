@@ -0,0 +1,187 @@
+use anyhow::{bail, Context as _};
+use ndarray::{concatenate, s, Array2, ArrayD, Axis, Ix2};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::IO,
+    offline::OfflineMaterial,
+    shape_mismatch_error::ShapeMismatchError,
+    softmax::{row_length_bits, softmax, softmax_locally},
+    split::Split,
+    truncation, Com,
+};
+
+/// Converts a tensor into a (sequence length, model dimension) sequence.
+fn into_sequence(x: ArrayD<Com>, dimension: usize) -> Result<Array2<Com>, ShapeMismatchError> {
+    ShapeMismatchError::check(x.shape(), &[None, Some(dimension)])?;
+    Ok(x.into_dimensionality::<Ix2>().unwrap())
+}
+
+/// The scale of the attention scores, 1 / √(key dimension).
+fn score_scale(key_dimension: usize) -> Com {
+    Com::from_num(1.0 / (key_dimension as f64).sqrt())
+}
+
+/// Single-head scaled dot-product self-attention over a (sequence length, model dimension) input,
+/// softmax(Q · Kᵀ / √d) · V where Q = X · W_Q, K = X · W_K and V = X · W_V.
+///
+/// The softmax is approximated, see `softmax`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttentionLayer {
+    /// W_Q, (model dimension, key dimension)
+    query_kernel: Array2<Com>,
+    /// W_K, (model dimension, key dimension)
+    key_kernel: Array2<Com>,
+    /// W_V, (model dimension, value dimension)
+    value_kernel: Array2<Com>,
+}
+
+impl AttentionLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let input = into_sequence(input, self.query_kernel.nrows())?;
+
+        let queries = input.dot(&self.query_kernel);
+        let keys = input.dot(&self.key_kernel);
+        let values = input.dot(&self.value_kernel);
+
+        let scores = queries.dot(&keys.t()) * score_scale(self.key_kernel.ncols());
+        Ok(softmax_locally(&scores)?.dot(&values).into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let dimension = self.query_kernel.nrows();
+        if self.key_kernel.dim() != self.query_kernel.dim() {
+            bail!(
+                "The key kernel {:?} does not match the query kernel {:?}",
+                self.key_kernel.dim(),
+                self.query_kernel.dim()
+            );
+        }
+        if self.value_kernel.nrows() != dimension {
+            bail!(
+                "The value kernel takes {} dimensions rather than {}",
+                self.value_kernel.nrows(),
+                dimension
+            );
+        }
+
+        ShapeMismatchError::check(input_shape, &[None, Some(dimension)])?;
+        // The softmax runs over rows as long as the sequence
+        row_length_bits(input_shape[0])?;
+        Ok(vec![input_shape[0], self.value_kernel.ncols()])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AttentionLayerShare {
+    pub(self) query_kernel_share: Array2<Com>,
+    pub(self) key_kernel_share: Array2<Com>,
+    pub(self) value_kernel_share: Array2<Com>,
+}
+
+impl AttentionLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (dimension, key_dimension) = self.query_kernel_share.dim();
+        let value_dimension = self.value_kernel_share.ncols();
+        let input_share = into_sequence(input_share, dimension)?;
+        let length = input_share.nrows();
+
+        // Project the queries, the keys and the values at once
+        let kernels_share = concatenate![
+            Axis(1),
+            self.query_kernel_share,
+            self.key_kernel_share,
+            self.value_kernel_share
+        ];
+        let mt = material.take_matrix_product_triplet(length, dimension, kernels_share.ncols())?;
+        let projections_share = mt
+            .matrix_product::<PARTY>(&input_share, &kernels_share, (sender, receiver))
+            .await
+            .context("Failed to project the input")?;
+        let queries_share = projections_share.slice(s![.., ..key_dimension]).to_owned();
+        let keys_share = projections_share
+            .slice(s![.., key_dimension..2 * key_dimension])
+            .to_owned();
+        let values_share = projections_share
+            .slice(s![.., 2 * key_dimension..])
+            .to_owned();
+
+        // Both operands of Q · Kᵀ are shared
+        let mt = material.take_matrix_product_triplet(length, key_dimension, length)?;
+        let scores_share = mt
+            .matrix_product::<PARTY>(
+                &queries_share,
+                &keys_share.t().to_owned(),
+                (sender, receiver),
+            )
+            .await
+            .context("Failed to multiply the queries by the keys")?;
+        let scores_share = truncation::scale::<PARTY, _>(&scores_share, score_scale(key_dimension));
+
+        let weights_share = softmax::<PARTY>(&scores_share, material, (sender, receiver))
+            .await
+            .context("Failed to evaluate the softmax")?;
+
+        let mt = material.take_matrix_product_triplet(length, length, value_dimension)?;
+        let output_share = mt
+            .matrix_product::<PARTY>(&weights_share, &values_share, (sender, receiver))
+            .await
+            .context("Failed to multiply the attention weights by the values")?;
+
+        Ok(output_share.into_dyn())
+    }
+}
+
+impl Split for AttentionLayer {
+    type Splitted = AttentionLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let query_kernel_shares = self.query_kernel.split(rng);
+        let key_kernel_shares = self.key_kernel.split(rng);
+        let value_kernel_shares = self.value_kernel.split(rng);
+
+        (
+            AttentionLayerShare {
+                query_kernel_share: query_kernel_shares.0,
+                key_kernel_share: key_kernel_shares.0,
+                value_kernel_share: value_kernel_shares.0,
+            },
+            AttentionLayerShare {
+                query_kernel_share: query_kernel_shares.1,
+                key_kernel_share: key_kernel_shares.1,
+                value_kernel_share: value_kernel_shares.1,
+            },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_attention_matches_plaintext() {
+    use ndarray::array;
+
+    use crate::{layer::Layer, testing};
+
+    let integers = |x: Array2<i32>| x.mapv(Com::from_num);
+    // The projected queries and keys are ±3 times orthogonal unit vectors, so every row of the
+    // scores is 9 on the diagonal and at most 0 elsewhere, and the softmax is one-hot
+    let kernel = integers(array![[3, 0], [0, 3], [0, 0]]);
+    let layer = AttentionLayer {
+        query_kernel: kernel.clone(),
+        key_kernel: kernel,
+        value_kernel: integers(array![[1, 0, -1], [0, 1, 1], [1, -1, 0]]),
+    };
+    let input = integers(array![[1, 0, 1], [0, 1, 1], [-1, 0, 0], [0, -1, -1]]).into_dyn();
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    let (output, log) = testing::run_secure(Layer::AttentionLayer(layer), &input).await;
+
+    // Each truncation errs by one unit in the last place
+    assert_eq!(output.shape(), [4, 3]);
+    testing::assert_close(&output, &expected, 4);
+    assert_eq!(log.count(true, "MatrixProductInteraction"), 3);
+}
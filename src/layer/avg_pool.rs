@@ -0,0 +1,181 @@
+use crate::{
+    convolution::{
+        feature_map_size, gather_windows, into_feature_map, pooling_output_shape, pooling_window,
+    },
+    split::Split,
+    tensor, truncation, Com,
+};
+use ndarray::{ArrayD, Axis};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// Averages every (pool size, pool size) window of a (channels, height, width) feature map.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AvgPool2DLayer {
+    pub pool_size: usize,
+    /// Defaults to the pool size, i.e. non-overlapping windows
+    #[serde(default)]
+    pub stride: Option<usize>,
+}
+
+impl AvgPool2DLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (pool_size, stride) = pooling_window(self.pool_size, self.stride)?;
+        let channels = input.shape().first().copied().unwrap_or_default();
+        let input = into_feature_map(input, channels, (pool_size, pool_size), 0)?;
+
+        let (windows, (output_height, output_width)) =
+            gather_windows(&input.view(), (pool_size, pool_size), stride);
+        let divisor = Com::from_num(pool_size * pool_size);
+        let averages = windows.sum_axis(Axis(1)) / divisor;
+
+        Ok(tensor::unflatten(
+            averages,
+            &[channels, output_height, output_width],
+        ))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        pooling_output_shape(input_shape, self.pool_size, self.stride)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AvgPool2DLayerShare {
+    pub pool_size: usize,
+    pub stride: Option<usize>,
+}
+
+impl AvgPool2DLayerShare {
+    pub fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (pool_size, stride) = pooling_window(self.pool_size, self.stride)?;
+        let channels = input_share.shape().first().copied().unwrap_or_default();
+        let input_share = into_feature_map(input_share, channels, (pool_size, pool_size), 0)?;
+
+        // Summing is linear and dividing by a public integer is a local truncation
+        let (windows_share, (output_height, output_width)) =
+            gather_windows(&input_share.view(), (pool_size, pool_size), stride);
+        let averages_share =
+            truncation::divide::<PARTY, _>(&windows_share.sum_axis(Axis(1)), pool_size * pool_size);
+
+        Ok(tensor::unflatten(
+            averages_share,
+            &[channels, output_height, output_width],
+        ))
+    }
+}
+
+impl Split for AvgPool2DLayer {
+    type Splitted = AvgPool2DLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            AvgPool2DLayerShare {
+                pool_size: self.pool_size,
+                stride: self.stride,
+            },
+            AvgPool2DLayerShare {
+                pool_size: self.pool_size,
+                stride: self.stride,
+            },
+        )
+    }
+}
+
+/// Averages every channel of a (channels, height, width) feature map into a vector.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GlobalAvgPoolLayer {}
+
+impl GlobalAvgPoolLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let channels = input.shape().first().copied().unwrap_or_default();
+        let input = into_feature_map(input, channels, (1, 1), 0)?;
+
+        let (_, height, width) = input.dim();
+        let divisor = Com::from_num(height * width);
+        let sums = input.sum_axis(Axis(2)).sum_axis(Axis(1));
+
+        Ok((sums / divisor).into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let (channels, _, _) = feature_map_size(input_shape, None, (1, 1), 0)?;
+        Ok(vec![channels])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GlobalAvgPoolLayerShare {}
+
+impl GlobalAvgPoolLayerShare {
+    pub fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let channels = input_share.shape().first().copied().unwrap_or_default();
+        let input_share = into_feature_map(input_share, channels, (1, 1), 0)?;
+
+        let (_, height, width) = input_share.dim();
+        let sums_share = input_share.sum_axis(Axis(2)).sum_axis(Axis(1));
+
+        Ok(truncation::divide::<PARTY, _>(&sums_share, height * width).into_dyn())
+    }
+}
+
+impl Split for GlobalAvgPoolLayer {
+    type Splitted = GlobalAvgPoolLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (GlobalAvgPoolLayerShare {}, GlobalAvgPoolLayerShare {})
+    }
+}
+
+#[test]
+fn test_avg_pool_matches_plaintext() {
+    use crate::reconstruct::Reconstruct;
+    use ndarray::Array3;
+
+    let rng = ring::rand::SystemRandom::new();
+    let input = Array3::from_shape_fn((2, 6, 6), |(c, i, j)| {
+        Com::from_num((c as f32 - 0.5) * (i as f32 * 1.5 - j as f32))
+    })
+    .into_dyn();
+    let input_shares = input.split(&rng);
+    let tolerance = Com::from_num(0.5);
+
+    // A power-of-two window and one which is not
+    for pool_size in [2, 3] {
+        let layer = AvgPool2DLayer {
+            pool_size,
+            stride: None,
+        };
+        let expected = layer.infer_locally(input.clone()).unwrap();
+
+        let (server_share, client_share) = layer.split(&rng);
+        let output = ArrayD::reconstruct((
+            &server_share.infer::<true>(input_shares.0.clone()).unwrap(),
+            &client_share.infer::<false>(input_shares.1.clone()).unwrap(),
+        ));
+
+        assert_eq!(output.shape(), expected.shape());
+        assert!(ndarray::Zip::from(&output)
+            .and(&expected)
+            .all(|&o, &e| (o - e).abs() <= tolerance));
+    }
+
+    let expected = GlobalAvgPoolLayer {}.infer_locally(input.clone()).unwrap();
+    let output = ArrayD::reconstruct((
+        &GlobalAvgPoolLayerShare {}
+            .infer::<true>(input_shares.0.clone())
+            .unwrap(),
+        &GlobalAvgPoolLayerShare {}
+            .infer::<false>(input_shares.1.clone())
+            .unwrap(),
+    ));
+    assert!(ndarray::Zip::from(&output)
+        .and(&expected)
+        .all(|&o, &e| (o - e).abs() <= tolerance));
+}
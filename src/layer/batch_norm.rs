@@ -0,0 +1,241 @@
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, ArrayD};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::IO, offline::OfflineMaterial, shape_mismatch_error::ShapeMismatchError, split::Split,
+    tensor, Com,
+};
+
+fn default_epsilon() -> f32 {
+    1e-3
+}
+
+/// Normalises every feature (or channel, for feature maps) with the statistics gathered during
+/// training, i.e. γ · (x - μ) / √(σ² + ε) + β.
+///
+/// The features are along the first axis.
+///
+/// The statistics are real numbers rather than `Com`s, since a small variance would round to 0.
+/// Only the folded weights and biases, or the scale and the shift of an unfolded normalisation,
+/// are quantized.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchNormLayer {
+    pub mean: Array1<f64>,
+    pub variance: Array1<f64>,
+    pub gamma: Array1<f64>,
+    pub beta: Array1<f64>,
+    #[serde(default = "default_epsilon")]
+    pub epsilon: f32,
+}
+
+impl BatchNormLayer {
+    pub fn features(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// Rewrites the normalisation as the affine transformation x · scale + shift.
+    ///
+    /// This is computed in floating-point, so that folding into a preceding layer only rounds
+    /// once.
+    pub(crate) fn scale_and_shift(&self) -> (Array1<f64>, Array1<f64>) {
+        let scale = ndarray::Zip::from(&self.gamma)
+            .and(&self.variance)
+            .map_collect(|gamma, variance| gamma / (variance + self.epsilon as f64).sqrt());
+        let shift = ndarray::Zip::from(&self.beta)
+            .and(&self.mean)
+            .and(&scale)
+            .map_collect(|beta, mean, scale| beta - mean * scale);
+
+        (scale, shift)
+    }
+
+    fn quantized_scale_and_shift(&self) -> (Array1<Com>, Array1<Com>) {
+        let (scale, shift) = self.scale_and_shift();
+        (scale.mapv(Com::from_num), shift.mapv(Com::from_num))
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let shape = input.shape().to_vec();
+        check_features(&shape, self.features())?;
+
+        let (scale, shift) = self.quantized_scale_and_shift();
+        let input = tensor::flatten(input);
+        let output = &input * &broadcast(&scale, input.len()) + &broadcast(&shift, input.len());
+
+        Ok(tensor::unflatten(output, &shape))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        for (name, parameter) in [
+            ("variances", &self.variance),
+            ("gammas", &self.gamma),
+            ("betas", &self.beta),
+        ] {
+            if parameter.len() != self.features() {
+                bail!(
+                    "There are {} {} for {} features",
+                    parameter.len(),
+                    name,
+                    self.features()
+                );
+            }
+        }
+        check_features(input_shape, self.features())?;
+        Ok(input_shape.to_vec())
+    }
+}
+
+/// A batch normalisation that could not be folded into a preceding linear layer, evaluated as a
+/// secure affine transformation.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchNormLayerShare {
+    pub(self) scale_share: Array1<Com>,
+    pub(self) shift_share: Array1<Com>,
+}
+
+impl BatchNormLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let shape = input_share.shape().to_vec();
+        check_features(&shape, self.scale_share.len())?;
+
+        let input_share = tensor::flatten(input_share);
+        let n = input_share.len();
+
+        let mt = material.take_hadamard_triplet(n)?;
+        let product_share = mt
+            .hadamard_product::<PARTY>(
+                &input_share,
+                &broadcast(&self.scale_share, n),
+                (sender, receiver),
+            )
+            .await
+            .context("Failed to multiply the activations by the scale")?;
+
+        Ok(tensor::unflatten(
+            product_share + broadcast(&self.shift_share, n),
+            &shape,
+        ))
+    }
+}
+
+impl Split for BatchNormLayer {
+    type Splitted = BatchNormLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let (scale, shift) = self.quantized_scale_and_shift();
+        let scale_shares = scale.split(rng);
+        let shift_shares = shift.split(rng);
+
+        (
+            BatchNormLayerShare {
+                scale_share: scale_shares.0,
+                shift_share: shift_shares.0,
+            },
+            BatchNormLayerShare {
+                scale_share: scale_shares.1,
+                shift_share: shift_shares.1,
+            },
+        )
+    }
+}
+
+fn check_features(shape: &[usize], features: usize) -> Result<(), ShapeMismatchError> {
+    let mut expected = vec![None; shape.len().max(1)];
+    expected[0] = Some(features);
+    ShapeMismatchError::check(shape, &expected)
+}
+
+/// Repeats every per-feature value over its feature, to match a flattened tensor of length `n`.
+fn broadcast(x: &Array1<Com>, n: usize) -> Array1<Com> {
+    let repetitions = n / x.len();
+    Array1::from_iter(
+        x.iter()
+            .flat_map(|&x| std::iter::repeat(x).take(repetitions)),
+    )
+}
+
+#[test]
+fn test_batch_norm_folding() {
+    use crate::model::Model;
+
+    // Com has two fractional bits, so 4 is 1.0, but the statistics are real numbers
+    let model: Model = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "DenseLayer",
+             "weights": {"v": 1, "dim": [2, 2], "data": [4, 8, -4, 4]},
+             "biases": {"v": 1, "dim": [2], "data": [4, 0]}},
+            {"type": "BatchNormLayer",
+             "mean": {"v": 1, "dim": [2], "data": [1.0, -1.0]},
+             "variance": {"v": 1, "dim": [2], "data": [4.0, 1.0]},
+             "gamma": {"v": 1, "dim": [2], "data": [2.0, 1.0]},
+             "beta": {"v": 1, "dim": [2], "data": [0.0, 0.5]},
+             "epsilon": 0.0}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(model.nodes.len(), 1);
+
+    // (x · W + b - μ) · γ / σ + β
+    let input = ndarray::array![2.0, 1.0].mapv(Com::from_num).into_dyn();
+    let output = model.infer_locally(input).unwrap();
+    assert_eq!(
+        output,
+        ndarray::array![1.0, 6.5].mapv(Com::from_num).into_dyn()
+    );
+}
+
+#[test]
+fn test_small_variances_are_not_quantized() {
+    use crate::model::Model;
+
+    // A variance of 1/16 would round to 0 with two fractional bits
+    let model: Model = serde_json::from_str(
+        r#"{"format": {"integer_bits": 30, "fractional_bits": 2},
+            "layers": [
+            {"type": "DenseLayer", "weights": [[1.0]], "biases": [0.0]},
+            {"type": "BatchNormLayer",
+             "mean": [0.0], "variance": [0.0625], "gamma": [0.5], "beta": [0.0],
+             "epsilon": 0.0}
+        ]}"#,
+    )
+    .unwrap();
+
+    let input = ndarray::array![1.0].mapv(Com::from_num).into_dyn();
+    let output = model.infer_locally(input).unwrap();
+    assert_eq!(output, ndarray::array![2.0].mapv(Com::from_num).into_dyn());
+}
+
+#[tokio::test]
+async fn test_unfolded_batch_norm_matches_plaintext() {
+    use crate::{model::Model, testing};
+
+    // A ReLU cannot absorb the normalisation, so it is evaluated securely
+    let model: Model = serde_json::from_str(
+        r#"{"format": {"integer_bits": 30, "fractional_bits": 2},
+            "layers": [
+            {"type": "ReLULayer"},
+            {"type": "BatchNormLayer",
+             "mean": [1.0, -1.0], "variance": [4.0, 1.0], "gamma": [2.0, 1.0], "beta": [0.0, 0.5],
+             "epsilon": 0.0}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(model.nodes.len(), 2);
+
+    let input = ndarray::array![[3.0, -1.0], [2.5, 0.75]]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let expected = model.infer_locally(input.clone()).unwrap();
+    let (output, log) = testing::run_secure_model(&model, &input).await;
+
+    // Truncating the shares of the products may be off by one unit in the last place
+    testing::assert_close(&output, &expected, 1);
+    assert_eq!(log.count(true, "HadamardProductInteraction"), 1);
+}
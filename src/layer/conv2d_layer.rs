@@ -0,0 +1,211 @@
+use anyhow::Context as _;
+use ndarray::{Array1, Array4, ArrayD, Axis, Zip};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    convolution::{check_stride, convolve, feature_map_size, into_feature_map, output_size, pad},
+    message::IO,
+    multiplication_triplet_share::BilinearOperation,
+    offline::OfflineMaterial,
+    split::Split,
+    Com,
+};
+
+fn default_stride() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Conv2DLayer {
+    /// (output channels, input channels, kernel height, kernel width)
+    kernels: Array4<Com>,
+    /// One bias per output channel
+    biases: Array1<Com>,
+    #[serde(default = "default_stride")]
+    stride: usize,
+    #[serde(default)]
+    padding: usize,
+}
+
+impl Conv2DLayer {
+    pub fn new(kernels: Array4<Com>, biases: Array1<Com>, stride: usize, padding: usize) -> Self {
+        Conv2DLayer {
+            kernels,
+            biases,
+            stride,
+            padding,
+        }
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (_, input_channels, kernel_height, kernel_width) = self.kernels.dim();
+        let input = into_feature_map(
+            input,
+            input_channels,
+            (kernel_height, kernel_width),
+            self.padding,
+        )?;
+
+        let output = convolve(
+            &pad(&input.view(), self.padding).view(),
+            &self.kernels.view(),
+            self.stride,
+        ) + &self.biases.view().insert_axis(Axis(1)).insert_axis(Axis(2));
+
+        Ok(output.into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let (output_channels, input_channels, kernel_height, kernel_width) = self.kernels.dim();
+        super::check_biases(self.biases.len(), output_channels)?;
+        check_stride(self.stride)?;
+        let (_, height, width) = feature_map_size(
+            input_shape,
+            Some(input_channels),
+            (kernel_height, kernel_width),
+            self.padding,
+        )?;
+
+        let (output_height, output_width) = output_size(
+            (height + 2 * self.padding, width + 2 * self.padding),
+            (kernel_height, kernel_width),
+            self.stride,
+        );
+        Ok(vec![output_channels, output_height, output_width])
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.kernels.dim().0
+    }
+
+    /// Folds a following per-channel affine transformation, e.g. a batch normalisation, into the
+    /// kernels and the biases.
+    pub(crate) fn fold(&mut self, scale: &Array1<f64>, shift: &Array1<f64>) {
+        for (mut kernel, &scale) in self.kernels.outer_iter_mut().zip(scale) {
+            kernel.mapv_inplace(|k| Com::from_num(k.0.to_num::<f64>() * scale));
+        }
+        self.biases = Zip::from(&self.biases)
+            .and(scale)
+            .and(shift)
+            .map_collect(|b, scale, shift| Com::from_num(b.0.to_num::<f64>() * scale + shift));
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Conv2DLayerShare {
+    pub(self) kernels_share: Array4<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) stride: usize,
+    pub(self) padding: usize,
+}
+
+impl Conv2DLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (_, input_channels, kernel_height, kernel_width) = self.kernels_share.dim();
+        let input_share = into_feature_map(
+            input_share,
+            input_channels,
+            (kernel_height, kernel_width),
+            self.padding,
+        )?;
+        let padded_input_share = pad(&input_share.view(), self.padding);
+
+        let mt = material.take_convolution_triplet(
+            BilinearOperation::Convolution {
+                stride: self.stride,
+            },
+            padded_input_share.dim(),
+            self.kernels_share.dim(),
+        )?;
+        let output_share = mt
+            .convolution::<PARTY>(
+                &padded_input_share,
+                &self.kernels_share,
+                self.stride,
+                (sender, receiver),
+            )
+            .await
+            .context("Failed to convolve the activations with the kernels")?
+            + &self
+                .biases_share
+                .view()
+                .insert_axis(Axis(1))
+                .insert_axis(Axis(2));
+
+        Ok(output_share.into_dyn())
+    }
+}
+
+impl Split for Conv2DLayer {
+    type Splitted = Conv2DLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let kernels_shares = self.kernels.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            Conv2DLayerShare {
+                kernels_share: kernels_shares.0,
+                biases_share: biases_shares.0,
+                stride: self.stride,
+                padding: self.padding,
+            },
+            Conv2DLayerShare {
+                kernels_share: kernels_shares.1,
+                biases_share: biases_shares.1,
+                stride: self.stride,
+                padding: self.padding,
+            },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_conv2d_matches_plaintext() {
+    use ndarray::Array3;
+
+    use crate::{layer::Layer, testing};
+
+    // Multiples of 1/4 in [-1, 1] and integral kernels, so the plaintext products are exact
+    let value = |i: usize| Com::from_num((i * 7 % 9) as f32 * 0.25 - 1.0);
+    let input = Array3::from_shape_fn((2, 5, 5), |(c, i, j)| value(c * 25 + i * 5 + j)).into_dyn();
+    let kernels = Array4::from_shape_fn((3, 2, 3, 3), |(o, c, i, j)| {
+        Com::from_num((o * 18 + c * 9 + i * 3 + j) as i32 % 5 - 2)
+    });
+    let biases = Array1::from_shape_fn(3, value);
+
+    for (stride, padding) in [(1, 0), (2, 1)] {
+        let layer = Conv2DLayer::new(kernels.clone(), biases.clone(), stride, padding);
+        let expected = layer.infer_locally(input.clone()).unwrap();
+        let (output, log) = testing::run_secure(Layer::Conv2DLayer(layer), &input).await;
+
+        // Truncating the shares of the products may be off by one unit in the last place
+        testing::assert_close(&output, &expected, 1);
+        assert_eq!(log.count(true, "ConvolutionInteraction"), 1);
+    }
+}
+
+#[test]
+fn test_load_convolution_model() {
+    use ndarray::array;
+
+    use crate::model::Model;
+
+    let json = std::fs::read_to_string("models/convolution.json").unwrap();
+    let model: Model = serde_json::from_str(&json).unwrap();
+
+    // The kernel is 4 on its diagonal, so every output is 4 (x[i][j] + x[i + 1][j + 1])
+    let input = array![[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let expected = array![[[24.0, 32.0], [48.0, 56.0]]]
+        .mapv(Com::from_num)
+        .into_dyn();
+    assert_eq!(model.infer_locally(input).unwrap(), expected);
+}
@@ -0,0 +1,208 @@
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, Array3, Array4, ArrayD, Axis};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    convolution::{
+        check_stride, crop, feature_map_size, into_feature_map, transposed_convolve,
+        transposed_output_size,
+    },
+    message::IO,
+    multiplication_triplet_share::BilinearOperation,
+    offline::OfflineMaterial,
+    split::Split,
+    Com,
+};
+
+fn default_stride() -> usize {
+    1
+}
+
+/// Crops the padding from the output of a transposed convolution and adds the biases.
+fn finish(
+    output: Array3<Com>,
+    biases: &Array1<Com>,
+    padding: usize,
+) -> anyhow::Result<ArrayD<Com>> {
+    let (_, height, width) = output.dim();
+    if 2 * padding >= height || 2 * padding >= width {
+        bail!(
+            "The padding ({}) leaves nothing of the output ({}x{})",
+            padding,
+            height,
+            width
+        );
+    }
+
+    Ok(
+        (crop(&output.view(), padding) + &biases.view().insert_axis(Axis(1)).insert_axis(Axis(2)))
+            .into_dyn(),
+    )
+}
+
+/// A transposed convolution, which upsamples a (channels, height, width) feature map to
+/// ((height - 1) · stride + kernel height - 2 · padding, ...), like PyTorch's ConvTranspose2d.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConvTranspose2DLayer {
+    /// (input channels, output channels, kernel height, kernel width)
+    kernels: Array4<Com>,
+    /// One bias per output channel
+    biases: Array1<Com>,
+    #[serde(default = "default_stride")]
+    stride: usize,
+    /// Removed from every side of the output
+    #[serde(default)]
+    padding: usize,
+}
+
+impl ConvTranspose2DLayer {
+    pub fn new(kernels: Array4<Com>, biases: Array1<Com>, stride: usize, padding: usize) -> Self {
+        ConvTranspose2DLayer {
+            kernels,
+            biases,
+            stride,
+            padding,
+        }
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        check_stride(self.stride)?;
+        let input_channels = self.kernels.dim().0;
+        let input = into_feature_map(input, input_channels, (1, 1), 0)?;
+
+        let output = transposed_convolve(&input.view(), &self.kernels.view(), self.stride);
+        finish(output, &self.biases, self.padding)
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let (input_channels, output_channels, kernel_height, kernel_width) = self.kernels.dim();
+        super::check_biases(self.biases.len(), output_channels)?;
+        check_stride(self.stride)?;
+        let (_, height, width) = feature_map_size(input_shape, Some(input_channels), (1, 1), 0)?;
+
+        let (height, width) =
+            transposed_output_size((height, width), (kernel_height, kernel_width), self.stride);
+        if 2 * self.padding >= height || 2 * self.padding >= width {
+            bail!(
+                "The padding ({}) leaves nothing of the output ({}x{})",
+                self.padding,
+                height,
+                width
+            );
+        }
+        Ok(vec![
+            output_channels,
+            height - 2 * self.padding,
+            width - 2 * self.padding,
+        ])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConvTranspose2DLayerShare {
+    pub(self) kernels_share: Array4<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) stride: usize,
+    pub(self) padding: usize,
+}
+
+impl ConvTranspose2DLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        check_stride(self.stride)?;
+        let input_channels = self.kernels_share.dim().0;
+        let input_share = into_feature_map(input_share, input_channels, (1, 1), 0)?;
+
+        let mt = material.take_convolution_triplet(
+            BilinearOperation::TransposedConvolution {
+                stride: self.stride,
+            },
+            input_share.dim(),
+            self.kernels_share.dim(),
+        )?;
+        let output_share = mt
+            .transposed_convolution::<PARTY>(
+                &input_share,
+                &self.kernels_share,
+                self.stride,
+                (sender, receiver),
+            )
+            .await
+            .context("Failed to convolve the activations with the kernels")?;
+
+        // Cropping and adding the biases are local
+        finish(output_share, &self.biases_share, self.padding)
+    }
+}
+
+impl Split for ConvTranspose2DLayer {
+    type Splitted = ConvTranspose2DLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let kernels_shares = self.kernels.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            ConvTranspose2DLayerShare {
+                kernels_share: kernels_shares.0,
+                biases_share: biases_shares.0,
+                stride: self.stride,
+                padding: self.padding,
+            },
+            ConvTranspose2DLayerShare {
+                kernels_share: kernels_shares.1,
+                biases_share: biases_shares.1,
+                stride: self.stride,
+                padding: self.padding,
+            },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_conv_transpose_matches_pytorch() {
+    use ndarray::array;
+
+    use crate::{layer::Layer, testing};
+
+    let input = array![[[1.0, 2.0], [3.0, 4.0]]]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let kernels = array![[[[1.0, 1.0], [1.0, 1.0]], [[1.0, 0.0], [0.0, -1.0]]]].mapv(Com::from_num);
+    let biases = array![0.5, 0.0].mapv(Com::from_num);
+
+    // F.conv_transpose2d(x, k, b, stride, padding) in PyTorch
+    let layer = ConvTranspose2DLayer::new(kernels.clone(), biases.clone(), 1, 0);
+    let expected = array![
+        [[1.5, 3.5, 2.5], [4.5, 10.5, 6.5], [3.5, 7.5, 4.5]],
+        [[1.0, 2.0, 0.0], [3.0, 3.0, -2.0], [0.0, -3.0, -4.0]]
+    ];
+    assert_eq!(
+        layer.infer_locally(input.clone()).unwrap(),
+        expected.mapv(Com::from_num).into_dyn()
+    );
+
+    let padded = ConvTranspose2DLayer::new(kernels.clone(), biases.clone(), 1, 1);
+    assert_eq!(
+        padded.infer_locally(input.clone()).unwrap(),
+        array![[[10.5]], [[3.0]]].mapv(Com::from_num).into_dyn()
+    );
+
+    let strided = ConvTranspose2DLayer::new(kernels, biases, 2, 0);
+    let output = strided.infer_locally(input.clone()).unwrap();
+    assert_eq!(output.shape(), [2, 4, 4]);
+    assert_eq!(output[[0, 3, 3]], Com::from_num(4.5));
+    assert_eq!(output[[1, 1, 1]], Com::from_num(-1.0));
+
+    for layer in [layer, padded, strided] {
+        let expected = layer.infer_locally(input.clone()).unwrap();
+        let (output, _) = testing::run_secure(Layer::ConvTranspose2DLayer(layer), &input).await;
+        // Truncating the shares of the products may be off by one unit in the last place
+        testing::assert_close(&output, &expected, 1);
+    }
+}
@@ -1,21 +1,74 @@
-use anyhow::Context as _;
-use ndarray::{Array1, Array2, Ix1, Ix2};
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, Array2, ArrayD, Zip};
 use ring::rand::SecureRandom;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    message::IO, multiplication_triplet_share::MultiplicationTripletShare, split::Split, Com,
+    com,
+    message::{Message, PrivateDenseLayerInteraction, IO},
+    multiplication_triplet_share::{
+        decrypt_product, encrypt_operand, multiply_encrypted, ring_dot,
+    },
+    offline::OfflineMaterial,
+    paillier::{PaillierPrivateKey, PaillierPublicKey},
+    shape_mismatch_error::ShapeMismatchError,
+    split::Split,
+    tensor, truncation,
+    unexpected_message_error::UnexpectedMessageError,
+    Com,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DenseLayer {
     weights: Array2<Com>,
     biases: Array1<Com>,
 }
 
 impl DenseLayer {
-    pub fn infer_locally(&self, input: Array1<Com>) -> Array1<Com> {
-        &input.dot(&self.weights) + &self.biases
+    pub fn new(weights: Array2<Com>, biases: Array1<Com>) -> Self {
+        DenseLayer { weights, biases }
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let input = tensor::into_vector(input, self.input_size())?;
+        Ok((&input.dot(&self.weights) + &self.biases).into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        super::check_biases(self.biases.len(), self.output_size())?;
+        ShapeMismatchError::check(input_shape, &[Some(self.input_size())])?;
+        Ok(vec![self.output_size()])
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.weights.nrows()
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.weights.ncols()
+    }
+
+    /// Folds a following affine transformation, e.g. a batch normalisation, into the weights and
+    /// the biases.
+    pub(crate) fn fold(&mut self, scale: &Array1<f64>, shift: &Array1<f64>) {
+        for (mut column, &scale) in self.weights.columns_mut().into_iter().zip(scale) {
+            column.mapv_inplace(|w| Com::from_num(w.0.to_num::<f64>() * scale));
+        }
+        self.biases = Zip::from(&self.biases)
+            .and(scale)
+            .and(shift)
+            .map_collect(|b, scale, shift| Com::from_num(b.0.to_num::<f64>() * scale + shift));
+    }
+
+    /// Splits the layer s.t. the server keeps the weights and the client only gets the dimensions.
+    pub fn split_private_weights(&self) -> (PrivateDenseLayerShare, PrivateDenseLayerShare) {
+        (
+            PrivateDenseLayerShare::Server(self.clone()),
+            PrivateDenseLayerShare::Client {
+                input_size: self.input_size(),
+                output_size: self.output_size(),
+            },
+        )
     }
 }
 
@@ -28,20 +81,24 @@ pub struct DenseLayerShare {
 impl DenseLayerShare {
     pub async fn infer<const PARTY: bool>(
         &self,
-        input_share: Array1<Com>,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
         (sender, receiver): IO<'_>,
-    ) -> anyhow::Result<Array1<Com>> {
-        // let mt =
-        //     MultiplicationTripletShare::<Ix1, Ix2>::new(input_share.len(), self.biases_share.len());
-        let mt = MultiplicationTripletShare::<Ix1, Ix2>::new(
-            self.weights_share.nrows(),
-            self.biases_share.len(),
-        );
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let input_share = tensor::into_vector(input_share, self.weights_share.nrows())?;
+
+        let (k, m) = self.dim();
+        let mt = material.take_dot_product_triplet(k, m)?;
         let product = mt
             .dot_product::<PARTY>(&input_share, &self.weights_share, (sender, receiver))
             .await
             .context("Failed to multiply the activations by the weights")?;
-        Ok(product + &self.biases_share)
+        Ok((product + &self.biases_share).into_dyn())
+    }
+
+    /// The (input size, output size) of the layer.
+    pub(crate) fn dim(&self) -> (usize, usize) {
+        self.weights_share.dim()
     }
 }
 
@@ -64,3 +121,222 @@ impl Split for DenseLayer {
         )
     }
 }
+
+/// A share of a dense layer whose weights never leave the server.
+///
+/// Only the activations are secret-shared. The server keeps the plaintext layer and the client
+/// only learns its dimensions. The multiplication uses randomness correlated to the server's
+/// fixed weights, as in Delphi (Mishra et al. Delphi: A Cryptographic Inference Service for
+/// Neural Networks. USENIX Security 2020.): in the offline phase, the client samples a mask r and
+/// the parties get shares of rW using Paillier, so the server never learns r.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "party")]
+pub enum PrivateDenseLayerShare {
+    Server(DenseLayer),
+    Client {
+        input_size: usize,
+        output_size: usize,
+    },
+}
+
+/// A party's share of the randomness of a `PrivateDenseLayerShare`.
+pub(crate) struct PrivateDenseCorrelation {
+    /// The mask of the input, which only the client knows
+    pub(crate) r: Option<Array1<Com>>,
+    /// A share of rW in the ring, i.e. not truncated
+    pub(crate) rw_share: Array1<Com>,
+}
+
+impl PrivateDenseLayerShare {
+    /// The (input size, output size) of the layer.
+    pub(crate) fn dim(&self) -> (usize, usize) {
+        match self {
+            PrivateDenseLayerShare::Server(dense_layer) => dense_layer.weights.dim(),
+            PrivateDenseLayerShare::Client {
+                input_size,
+                output_size,
+            } => (*input_size, *output_size),
+        }
+    }
+
+    /// Generates our share of the randomness of the layer, in the offline phase.
+    ///
+    /// The client encrypts r under its key. The server multiplies it by the weights
+    /// homomorphically and masks the product with s, so the client gets rW + s and the server
+    /// keeps -s.
+    ///
+    /// # Parameters
+    /// - `(our_key, their_key)`: our Paillier key pair and the public key of the other party
+    /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+    /// - `rng`: A secure random number generator.
+    pub(crate) async fn correlate(
+        &self,
+        (our_key, their_key): (&PaillierPrivateKey, &PaillierPublicKey),
+        (sender, receiver): IO<'_>,
+        rng: &dyn SecureRandom,
+    ) -> anyhow::Result<PrivateDenseCorrelation> {
+        match self {
+            PrivateDenseLayerShare::Server(dense_layer) => {
+                let encrypted_r =
+                    if let Some(Message::EncryptedOperand(contents)) = receiver.recv().await {
+                        contents
+                    } else {
+                        bail!(UnexpectedMessageError {});
+                    };
+
+                let (encrypted_product, rw_share) =
+                    multiply_encrypted(&encrypted_r, &dense_layer.weights, their_key, rng)?;
+                sender
+                    .send(Message::EncryptedProduct(encrypted_product))
+                    .await?;
+
+                Ok(PrivateDenseCorrelation {
+                    r: None,
+                    rw_share: rw_share.mapv(|x| Com::from_bits(x as i32)),
+                })
+            }
+            PrivateDenseLayerShare::Client {
+                input_size,
+                output_size,
+            } => {
+                let r = com::sample(*input_size, rng);
+                sender
+                    .send(Message::EncryptedOperand(encrypt_operand(
+                        &r,
+                        &our_key.public_key,
+                        rng,
+                    )))
+                    .await?;
+
+                let encrypted_product =
+                    if let Some(Message::EncryptedProduct(contents)) = receiver.recv().await {
+                        contents
+                    } else {
+                        bail!(UnexpectedMessageError {});
+                    };
+                let rw_share = decrypt_product(&encrypted_product, *output_size, our_key)?;
+
+                Ok(PrivateDenseCorrelation {
+                    r: Some(r),
+                    rw_share: rw_share.mapv(|x| Com::from_bits(x as i32)),
+                })
+            }
+        }
+    }
+
+    pub async fn infer(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (input_size, output_size) = self.dim();
+        let input_share = tensor::into_vector(input_share, input_size)?;
+        let correlation = material.take_private_dense_correlation(input_size, output_size)?;
+
+        match self {
+            PrivateDenseLayerShare::Server(dense_layer) => {
+                let interaction = if let Some(Message::PrivateDenseLayerInteraction(contents)) =
+                    receiver.recv().await
+                {
+                    contents
+                } else {
+                    bail!(UnexpectedMessageError {});
+                };
+                ShapeMismatchError::check(
+                    interaction.masked_input_share.shape(),
+                    &[Some(input_size)],
+                )?;
+
+                // (x - r)W - s, while the client holds rW + s
+                let masked_input = input_share + &interaction.masked_input_share;
+                let product_share =
+                    ring_dot(&masked_input, &dense_layer.weights) + &correlation.rw_share;
+                Ok(
+                    (truncation::truncate::<true, _>(&product_share, com::frac_bits())
+                        + &dense_layer.biases)
+                        .into_dyn(),
+                )
+            }
+            PrivateDenseLayerShare::Client { .. } => {
+                let r = correlation
+                    .r
+                    .context("The client's correlation of a dense layer lacks the mask")?;
+
+                // Send our input share masked by r, the server completes the rest
+                sender
+                    .send(Message::PrivateDenseLayerInteraction(
+                        PrivateDenseLayerInteraction {
+                            masked_input_share: input_share - &r,
+                        },
+                    ))
+                    .await
+                    .context("Failed to send the masked input share")?;
+
+                Ok(
+                    truncation::truncate::<false, _>(&correlation.rw_share, com::frac_bits())
+                        .into_dyn(),
+                )
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_private_dense_layer_hides_the_input() {
+    use crate::{layer::LayerShare, testing};
+    use ndarray::array;
+
+    let layer = DenseLayer::new(
+        array![[1.0, -2.0], [0.5, 3.0]].mapv(Com::from_num),
+        array![0.25, 0.0].mapv(Com::from_num),
+    );
+    let input = array![2.0, -1.0].mapv(Com::from_num).into_dyn();
+    let expected = layer.infer_locally(input.clone()).unwrap();
+
+    let rng = ring::rand::SystemRandom::new();
+    let input_shares = input.split(&rng);
+
+    let mut masked_inputs = vec![];
+    for _ in 0..2 {
+        let layer_shares = layer.split_private_weights();
+        let (output, log) = testing::run_secure_shares(
+            (
+                LayerShare::PrivateDenseLayerShare(layer_shares.0),
+                LayerShare::PrivateDenseLayerShare(layer_shares.1),
+            ),
+            input_shares.clone(),
+        )
+        .await;
+
+        // Truncating the shares may be off by one unit in the last place
+        testing::assert_close(&output, &expected, 1);
+
+        // The server only sees the client's public key, r encrypted under it and the client's
+        // share masked by r
+        let received = log.sent(false);
+        assert!(received.iter().all(|message| matches!(
+            message,
+            Message::PaillierPublicKey(_)
+                | Message::EncryptedOperand(_)
+                | Message::PrivateDenseLayerInteraction(_)
+        )));
+        let masked_input = received
+            .into_iter()
+            .find_map(|message| match message {
+                Message::PrivateDenseLayerInteraction(interaction) => {
+                    Some(interaction.masked_input_share)
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(masked_input
+            .iter()
+            .zip(&input_shares.1)
+            .all(|(masked, share)| masked != share));
+        masked_inputs.push(masked_input);
+    }
+
+    // r is fresh, so the server's view of the same input differs between inferences
+    assert_ne!(masked_inputs[0], masked_inputs[1]);
+}
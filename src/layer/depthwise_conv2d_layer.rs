@@ -0,0 +1,363 @@
+use anyhow::Context as _;
+use ndarray::{Array1, Array3, Array4, ArrayD, Axis};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    convolution::{
+        check_stride, convolve, depthwise_convolve, feature_map_size, into_feature_map,
+        output_size, pad,
+    },
+    message::IO,
+    multiplication_triplet_share::BilinearOperation,
+    offline::OfflineMaterial,
+    shape_mismatch_error::ShapeMismatchError,
+    split::Split,
+    Com,
+};
+
+fn default_stride() -> usize {
+    1
+}
+
+/// Adds one bias per channel of a (channels, height, width) feature map.
+fn add_biases(x: Array3<Com>, biases: &Array1<Com>) -> Array3<Com> {
+    x + &biases.view().insert_axis(Axis(1)).insert_axis(Axis(2))
+}
+
+/// The shape of the output of a depthwise convolution, i.e. (channels · multiplier, height, width).
+fn depthwise_output_shape(
+    input_shape: &[usize],
+    kernels: &Array4<Com>,
+    stride: usize,
+    padding: usize,
+) -> anyhow::Result<Vec<usize>> {
+    let (channels, multiplier, kernel_height, kernel_width) = kernels.dim();
+    check_stride(stride)?;
+    let (_, height, width) = feature_map_size(
+        input_shape,
+        Some(channels),
+        (kernel_height, kernel_width),
+        padding,
+    )?;
+
+    let (output_height, output_width) = output_size(
+        (height + 2 * padding, width + 2 * padding),
+        (kernel_height, kernel_width),
+        stride,
+    );
+    Ok(vec![channels * multiplier, output_height, output_width])
+}
+
+/// Convolves every input channel with its own kernels only.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DepthwiseConv2DLayer {
+    /// (input channels, depth multiplier, kernel height, kernel width)
+    kernels: Array4<Com>,
+    /// One bias per output channel, i.e. input channels · depth multiplier
+    biases: Array1<Com>,
+    #[serde(default = "default_stride")]
+    stride: usize,
+    #[serde(default)]
+    padding: usize,
+}
+
+impl DepthwiseConv2DLayer {
+    pub fn new(kernels: Array4<Com>, biases: Array1<Com>, stride: usize, padding: usize) -> Self {
+        DepthwiseConv2DLayer {
+            kernels,
+            biases,
+            stride,
+            padding,
+        }
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (channels, _, kernel_height, kernel_width) = self.kernels.dim();
+        let input = into_feature_map(input, channels, (kernel_height, kernel_width), self.padding)?;
+
+        let output = depthwise_convolve(
+            &pad(&input.view(), self.padding).view(),
+            &self.kernels.view(),
+            self.stride,
+        );
+
+        Ok(add_biases(output, &self.biases).into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let output_shape =
+            depthwise_output_shape(input_shape, &self.kernels, self.stride, self.padding)?;
+        super::check_biases(self.biases.len(), output_shape[0])?;
+        Ok(output_shape)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DepthwiseConv2DLayerShare {
+    pub(self) kernels_share: Array4<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) stride: usize,
+    pub(self) padding: usize,
+}
+
+impl DepthwiseConv2DLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let output_share = depthwise_convolution::<PARTY>(
+            input_share,
+            &self.kernels_share,
+            self.stride,
+            self.padding,
+            material,
+            (sender, receiver),
+        )
+        .await?;
+
+        Ok(add_biases(output_share, &self.biases_share).into_dyn())
+    }
+}
+
+/// The secure depthwise convolution shared by `DepthwiseConv2DLayerShare` and
+/// `SeparableConv2DLayerShare`, without biases.
+async fn depthwise_convolution<const PARTY: bool>(
+    input_share: ArrayD<Com>,
+    kernels_share: &Array4<Com>,
+    stride: usize,
+    padding: usize,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array3<Com>> {
+    let (channels, _, kernel_height, kernel_width) = kernels_share.dim();
+    let input_share = into_feature_map(
+        input_share,
+        channels,
+        (kernel_height, kernel_width),
+        padding,
+    )?;
+    let padded_input_share = pad(&input_share.view(), padding);
+
+    let mt = material.take_convolution_triplet(
+        BilinearOperation::DepthwiseConvolution { stride },
+        padded_input_share.dim(),
+        kernels_share.dim(),
+    )?;
+    mt.depthwise_convolution::<PARTY>(
+        &padded_input_share,
+        kernels_share,
+        stride,
+        (sender, receiver),
+    )
+    .await
+    .context("Failed to convolve the channels with their kernels")
+}
+
+impl Split for DepthwiseConv2DLayer {
+    type Splitted = DepthwiseConv2DLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let kernels_shares = self.kernels.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            DepthwiseConv2DLayerShare {
+                kernels_share: kernels_shares.0,
+                biases_share: biases_shares.0,
+                stride: self.stride,
+                padding: self.padding,
+            },
+            DepthwiseConv2DLayerShare {
+                kernels_share: kernels_shares.1,
+                biases_share: biases_shares.1,
+                stride: self.stride,
+                padding: self.padding,
+            },
+        )
+    }
+}
+
+/// A depthwise convolution followed by a pointwise (1x1) convolution which mixes the channels.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeparableConv2DLayer {
+    /// (input channels, depth multiplier, kernel height, kernel width)
+    depthwise_kernels: Array4<Com>,
+    /// (output channels, input channels · depth multiplier, 1, 1)
+    pointwise_kernels: Array4<Com>,
+    /// One bias per output channel
+    biases: Array1<Com>,
+    #[serde(default = "default_stride")]
+    stride: usize,
+    #[serde(default)]
+    padding: usize,
+}
+
+impl SeparableConv2DLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (channels, _, kernel_height, kernel_width) = self.depthwise_kernels.dim();
+        let input = into_feature_map(input, channels, (kernel_height, kernel_width), self.padding)?;
+
+        let depthwise_output = depthwise_convolve(
+            &pad(&input.view(), self.padding).view(),
+            &self.depthwise_kernels.view(),
+            self.stride,
+        );
+        let output = convolve(&depthwise_output.view(), &self.pointwise_kernels.view(), 1);
+
+        Ok(add_biases(output, &self.biases).into_dyn())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let mut output_shape = depthwise_output_shape(
+            input_shape,
+            &self.depthwise_kernels,
+            self.stride,
+            self.padding,
+        )?;
+        let output_channels = self.pointwise_kernels.dim().0;
+        ShapeMismatchError::check(
+            self.pointwise_kernels.shape(),
+            &[None, Some(output_shape[0]), Some(1), Some(1)],
+        )
+        .context("The pointwise kernels do not fit the depthwise convolution")?;
+        super::check_biases(self.biases.len(), output_channels)?;
+
+        output_shape[0] = output_channels;
+        Ok(output_shape)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SeparableConv2DLayerShare {
+    pub(self) depthwise_kernels_share: Array4<Com>,
+    pub(self) pointwise_kernels_share: Array4<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) stride: usize,
+    pub(self) padding: usize,
+}
+
+impl SeparableConv2DLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let depthwise_output_share = depthwise_convolution::<PARTY>(
+            input_share,
+            &self.depthwise_kernels_share,
+            self.stride,
+            self.padding,
+            material,
+            (sender, receiver),
+        )
+        .await?;
+
+        let mt = material.take_convolution_triplet(
+            BilinearOperation::Convolution { stride: 1 },
+            depthwise_output_share.dim(),
+            self.pointwise_kernels_share.dim(),
+        )?;
+        let output_share = mt
+            .convolution::<PARTY>(
+                &depthwise_output_share,
+                &self.pointwise_kernels_share,
+                1,
+                (sender, receiver),
+            )
+            .await
+            .context("Failed to mix the channels")?;
+
+        Ok(add_biases(output_share, &self.biases_share).into_dyn())
+    }
+}
+
+impl Split for SeparableConv2DLayer {
+    type Splitted = SeparableConv2DLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let depthwise_kernels_shares = self.depthwise_kernels.split(rng);
+        let pointwise_kernels_shares = self.pointwise_kernels.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            SeparableConv2DLayerShare {
+                depthwise_kernels_share: depthwise_kernels_shares.0,
+                pointwise_kernels_share: pointwise_kernels_shares.0,
+                biases_share: biases_shares.0,
+                stride: self.stride,
+                padding: self.padding,
+            },
+            SeparableConv2DLayerShare {
+                depthwise_kernels_share: depthwise_kernels_shares.1,
+                pointwise_kernels_share: pointwise_kernels_shares.1,
+                biases_share: biases_shares.1,
+                stride: self.stride,
+                padding: self.padding,
+            },
+        )
+    }
+}
+
+#[test]
+fn test_depthwise_channel_order_matches_keras() {
+    use ndarray::{array, Array3};
+
+    // Keras' DepthwiseConv2D outputs input channel c convolved with kernel m at c · multiplier + m
+    let kernels = array![[[[1.0]], [[2.0]]], [[[3.0]], [[4.0]]]].mapv(Com::from_num);
+    let layer = DepthwiseConv2DLayer::new(kernels, Array1::zeros(4), 1, 0);
+    let input = Array3::from_shape_fn((2, 1, 1), |(c, _, _)| Com::from_num([1, 10][c])).into_dyn();
+
+    let output = layer.infer_locally(input).unwrap();
+    assert_eq!(
+        output.iter().copied().collect::<Vec<_>>(),
+        [1.0, 2.0, 30.0, 40.0].map(Com::from_num)
+    );
+}
+
+#[tokio::test]
+async fn test_depthwise_and_separable_match_plaintext() {
+    use ndarray::Array3;
+
+    use crate::{layer::Layer, testing};
+
+    // Small values, multiples of 1/4 in [-1, 1], and integral kernels, so the plaintext products
+    // are exact
+    let value = |i: usize| Com::from_num((i * 7 % 9) as f32 * 0.25 - 1.0);
+    let integer = |i: usize| Com::from_num((i * 7 % 5) as i32 - 2);
+    let sign = |i: usize| Com::from_num((i * 7 % 3) as i32 - 1);
+    let input = Array3::from_shape_fn((2, 5, 5), |(c, i, j)| value(c * 25 + i * 5 + j)).into_dyn();
+    let depthwise_kernels = Array4::from_shape_fn((2, 2, 3, 3), |(c, m, i, j)| {
+        integer(c * 18 + m * 9 + i * 3 + j + 1)
+    });
+    let biases = Array1::from_shape_fn(4, value);
+
+    for (stride, padding) in [(1, 0), (2, 1)] {
+        let layer =
+            DepthwiseConv2DLayer::new(depthwise_kernels.clone(), biases.clone(), stride, padding);
+        let expected = layer.infer_locally(input.clone()).unwrap();
+        let (output, _) = testing::run_secure(Layer::DepthwiseConv2DLayer(layer), &input).await;
+        // Truncating the shares of the products may be off by one unit in the last place
+        testing::assert_close(&output, &expected, 1);
+
+        let layer = SeparableConv2DLayer {
+            depthwise_kernels: depthwise_kernels.clone(),
+            pointwise_kernels: Array4::from_shape_fn((3, 4, 1, 1), |(o, i, _, _)| sign(o * 4 + i)),
+            biases: Array1::from_shape_fn(3, value),
+            stride,
+            padding,
+        };
+        let expected = layer.infer_locally(input.clone()).unwrap();
+        let (output, _) = testing::run_secure(Layer::SeparableConv2DLayer(layer), &input).await;
+        assert_eq!(
+            output.shape(),
+            [3, expected.shape()[1], expected.shape()[2]]
+        );
+        // The pointwise kernels sum up to four errors of the depthwise convolution, besides their
+        // own
+        testing::assert_close(&output, &expected, 5);
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{bail, Context as _};
+use ndarray::{Array2, ArrayD};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{equality, message::IO, offline::OfflineMaterial, split::Split, tensor, Com};
+
+/// Replaces each input, an index in [0, vocabulary size), by a row of a table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbeddingLayer {
+    /// (vocabulary size, embedding dimension)
+    table: Array2<Com>,
+}
+
+impl EmbeddingLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (vocabulary_size, dimension) = self.table.dim();
+        let shape = output_shape(input.shape(), dimension);
+
+        let mut output = Vec::with_capacity(input.len() * dimension);
+        for index in input.iter() {
+            let row = index.0.to_num::<f64>();
+            if row.fract() != 0.0 || row < 0.0 || row >= vocabulary_size as f64 {
+                bail!(
+                    "The index {} is not in the vocabulary of size {}",
+                    index,
+                    vocabulary_size
+                );
+            }
+            output.extend(self.table.row(row as usize));
+        }
+
+        Ok(ArrayD::from_shape_vec(shape, output).unwrap())
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        Ok(output_shape(input_shape, self.table.ncols()))
+    }
+}
+
+/// Every index is replaced by a vector along a new, last axis.
+fn output_shape(input_shape: &[usize], dimension: usize) -> Vec<usize> {
+    let mut shape = input_shape.to_vec();
+    shape.push(dimension);
+    shape
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmbeddingLayerShare {
+    pub(self) table_share: Array2<Com>,
+}
+
+impl EmbeddingLayerShare {
+    /// Looks up the rows as one-hot vectors times the table, so neither party learns the indices.
+    ///
+    /// Checking the indices would reveal whether they are in the vocabulary, so indices out of it
+    /// are not rejected, unlike in `EmbeddingLayer::infer_locally`. Their one-hot vectors are
+    /// zero, and so are their rows.
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (vocabulary_size, dimension) = self.table_share.dim();
+        let shape = output_shape(input_share.shape(), dimension);
+        let input_share = tensor::flatten(input_share);
+        let n = input_share.len();
+
+        let one_hot_share =
+            equality::one_hot::<PARTY>(&input_share, vocabulary_size, material, (sender, receiver))
+                .await
+                .context("Failed to encode the indices")?
+                .into_shape((n, vocabulary_size))
+                .unwrap();
+
+        // The one-hot vectors are masked by a triplet of the offline phase, like any operand
+        let mt = material.take_matrix_product_triplet(n, vocabulary_size, dimension)?;
+        let output_share = mt
+            .matrix_product::<PARTY>(&one_hot_share, &self.table_share, (sender, receiver))
+            .await
+            .context("Failed to look up the table")?;
+
+        Ok(tensor::unflatten(
+            tensor::flatten(output_share.into_dyn()),
+            &shape,
+        ))
+    }
+}
+
+impl Split for EmbeddingLayer {
+    type Splitted = EmbeddingLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let table_shares = self.table.split(rng);
+
+        (
+            EmbeddingLayerShare {
+                table_share: table_shares.0,
+            },
+            EmbeddingLayerShare {
+                table_share: table_shares.1,
+            },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_embedding_matches_plaintext() {
+    use ndarray::array;
+
+    use crate::{layer::Layer, message::Message, testing};
+
+    let layer = EmbeddingLayer {
+        table: array![[1.0, -2.0], [0.25, 0.0], [-3.5, 4.0]].mapv(Com::from_num),
+    };
+
+    // The index 3 is out of the vocabulary
+    for input in [
+        array![[2.0, 0.0], [1.0, 2.0]],
+        array![[0.0, 3.0], [1.0, 2.0]],
+    ] {
+        let input = input.mapv(Com::from_num).into_dyn();
+        let (output, log) = testing::run_secure(Layer::EmbeddingLayer(layer.clone()), &input).await;
+        assert_eq!(output.shape(), [2, 2, 2]);
+
+        // The exchanged e = one-hot vectors - a does not reveal the one-hot vectors
+        let e_shares = [true, false].map(|party| {
+            log.sent(party)
+                .into_iter()
+                .find_map(|message| match message {
+                    Message::MatrixProductInteraction(interaction) => Some(interaction.e_share),
+                    _ => None,
+                })
+                .unwrap()
+        });
+        let indices: Vec<Com> = input.iter().copied().collect();
+        let one_hot = Array2::from_shape_fn((4, 3), |(i, j)| {
+            if indices[i] == Com::from_num(j as i32) {
+                Com::from_num(1)
+            } else {
+                Com::ZERO
+            }
+        });
+        assert_ne!(&e_shares[0] + &e_shares[1], one_hot);
+
+        match layer.infer_locally(input) {
+            Ok(expected) => assert_eq!(output, expected),
+            // Securely, the row of an index out of the vocabulary is zero
+            Err(_) => {
+                assert_eq!(output[[0, 0, 1]], Com::from_num(-2.0));
+                assert!(output
+                    .slice(ndarray::s![0, 1, ..])
+                    .iter()
+                    .all(|&x| x == Com::ZERO));
+            }
+        }
+    }
+
+    let out_of_vocabulary = array![3.0].mapv(Com::from_num).into_dyn();
+    assert!(layer.infer_locally(out_of_vocabulary).is_err());
+}
@@ -0,0 +1,32 @@
+use crate::{split::Split, tensor, Com};
+use ndarray::ArrayD;
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// Flattens the activations into a vector, e.g. between a convolution and a dense layer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct FlattenLayer {}
+
+impl FlattenLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        Ok(tensor::flatten(input).into_dyn())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FlattenLayerShare {}
+
+impl FlattenLayerShare {
+    pub fn infer(&self, input_share: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        // Reshaping is local
+        Ok(tensor::flatten(input_share).into_dyn())
+    }
+}
+
+impl Split for FlattenLayer {
+    type Splitted = FlattenLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (FlattenLayerShare {}, FlattenLayerShare {})
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{bitxa, message::IO, offline::OfflineMaterial, split::Split, tensor, truncation, Com};
+use anyhow::Context;
+use ndarray::ArrayD;
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use super::relu::drelu;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct LeakyReLULayer {
+    /// The slope for negative inputs, which need not be representable as a `Com`, e.g. the
+    /// default of ONNX, 0.01
+    pub slope: f32,
+}
+
+impl LeakyReLULayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let slope = f64::from(self.slope);
+        Ok(input.mapv(|x| {
+            if x > Com::ZERO {
+                x
+            } else {
+                Com::from_num(x.0.to_num::<f64>() * slope)
+            }
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LeakyReLULayerShare {
+    pub slope: f32,
+}
+
+impl LeakyReLULayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let shape = input_share.shape().to_vec();
+        let input_share = tensor::flatten(input_share);
+
+        let drelu_output_share = drelu::drelu::<PARTY>(&input_share, material, (sender, receiver))
+            .await
+            .context("Failed to evaluate DReLU")?;
+        let key = material.take_bitxa_key(input_share.len())?;
+        let relu_output_share =
+            bitxa::<PARTY>(&input_share, &drelu_output_share, key, (sender, receiver))
+                .await
+                .context("Failed to evaluate BitXA")?;
+
+        // slope · x + (1 - slope) · ReLU(x), where the slope is typically too small for a Com
+        let slope = f64::from(self.slope);
+        let output_share = truncation::scale_precisely::<PARTY, _>(&input_share, slope)
+            + truncation::scale_precisely::<PARTY, _>(&relu_output_share, 1.0 - slope);
+
+        Ok(tensor::unflatten(output_share, &shape))
+    }
+}
+
+impl Split for LeakyReLULayer {
+    type Splitted = LeakyReLULayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            LeakyReLULayerShare { slope: self.slope },
+            LeakyReLULayerShare { slope: self.slope },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_leaky_relu_matches_plaintext() {
+    use crate::{layer::Layer, testing};
+
+    // Integers, so that the products with the slope are exact
+    let input = ndarray::array![-100.0, -8.0, -1.0, 0.0, 1.0, 6.0, 100.0]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let layer = LeakyReLULayer { slope: 0.25 };
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    assert_eq!(
+        expected,
+        ndarray::array![-25.0, -2.0, -0.25, 0.0, 1.0, 6.0, 100.0]
+            .mapv(Com::from_num)
+            .into_dyn()
+    );
+
+    let (output, _) = testing::run_secure(Layer::LeakyReLULayer(layer), &input).await;
+
+    // Each of the two scalings truncates, erring by one unit in the last place
+    testing::assert_close(&output, &expected, 2);
+}
+
+#[tokio::test]
+async fn test_leaky_relu_with_small_slope() {
+    use crate::{layer::Layer, testing};
+
+    // The default slope of ONNX, which is 0 as a Com
+    let input = ndarray::array![-400.0, -100.0, -8.0, 0.0, 1.0, 6.0, 100.0]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let layer = LeakyReLULayer { slope: 0.01 };
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    assert_eq!(
+        expected,
+        ndarray::array![-4.0, -1.0, 0.0, 0.0, 1.0, 6.0, 100.0]
+            .mapv(Com::from_num)
+            .into_dyn()
+    );
+
+    let (output, _) = testing::run_secure(Layer::LeakyReLULayer(layer), &input).await;
+
+    // The slopes are rounded to 12 fractional bits, which errs by less than a unit in the last
+    // place on these inputs, besides the two truncations
+    testing::assert_close(&output, &expected, 2);
+}
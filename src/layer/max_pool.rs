@@ -0,0 +1,104 @@
+use crate::{
+    convolution::{gather_windows, into_feature_map, pooling_output_shape, pooling_window},
+    maximum::maximum,
+    message::IO,
+    offline::OfflineMaterial,
+    split::Split,
+    tensor, Com,
+};
+use ndarray::{ArrayD, Axis};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// Takes the maximum of every (pool size, pool size) window of a (channels, height, width)
+/// feature map.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MaxPool2DLayer {
+    pub pool_size: usize,
+    /// Defaults to the pool size, i.e. non-overlapping windows
+    #[serde(default)]
+    pub stride: Option<usize>,
+}
+
+impl MaxPool2DLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let (pool_size, stride) = pooling_window(self.pool_size, self.stride)?;
+        let channels = input.shape().first().copied().unwrap_or_default();
+        let input = into_feature_map(input, channels, (pool_size, pool_size), 0)?;
+
+        let (windows, (output_height, output_width)) =
+            gather_windows(&input.view(), (pool_size, pool_size), stride);
+        let maxima = windows.map_axis(Axis(1), |window| *window.iter().max().unwrap());
+
+        Ok(tensor::unflatten(
+            maxima,
+            &[channels, output_height, output_width],
+        ))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        pooling_output_shape(input_shape, self.pool_size, self.stride)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaxPool2DLayerShare {
+    pub pool_size: usize,
+    pub stride: Option<usize>,
+}
+
+impl MaxPool2DLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (pool_size, stride) = pooling_window(self.pool_size, self.stride)?;
+        let channels = input_share.shape().first().copied().unwrap_or_default();
+        let input_share = into_feature_map(input_share, channels, (pool_size, pool_size), 0)?;
+
+        // Gathering is local, the windows are compared all at once
+        let (windows_share, (output_height, output_width)) =
+            gather_windows(&input_share.view(), (pool_size, pool_size), stride);
+        let maxima_share = maximum::<PARTY>(windows_share, material, (sender, receiver)).await?;
+
+        Ok(tensor::unflatten(
+            maxima_share,
+            &[channels, output_height, output_width],
+        ))
+    }
+}
+
+impl Split for MaxPool2DLayer {
+    type Splitted = MaxPool2DLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            MaxPool2DLayerShare {
+                pool_size: self.pool_size,
+                stride: self.stride,
+            },
+            MaxPool2DLayerShare {
+                pool_size: self.pool_size,
+                stride: self.stride,
+            },
+        )
+    }
+}
+
+#[test]
+fn test_empty_windows_and_zero_strides_are_rejected() {
+    use crate::model::Model;
+
+    let input = ndarray::Array3::<Com>::zeros((1, 4, 4)).into_dyn();
+    for layer in [
+        r#"{"type": "MaxPool2DLayer", "pool_size": 0}"#,
+        r#"{"type": "MaxPool2DLayer", "pool_size": 2, "stride": 0}"#,
+        r#"{"type": "AvgPool2DLayer", "pool_size": 0}"#,
+        r#"{"type": "AvgPool2DLayer", "pool_size": 2, "stride": 0}"#,
+    ] {
+        let model: Model = serde_json::from_str(&format!(r#"{{"layers": [{}]}}"#, layer)).unwrap();
+        assert!(model.infer_locally(input.clone()).is_err());
+    }
+}
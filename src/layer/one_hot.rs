@@ -0,0 +1,78 @@
+use crate::{equality, message::IO, offline::OfflineMaterial, split::Split, tensor, Com};
+use ndarray::{Array1, ArrayD};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// Encodes each input, an index in [0, classes), as a one-hot vector.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct OneHotLayer {
+    pub classes: usize,
+}
+
+impl OneHotLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let output = Array1::from_iter(input.iter().flat_map(|x| {
+            (0..self.classes).map(move |k| {
+                if *x == Com::from_num(k) {
+                    Com::from_num(1)
+                } else {
+                    Com::ZERO
+                }
+            })
+        }));
+
+        Ok(tensor::unflatten(
+            output,
+            &output_shape(input.shape(), self.classes),
+        ))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        Ok(output_shape(input_shape, self.classes))
+    }
+}
+
+/// Every index is replaced by a vector along a new, last axis.
+fn output_shape(input_shape: &[usize], classes: usize) -> Vec<usize> {
+    let mut shape = input_shape.to_vec();
+    shape.push(classes);
+    shape
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OneHotLayerShare {
+    pub classes: usize,
+}
+
+impl OneHotLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let shape = output_shape(input_share.shape(), self.classes);
+        let input_share = tensor::flatten(input_share);
+
+        let output_share =
+            equality::one_hot::<PARTY>(&input_share, self.classes, material, (sender, receiver))
+                .await?;
+
+        Ok(tensor::unflatten(output_share, &shape))
+    }
+}
+
+impl Split for OneHotLayer {
+    type Splitted = OneHotLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            OneHotLayerShare {
+                classes: self.classes,
+            },
+            OneHotLayerShare {
+                classes: self.classes,
+            },
+        )
+    }
+}
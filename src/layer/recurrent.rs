@@ -0,0 +1,471 @@
+use anyhow::Context as _;
+use ndarray::{s, stack, Array1, Array2, ArrayD, ArrayView1, Axis, Ix2};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    approximation::{hard_sigmoid, hard_sigmoid_locally, hard_tanh, hard_tanh_locally},
+    message::IO,
+    offline::OfflineMaterial,
+    shape_mismatch_error::ShapeMismatchError,
+    split::Split,
+    Com,
+};
+
+/// Converts a tensor into a (sequence length, input size) sequence.
+fn into_sequence(x: ArrayD<Com>, input_size: usize) -> Result<Array2<Com>, ShapeMismatchError> {
+    ShapeMismatchError::check(x.shape(), &[None, Some(input_size)])?;
+    Ok(x.into_dimensionality::<Ix2>().unwrap())
+}
+
+/// Either the hidden state after each step, or only the last one.
+fn into_output(
+    hidden_states: Vec<Array1<Com>>,
+    hidden_size: usize,
+    sequences: bool,
+) -> ArrayD<Com> {
+    if sequences {
+        let views: Vec<ArrayView1<Com>> = hidden_states.iter().map(|h| h.view()).collect();
+        if views.is_empty() {
+            Array2::zeros((0, hidden_size)).into_dyn()
+        } else {
+            stack(Axis(0), &views).unwrap().into_dyn()
+        }
+    } else {
+        hidden_states
+            .last()
+            .cloned()
+            .unwrap_or_else(|| Array1::zeros(hidden_size))
+            .into_dyn()
+    }
+}
+
+/// Checks that the kernels and the biases fit a hidden state of the size of the recurrent kernel,
+/// where `width` is the number of columns, i.e. hidden size times the number of gates.
+fn check_parameters(
+    kernel: &Array2<Com>,
+    recurrent_kernel: &Array2<Com>,
+    biases: &Array1<Com>,
+    width: usize,
+) -> anyhow::Result<()> {
+    ShapeMismatchError::check(kernel.shape(), &[None, Some(width)])
+        .context("The kernel does not fit the hidden state")?;
+    ShapeMismatchError::check(
+        recurrent_kernel.shape(),
+        &[Some(recurrent_kernel.nrows()), Some(width)],
+    )
+    .context("The recurrent kernel does not fit the hidden state")?;
+    super::check_biases(biases.len(), width)
+}
+
+/// The shape of the output of `into_output` for a (sequence length, input size) input.
+fn sequence_output_shape(
+    input_shape: &[usize],
+    input_size: usize,
+    hidden_size: usize,
+    sequences: bool,
+) -> anyhow::Result<Vec<usize>> {
+    ShapeMismatchError::check(input_shape, &[None, Some(input_size)])?;
+    Ok(if sequences {
+        vec![input_shape[0], hidden_size]
+    } else {
+        vec![hidden_size]
+    })
+}
+
+/// An Elman recurrent layer, h' = tanh(x · W + h · U + b), unrolled over the first axis of its
+/// input. The hidden state starts at zero.
+///
+/// tanh is approximated by hard_tanh.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RNNLayer {
+    /// W, (input size, hidden size)
+    kernel: Array2<Com>,
+    /// U, (hidden size, hidden size)
+    recurrent_kernel: Array2<Com>,
+    biases: Array1<Com>,
+    /// Whether to output the hidden state after every step rather than only the last one
+    #[serde(default)]
+    return_sequences: bool,
+}
+
+impl RNNLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let input = into_sequence(input, self.kernel.nrows())?;
+        let hidden_size = self.recurrent_kernel.nrows();
+
+        let mut hidden_states = Vec::with_capacity(input.nrows());
+        let mut h = Array1::zeros(hidden_size);
+        for x in input.rows() {
+            h = (x.dot(&self.kernel) + h.dot(&self.recurrent_kernel) + &self.biases)
+                .mapv(hard_tanh_locally);
+            hidden_states.push(h.clone());
+        }
+
+        Ok(into_output(
+            hidden_states,
+            hidden_size,
+            self.return_sequences,
+        ))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let hidden_size = self.recurrent_kernel.nrows();
+        check_parameters(
+            &self.kernel,
+            &self.recurrent_kernel,
+            &self.biases,
+            hidden_size,
+        )?;
+        sequence_output_shape(
+            input_shape,
+            self.kernel.nrows(),
+            hidden_size,
+            self.return_sequences,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RNNLayerShare {
+    pub(self) kernel_share: Array2<Com>,
+    pub(self) recurrent_kernel_share: Array2<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) return_sequences: bool,
+}
+
+impl RNNLayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let (input_size, hidden_size) = self.kernel_share.dim();
+        let input_share = into_sequence(input_share, input_size)?;
+        let steps = input_share.nrows();
+
+        // The input does not depend on the hidden state, so it is projected for all steps at once
+        let mt = material.take_matrix_product_triplet(steps, input_size, hidden_size)?;
+        let projected_input_share = mt
+            .matrix_product::<PARTY>(&input_share, &self.kernel_share, (sender, receiver))
+            .await
+            .context("Failed to multiply the inputs by the kernel")?
+            + &self.biases_share;
+
+        let mut hidden_states_share = Vec::with_capacity(steps);
+        let mut h_share = Array1::zeros(hidden_size);
+        for t in 0..steps {
+            let mt = material.take_dot_product_triplet(hidden_size, hidden_size)?;
+            let recurrence_share = mt
+                .dot_product::<PARTY>(&h_share, &self.recurrent_kernel_share, (sender, receiver))
+                .await
+                .with_context(|| format!("Failed to multiply the hidden state at step {}", t))?;
+
+            h_share = hard_tanh::<PARTY>(
+                &(recurrence_share + projected_input_share.row(t)),
+                material,
+                (sender, receiver),
+            )
+            .await
+            .with_context(|| format!("Failed to evaluate tanh at step {}", t))?;
+            hidden_states_share.push(h_share.clone());
+        }
+
+        Ok(into_output(
+            hidden_states_share,
+            hidden_size,
+            self.return_sequences,
+        ))
+    }
+}
+
+impl Split for RNNLayer {
+    type Splitted = RNNLayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let kernel_shares = self.kernel.split(rng);
+        let recurrent_kernel_shares = self.recurrent_kernel.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            RNNLayerShare {
+                kernel_share: kernel_shares.0,
+                recurrent_kernel_share: recurrent_kernel_shares.0,
+                biases_share: biases_shares.0,
+                return_sequences: self.return_sequences,
+            },
+            RNNLayerShare {
+                kernel_share: kernel_shares.1,
+                recurrent_kernel_share: recurrent_kernel_shares.1,
+                biases_share: biases_shares.1,
+                return_sequences: self.return_sequences,
+            },
+        )
+    }
+}
+
+/// A gated recurrent unit, unrolled over the first axis of its input. The hidden state starts at
+/// zero.
+///
+/// - z = σ(x · W_z + h · U_z + b_z)
+/// - r = σ(x · W_r + h · U_r + b_r)
+/// - h̃ = tanh(x · W_h + (r ⊙ h) · U_h + b_h)
+/// - h' = (1 - z) ⊙ h + z ⊙ h̃
+///
+/// The weights of the gates are concatenated in the order z, r, h, as Keras does. σ and tanh
+/// are approximated by hard_sigmoid and hard_tanh.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GRULayer {
+    /// (input size, 3 · hidden size)
+    kernel: Array2<Com>,
+    /// (hidden size, 3 · hidden size)
+    recurrent_kernel: Array2<Com>,
+    /// 3 · hidden size
+    biases: Array1<Com>,
+    /// Whether to output the hidden state after every step rather than only the last one
+    #[serde(default)]
+    return_sequences: bool,
+}
+
+impl GRULayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let input = into_sequence(input, self.kernel.nrows())?;
+        let hidden_size = self.recurrent_kernel.nrows();
+        let gates = 2 * hidden_size;
+
+        let mut hidden_states = Vec::with_capacity(input.nrows());
+        let mut h = Array1::zeros(hidden_size);
+        for x in input.rows() {
+            let projected_input = x.dot(&self.kernel) + &self.biases;
+
+            let zr = (&projected_input.slice(s![..gates])
+                + h.dot(&self.recurrent_kernel.slice(s![.., ..gates])))
+            .mapv(hard_sigmoid_locally);
+            let (z, r) = zr.view().split_at(Axis(0), hidden_size);
+
+            let h_candidate = (&projected_input.slice(s![gates..])
+                + (&r * &h).dot(&self.recurrent_kernel.slice(s![.., gates..])))
+            .mapv(hard_tanh_locally);
+
+            h = &h + &z * &(h_candidate - &h);
+            hidden_states.push(h.clone());
+        }
+
+        Ok(into_output(
+            hidden_states,
+            hidden_size,
+            self.return_sequences,
+        ))
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        let hidden_size = self.recurrent_kernel.nrows();
+        check_parameters(
+            &self.kernel,
+            &self.recurrent_kernel,
+            &self.biases,
+            3 * hidden_size,
+        )?;
+        sequence_output_shape(
+            input_shape,
+            self.kernel.nrows(),
+            hidden_size,
+            self.return_sequences,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GRULayerShare {
+    pub(self) kernel_share: Array2<Com>,
+    pub(self) recurrent_kernel_share: Array2<Com>,
+    pub(self) biases_share: Array1<Com>,
+    pub(self) return_sequences: bool,
+}
+
+impl GRULayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let input_size = self.kernel_share.nrows();
+        let hidden_size = self.recurrent_kernel_share.nrows();
+        let input_share = into_sequence(input_share, input_size)?;
+        let steps = input_share.nrows();
+
+        let gates = 2 * hidden_size;
+        let gates_recurrent_kernel_share = self
+            .recurrent_kernel_share
+            .slice(s![.., ..gates])
+            .to_owned();
+        let candidate_recurrent_kernel_share = self
+            .recurrent_kernel_share
+            .slice(s![.., gates..])
+            .to_owned();
+
+        // The input does not depend on the hidden state, so it is projected for all steps at once
+        let mt = material.take_matrix_product_triplet(steps, input_size, 3 * hidden_size)?;
+        let projected_input_share = mt
+            .matrix_product::<PARTY>(&input_share, &self.kernel_share, (sender, receiver))
+            .await
+            .context("Failed to multiply the inputs by the kernel")?
+            + &self.biases_share;
+
+        let mut hidden_states_share = Vec::with_capacity(steps);
+        let mut h_share = Array1::zeros(hidden_size);
+        for t in 0..steps {
+            let projected_input_share = projected_input_share.row(t);
+
+            let mt = material.take_dot_product_triplet(hidden_size, gates)?;
+            let gates_recurrence_share = mt
+                .dot_product::<PARTY>(&h_share, &gates_recurrent_kernel_share, (sender, receiver))
+                .await
+                .with_context(|| format!("Failed to multiply the hidden state at step {}", t))?;
+            let zr_share = hard_sigmoid::<PARTY>(
+                &(gates_recurrence_share + projected_input_share.slice(s![..gates])),
+                material,
+                (sender, receiver),
+            )
+            .await
+            .with_context(|| format!("Failed to evaluate the gates at step {}", t))?;
+            let z_share = zr_share.slice(s![..hidden_size]).to_owned();
+            let r_share = zr_share.slice(s![hidden_size..]).to_owned();
+
+            let mt = material.take_hadamard_triplet(hidden_size)?;
+            let reset_h_share = mt
+                .hadamard_product::<PARTY>(&r_share, &h_share, (sender, receiver))
+                .await
+                .with_context(|| format!("Failed to reset the hidden state at step {}", t))?;
+            let mt = material.take_dot_product_triplet(hidden_size, hidden_size)?;
+            let candidate_recurrence_share = mt
+                .dot_product::<PARTY>(
+                    &reset_h_share,
+                    &candidate_recurrent_kernel_share,
+                    (sender, receiver),
+                )
+                .await
+                .with_context(|| format!("Failed to multiply the hidden state at step {}", t))?;
+            let h_candidate_share = hard_tanh::<PARTY>(
+                &(candidate_recurrence_share + projected_input_share.slice(s![gates..])),
+                material,
+                (sender, receiver),
+            )
+            .await
+            .with_context(|| format!("Failed to evaluate the candidate at step {}", t))?;
+
+            // h + z ⊙ (h̃ - h)
+            let mt = material.take_hadamard_triplet(hidden_size)?;
+            let update_share = mt
+                .hadamard_product::<PARTY>(
+                    &z_share,
+                    &(h_candidate_share - &h_share),
+                    (sender, receiver),
+                )
+                .await
+                .with_context(|| format!("Failed to update the hidden state at step {}", t))?;
+            h_share = h_share + update_share;
+            hidden_states_share.push(h_share.clone());
+        }
+
+        Ok(into_output(
+            hidden_states_share,
+            hidden_size,
+            self.return_sequences,
+        ))
+    }
+}
+
+impl Split for GRULayer {
+    type Splitted = GRULayerShare;
+
+    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        let kernel_shares = self.kernel.split(rng);
+        let recurrent_kernel_shares = self.recurrent_kernel.split(rng);
+        let biases_shares = self.biases.split(rng);
+
+        (
+            GRULayerShare {
+                kernel_share: kernel_shares.0,
+                recurrent_kernel_share: recurrent_kernel_shares.0,
+                biases_share: biases_shares.0,
+                return_sequences: self.return_sequences,
+            },
+            GRULayerShare {
+                kernel_share: kernel_shares.1,
+                recurrent_kernel_share: recurrent_kernel_shares.1,
+                biases_share: biases_shares.1,
+                return_sequences: self.return_sequences,
+            },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_gru_matches_plaintext() {
+    use crate::{layer::Layer, testing};
+    use ndarray::Array;
+
+    let weights = |rows, columns| {
+        Array::from_shape_fn((rows, columns), |(i, j)| {
+            Com::from_num(((i * 7 + j * 3) % 5) as f32 * 0.25 - 0.5)
+        })
+    };
+    // A signed permutation per gate, so the errors of truncation are not amplified
+    let recurrent_kernel = Array::from_shape_fn((2, 6), |(i, j)| match (j % 2 == i, j % 3) {
+        (true, 0) => Com::from_num(-1),
+        (true, _) => Com::from_num(1),
+        _ => Com::ZERO,
+    });
+    let layer = GRULayer {
+        kernel: weights(2, 6),
+        recurrent_kernel,
+        biases: Array1::from_elem(6, Com::from_num(0.25)),
+        return_sequences: true,
+    };
+    let input = weights(3, 2).into_dyn() * Com::from_num(4);
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    let (output, _) = testing::run_secure(Layer::GRULayer(layer), &input).await;
+
+    // Truncating every product errs by one unit in the last place, which the recurrence may
+    // accumulate
+    assert_eq!(output.shape(), [3, 2]);
+    testing::assert_close(&output, &expected, 6);
+}
+
+#[tokio::test]
+async fn test_rnn_matches_plaintext() {
+    use crate::{layer::Layer, testing};
+    use ndarray::Array;
+
+    let kernel = Array::from_shape_fn((2, 3), |(i, j)| {
+        Com::from_num(((i * 5 + j * 3) % 7) as f32 * 0.25 - 0.75)
+    });
+    // A signed permutation, so the errors of truncation are not amplified
+    let recurrent_kernel = Array::from_shape_fn((3, 3), |(i, j)| match (j == (i + 1) % 3, i % 2) {
+        (true, 0) => Com::from_num(1),
+        (true, _) => Com::from_num(-1),
+        _ => Com::ZERO,
+    });
+    let input =
+        Array::from_shape_fn((3, 2), |(i, j)| Com::from_num((i + 2 * j) as i32 - 2)).into_dyn();
+
+    for return_sequences in [true, false] {
+        let layer = RNNLayer {
+            kernel: kernel.clone(),
+            recurrent_kernel: recurrent_kernel.clone(),
+            biases: Array1::from_elem(3, Com::from_num(0.25)),
+            return_sequences,
+        };
+        let expected = layer.infer_locally(input.clone()).unwrap();
+        let (output, log) = testing::run_secure(Layer::RNNLayer(layer), &input).await;
+
+        // Truncating either product errs by one unit in the last place, at every step
+        testing::assert_close(&output, &expected, 6);
+        // One product of the whole input and one of the hidden state per step
+        assert_eq!(log.count(true, "MatrixProductInteraction"), 1);
+        assert_eq!(log.count(true, "DotProductInteraction"), 3);
+    }
+}
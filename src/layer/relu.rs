@@ -1,17 +1,17 @@
-use crate::{bitxa, message::IO, split::Split, Com};
+use crate::{bitxa, message::IO, offline::OfflineMaterial, split::Split, tensor, Com};
 use anyhow::Context;
-use ndarray::Array1;
+use ndarray::ArrayD;
 use ring::rand::SecureRandom;
 use serde::{Deserialize, Serialize};
 
 pub(crate) mod drelu;
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct ReLULayer {}
 
 impl ReLULayer {
-    pub fn infer_locally(&self, input: Array1<Com>) -> Array1<Com> {
-        input.mapv(|x| if x > Com::ZERO { x } else { Com::ZERO })
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        Ok(input.mapv(|x| if x > Com::ZERO { x } else { Com::ZERO }))
     }
 }
 
@@ -21,16 +21,23 @@ pub struct ReLULayerShare {}
 impl ReLULayerShare {
     pub async fn infer<const PARTY: bool>(
         &self,
-        input_share: Array1<Com>,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
         (sender, receiver): IO<'_>,
-        rng: &dyn SecureRandom,
-    ) -> anyhow::Result<Array1<Com>> {
-        let drelu_output_share = drelu::drelu::<PARTY>(&input_share, (sender, receiver), rng)
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let shape = input_share.shape().to_vec();
+        let input_share = tensor::flatten(input_share);
+
+        let drelu_output_share = drelu::drelu::<PARTY>(&input_share, material, (sender, receiver))
             .await
             .context("Failed to evaluate DReLU")?;
-        bitxa::<PARTY>(&input_share, &drelu_output_share, (sender, receiver), rng)
-            .await
-            .context("Failed to evaluate BitXA")
+        let key = material.take_bitxa_key(input_share.len())?;
+        let output_share =
+            bitxa::<PARTY>(&input_share, &drelu_output_share, key, (sender, receiver))
+                .await
+                .context("Failed to evaluate BitXA")?;
+
+        Ok(tensor::unflatten(output_share, &shape))
     }
 }
 
@@ -1,15 +1,15 @@
-use anyhow::{bail, Context};
-use ndarray::Array1;
+use anyhow::Context;
+use ndarray::{s, Array1};
 use ring::rand::SecureRandom;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     bit,
-    message::{Message, SignedComparisonKeys, IO},
+    message::{DDCFKey, SignedComparisonKeys, IO},
+    offline::OfflineMaterial,
     reconstruct::{Reconstruct, ReconstructOnline},
     signed_comparison::generate_signed_comparison_keys,
     split::Split as _,
-    unexpected_message_error::UnexpectedMessageError,
     Com,
 };
 
@@ -28,7 +28,10 @@ impl Reconstruct for DReLUInteraction {
 
 impl ReconstructOnline for DReLUInteraction {}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The preprocessing material of DReLU, which does not depend on the inputs.
+///
+/// Every element is independent, so a key for n elements may be cut into keys for fewer.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DReLUKey {
     pub r_in_1_share: Array1<Com>,
     pub r_in_2: Array1<Com>,
@@ -37,57 +40,91 @@ pub struct DReLUKey {
     // NOTE maybe the r_out shares from SignedComparisonKeys can be re-used for r_out_share
 }
 
-pub async fn drelu<const PARTY: bool>(
-    x_share: &Array1<Com>,
-    (sender, receiver): IO<'_>,
-    rng: &dyn SecureRandom,
-) -> anyhow::Result<Array1<bool>> {
-    // Deal/receive DReLU keys
-    // TODO technically, the signed comparison keys should also be here
-    // but actually maybe it's better to do it with promises and such
-    let key = if PARTY {
-        // Sample random key
-        // TODO use RandomConstructible
-        // BUG This destroys the results, they are consistent when I use zeros
-        let r_in_1 = Array1::<Com>::zeros(x_share.len());
-        let r_in_2 = Array1::<Com>::zeros(x_share.len());
-        // let r_in_1 = com::sample(x_share.len(), rng);
-        // let r_in_2 = com::sample(x_share.len(), rng);
-        // let r_in_1 = com::sample(x_share.len(), rng);
-        // let r_in_2 = com::sample(x_share.len(), rng);
-        let r_out = bit::sample(x_share.len(), rng);
-
-        let r_in_1_shares = r_in_1.split(rng);
-        let r_out_shares = r_out.split(rng);
-
-        let signed_comparison_keys =
-            generate_signed_comparison_keys(r_in_1, r_in_2.clone(), r_out, rng);
-
-        let our_key = DReLUKey {
+impl DReLUKey {
+    /// A key whose masks are all 0, which is only good for counting how many elements are needed.
+    pub(crate) fn zeros(n: usize) -> Self {
+        DReLUKey {
+            r_in_1_share: Array1::zeros(n),
+            r_in_2: Array1::zeros(n),
+            r_out_share: Array1::from_elem(n, false),
+            signed_comparison_key: SignedComparisonKeys {
+                ddcf_keys: DDCFKey {
+                    alpha: Array1::zeros(n),
+                    invert: Array1::from_elem(n, false),
+                },
+                r_shares: Array1::from_elem(n, false),
+            },
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.r_in_2.len()
+    }
+
+    /// Cuts out the key of elements `start..end`.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> Self {
+        let ddcf_keys = &self.signed_comparison_key.ddcf_keys;
+        DReLUKey {
+            r_in_1_share: self.r_in_1_share.slice(s![start..end]).to_owned(),
+            r_in_2: self.r_in_2.slice(s![start..end]).to_owned(),
+            r_out_share: self.r_out_share.slice(s![start..end]).to_owned(),
+            signed_comparison_key: SignedComparisonKeys {
+                ddcf_keys: DDCFKey {
+                    alpha: ddcf_keys.alpha.slice(s![start..end]).to_owned(),
+                    invert: ddcf_keys.invert.slice(s![start..end]).to_owned(),
+                },
+                r_shares: self
+                    .signed_comparison_key
+                    .r_shares
+                    .slice(s![start..end])
+                    .to_owned(),
+            },
+        }
+    }
+}
+
+/// Deals the keys of `n` elements, which the server does in the offline phase.
+pub(crate) fn generate_drelu_keys(n: usize, rng: &dyn SecureRandom) -> (DReLUKey, DReLUKey) {
+    // TODO use RandomConstructible
+    // BUG This destroys the results, they are consistent when I use zeros
+    let r_in_1 = Array1::<Com>::zeros(n);
+    let r_in_2 = Array1::<Com>::zeros(n);
+    // let r_in_1 = com::sample(n, rng);
+    // let r_in_2 = com::sample(n, rng);
+    let r_out = bit::sample(n, rng);
+
+    let r_in_1_shares = r_in_1.split(rng);
+    let r_out_shares = r_out.split(rng);
+
+    let signed_comparison_keys =
+        generate_signed_comparison_keys(r_in_1, r_in_2.clone(), r_out, rng);
+
+    (
+        DReLUKey {
             r_in_1_share: r_in_1_shares.0,
             r_in_2: r_in_2.clone(),
             r_out_share: r_out_shares.0,
             signed_comparison_key: signed_comparison_keys.0,
-        };
-        let their_key = DReLUKey {
+        },
+        DReLUKey {
             r_in_1_share: r_in_1_shares.1,
             r_in_2,
             r_out_share: r_out_shares.1,
             signed_comparison_key: signed_comparison_keys.1,
-        };
-
-        // Send one key to the other party
-        sender.send(Message::DReLUKey(their_key)).await?;
-
-        our_key
-    } else {
-        // Wait for the adversary to send us a key
-        if let Some(Message::DReLUKey(our_key)) = receiver.recv().await {
-            our_key
-        } else {
-            bail!(UnexpectedMessageError {});
-        }
-    };
+        },
+    )
+}
+
+/// Whether each element is non-negative, as a boolean share.
+///
+/// The keys are dealt in the offline phase, so the only online round is the reconstruction of
+/// the masked input.
+pub async fn drelu<const PARTY: bool>(
+    x_share: &Array1<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<bool>> {
+    let key = material.take_drelu_key(x_share.len())?;
 
     // NOTE the online stage starts here
     let masked_x_share = x_share + key.r_in_1_share;
@@ -0,0 +1,115 @@
+use crate::{bitxa, message::IO, offline::OfflineMaterial, split::Split, tensor, Com};
+use anyhow::Context;
+use ndarray::{concatenate, s, Array1, ArrayD, Axis};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+use super::relu::drelu;
+
+fn default_cap() -> f32 {
+    6.0
+}
+
+/// A ReLU clipped from above, i.e. min(max(x, 0), cap).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ReLU6Layer {
+    #[serde(default = "default_cap")]
+    pub cap: f32,
+}
+
+impl ReLU6Layer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let cap = Com::from_num(self.cap);
+        Ok(input.mapv(|x| x.clamp(Com::ZERO, cap)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReLU6LayerShare {
+    pub cap: f32,
+}
+
+impl ReLU6LayerShare {
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let shape = input_share.shape().to_vec();
+        let input_share = tensor::flatten(input_share);
+
+        let output_share = clip::<PARTY>(
+            &input_share,
+            Com::from_num(self.cap),
+            material,
+            (sender, receiver),
+        )
+        .await?;
+
+        Ok(tensor::unflatten(output_share, &shape))
+    }
+}
+
+/// Computes min(max(x, 0), cap) as ReLU(x) - ReLU(x - cap).
+///
+/// Both comparisons are batched, so this takes as many rounds as a single ReLU.
+pub(crate) async fn clip<const PARTY: bool>(
+    x_share: &Array1<Com>,
+    cap: Com,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<Com>> {
+    let n = x_share.len();
+
+    // Only one party should subtract the (public) cap
+    let shifted_x_share = if PARTY {
+        x_share - cap
+    } else {
+        x_share.clone()
+    };
+    let batch_share = concatenate![Axis(0), x_share, shifted_x_share];
+
+    let drelu_output_share = drelu::drelu::<PARTY>(&batch_share, material, (sender, receiver))
+        .await
+        .context("Failed to evaluate DReLU")?;
+    let key = material.take_bitxa_key(batch_share.len())?;
+    let relu_output_share =
+        bitxa::<PARTY>(&batch_share, &drelu_output_share, key, (sender, receiver))
+            .await
+            .context("Failed to evaluate BitXA")?;
+
+    Ok(&relu_output_share.slice(s![..n]) - &relu_output_share.slice(s![n..]))
+}
+
+impl Split for ReLU6Layer {
+    type Splitted = ReLU6LayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            ReLU6LayerShare { cap: self.cap },
+            ReLU6LayerShare { cap: self.cap },
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_relu6_matches_plaintext() {
+    use crate::{layer::Layer, testing};
+
+    // Negative, zero, between 0 and the cap, at the cap and above it
+    let input = ndarray::array![-7.5, -0.25, 0.0, 0.25, 3.5, 5.75, 6.0, 6.25, 100.0]
+        .mapv(Com::from_num)
+        .into_dyn();
+    let layer = ReLU6Layer { cap: 6.0 };
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    assert_eq!(
+        expected,
+        ndarray::array![0.0, 0.0, 0.0, 0.25, 3.5, 5.75, 6.0, 6.0, 6.0]
+            .mapv(Com::from_num)
+            .into_dyn()
+    );
+
+    let (output, _) = testing::run_secure(Layer::ReLU6Layer(layer), &input).await;
+    assert_eq!(output, expected);
+}
@@ -0,0 +1,99 @@
+use crate::{
+    convolution::{feature_map_size, into_feature_map},
+    split::Split,
+    Com,
+};
+use anyhow::bail;
+use ndarray::{Array3, ArrayD};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// Repeats every pixel of a (channels, height, width) feature map into a (scale, scale) block,
+/// i.e. nearest-neighbour upsampling.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Upsample2DLayer {
+    pub scale: usize,
+}
+
+impl Upsample2DLayer {
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        upsample(input, self.scale)
+    }
+
+    pub fn output_shape(&self, input_shape: &[usize]) -> anyhow::Result<Vec<usize>> {
+        check_scale(self.scale)?;
+        let (channels, height, width) = feature_map_size(input_shape, None, (1, 1), 0)?;
+        Ok(vec![channels, height * self.scale, width * self.scale])
+    }
+}
+
+/// Checks that a scale is positive, as a zero scale would leave nothing of the feature map.
+fn check_scale(scale: usize) -> anyhow::Result<()> {
+    if scale == 0 {
+        bail!("The scale should be positive");
+    }
+    Ok(())
+}
+
+fn upsample(input: ArrayD<Com>, scale: usize) -> anyhow::Result<ArrayD<Com>> {
+    check_scale(scale)?;
+    let channels = input.shape().first().copied().unwrap_or_default();
+    let input = into_feature_map(input, channels, (1, 1), 0)?;
+    let (_, height, width) = input.dim();
+
+    Ok(
+        Array3::from_shape_fn((channels, height * scale, width * scale), |(c, i, j)| {
+            input[[c, i / scale, j / scale]]
+        })
+        .into_dyn(),
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Upsample2DLayerShare {
+    pub scale: usize,
+}
+
+impl Upsample2DLayerShare {
+    pub fn infer(&self, input_share: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        // Copying elements is local
+        upsample(input_share, self.scale)
+    }
+}
+
+impl Split for Upsample2DLayer {
+    type Splitted = Upsample2DLayerShare;
+
+    fn split(&self, _: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
+        (
+            Upsample2DLayerShare { scale: self.scale },
+            Upsample2DLayerShare { scale: self.scale },
+        )
+    }
+}
+
+#[test]
+fn test_upsample_matches_plaintext() {
+    use crate::reconstruct::Reconstruct;
+
+    let rng = ring::rand::SystemRandom::new();
+    let input = Array3::from_shape_fn((2, 2, 3), |(c, i, j)| {
+        Com::from_num(c as f32 - i as f32 * 1.5 + j as f32)
+    })
+    .into_dyn();
+    let input_shares = input.split(&rng);
+
+    let layer = Upsample2DLayer { scale: 2 };
+    let expected = layer.infer_locally(input.clone()).unwrap();
+    assert_eq!(expected.shape(), [2, 4, 6]);
+    assert_eq!(expected[[1, 3, 5]], input[[1, 1, 2]]);
+
+    let (server_share, client_share) = layer.split(&rng);
+    let output = ArrayD::reconstruct((
+        &server_share.infer(input_shares.0.clone()).unwrap(),
+        &client_share.infer(input_shares.1.clone()).unwrap(),
+    ));
+    assert_eq!(output, expected);
+
+    assert!(Upsample2DLayer { scale: 0 }.infer_locally(input).is_err());
+}
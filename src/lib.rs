@@ -1,20 +1,44 @@
 #![feature(new_uninit)]
 
+pub(crate) mod approximation;
 mod com;
 pub mod split;
 mod unexpected_message_error;
 pub use com::Com; // TODO shouldn't be pub
+pub mod binary;
 pub(crate) mod bit;
 mod bitxa;
+pub mod calibration;
 pub mod client;
+pub(crate) mod convolution;
+pub(crate) mod equality;
+pub mod inspection;
+pub mod invalid_node_error;
 pub mod layer;
+pub(crate) mod maximum;
 pub mod message;
 pub mod model;
+pub mod model_header;
 mod multiplication_triplet_share;
+pub mod offline;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+pub mod overflow_error;
+mod paillier;
+pub mod preprocessing;
+pub mod quantization;
 pub mod server;
+pub mod shape_mismatch_error;
+pub mod weights;
 pub(crate) use bitxa::bitxa;
 pub(crate) mod reconstruct;
 pub(crate) mod signed_comparison;
+pub(crate) mod softmax;
+pub(crate) mod tensor;
+pub(crate) mod truncation;
+
+#[cfg(test)]
+mod testing;
 
 #[cfg(feature = "utils")]
 pub mod utils;
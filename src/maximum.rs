@@ -0,0 +1,91 @@
+use anyhow::Context as _;
+use ndarray::{concatenate, s, Array1, Array2, Axis};
+
+use crate::{bitxa, layer::relu::drelu::drelu, message::IO, offline::OfflineMaterial, Com};
+
+/// Computes the maximum of every row using a tree of comparisons.
+///
+/// Each level of the tree halves the number of candidates using max(a, b) = b + ReLU(a - b),
+/// comparing all the pairs of all the rows at once. Therefore, the number of rounds is
+/// logarithmic in the number of columns.
+///
+/// # Arguments
+///
+/// - `candidates_share`: A share of the candidates, a row per maximum
+/// - `material`: The material of the offline phase, of which DReLU and BitXA keys are taken
+/// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+///
+/// # Returns
+///
+/// A share of the maximum of every row
+pub(crate) async fn maximum<const PARTY: bool>(
+    candidates_share: Array2<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array1<Com>> {
+    let mut candidates_share = candidates_share;
+
+    while candidates_share.ncols() > 1 {
+        let (rows, columns) = candidates_share.dim();
+        let half = columns / 2;
+
+        let a_share = candidates_share.slice(s![.., ..half]);
+        let b_share = candidates_share.slice(s![.., half..2 * half]);
+        let difference_share = Array1::from_iter(&a_share - &b_share);
+
+        let drelu_output_share = drelu::<PARTY>(&difference_share, material, (sender, receiver))
+            .await
+            .context("Failed to evaluate DReLU")?;
+        let key = material.take_bitxa_key(difference_share.len())?;
+        let relu_output_share = bitxa::<PARTY>(
+            &difference_share,
+            &drelu_output_share,
+            key,
+            (sender, receiver),
+        )
+        .await
+        .context("Failed to evaluate BitXA")?;
+
+        let maxima_share =
+            &b_share + &Array2::from_shape_vec((rows, half), relu_output_share.to_vec()).unwrap();
+
+        // An odd candidate out advances to the next level as it is
+        candidates_share = if columns % 2 == 1 {
+            concatenate![Axis(1), maxima_share, candidates_share.slice(s![.., -1..])]
+        } else {
+            maxima_share
+        };
+    }
+
+    Ok(candidates_share.column(0).to_owned())
+}
+
+#[tokio::test]
+async fn test_maximum_takes_logarithmic_rounds() {
+    use crate::{offline::OfflinePlan, reconstruct::Reconstruct, split::Split, testing};
+    use ndarray::array;
+
+    let rng = ring::rand::SystemRandom::new();
+    let candidates = array![[3, -2, 5, 0, 7], [-1, -4, -3, -8, -2]].mapv(Com::from_num);
+    let candidates_shares = candidates.split(&rng);
+
+    // Five candidates take three levels, of 2, 1 and 1 comparisons per row
+    let mut materials = OfflineMaterial::deal(
+        &OfflinePlan {
+            bitxa_elements: 8,
+            drelu_elements: 8,
+            ..Default::default()
+        },
+        &rng,
+    );
+
+    let (mut server, mut client, log) = testing::connect();
+    let (server_output_share, client_output_share) = tokio::join!(
+        maximum::<true>(candidates_shares.0, &mut materials.0, server.io()),
+        maximum::<false>(candidates_shares.1, &mut materials.1, client.io()),
+    );
+    let output =
+        Array1::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+    assert_eq!(output, array![7, -1].mapv(Com::from_num));
+    assert_eq!(log.count(true, "BitXAInteraction"), 3);
+}
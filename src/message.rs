@@ -1,10 +1,15 @@
-use ndarray::{Array1, Array2};
+use ndarray::{Array1, Array2, Array3, Array4, ArrayD};
+use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+use crate::binary;
 use crate::bitxa::BitXAInteraction;
 use crate::layer::relu::drelu::{DReLUInteraction, DReLUKey};
 use crate::model::ModelShare;
+use crate::model_header::ModelHeader;
+use crate::offline::OfflinePlan;
+use crate::paillier::PaillierPublicKey;
 use crate::unexpected_message_error::UnexpectedMessageError;
 use crate::Com;
 
@@ -14,21 +19,50 @@ pub struct DotProductInteraction {
     pub f_share: Array2<Com>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatrixProductInteraction {
+    pub e_share: Array2<Com>,
+    pub f_share: Array2<Com>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HadamardProductInteraction {
     pub e_share: Array1<Com>,
     pub f_share: Array1<Com>,
 }
 
-// TODO move to other place
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PrivateDenseLayerInteraction {
+    pub masked_input_share: Array1<Com>,
+}
+
+/// Paillier ciphertexts of a share of the first operand of a multiplication triplet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedOperand {
+    pub ciphertexts: Vec<BigUint>,
+}
+
+/// Paillier ciphertexts of masked cross terms of a multiplication triplet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedProduct {
+    pub ciphertexts: Vec<BigUint>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConvolutionInteraction {
+    pub e_share: Array3<Com>,
+    pub f_share: Array4<Com>,
+}
+
+// TODO move to other place
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DDCFKey {
     pub alpha: Array1<Com>,
     pub invert: Array1<bool>,
 }
 
 // TODO move to other place
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct SignedComparisonKeys {
     pub ddcf_keys: DDCFKey,
     pub r_shares: Array1<bool>,
@@ -37,14 +71,34 @@ pub struct SignedComparisonKeys {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Message {
+    ModelHeader(ModelHeader),
     ModelShare(ModelShare),
-    InputShare(Array1<Com>),
+    InputShare(ArrayD<Com>),
     DotProductInteraction(DotProductInteraction),
+    MatrixProductInteraction(MatrixProductInteraction),
     HadamardProductInteraction(HadamardProductInteraction),
+    ConvolutionInteraction(ConvolutionInteraction),
+    PrivateDenseLayerInteraction(PrivateDenseLayerInteraction),
+    OfflinePlan(OfflinePlan),
+    PaillierPublicKey(PaillierPublicKey),
+    EncryptedOperand(EncryptedOperand),
+    EncryptedProduct(EncryptedProduct),
     DReLUKey(DReLUKey),
     DReLUInteraction(DReLUInteraction),
     BitXAInteraction(BitXAInteraction),
-    OutputShare(Array1<Com>),
+    OutputShare(ArrayD<Com>),
+}
+
+impl Message {
+    /// Encodes the message for the wire, in the binary format.
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        binary::to_vec(self)
+    }
+
+    /// Decodes a message in either the binary format or JSON.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        binary::from_slice_or_json(bytes)
+    }
 }
 
 macro_rules! impl_message_conversions {
@@ -1,62 +1,712 @@
-use anyhow::Context;
-use log::debug;
-use ndarray::Array1;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    ops::AddAssign,
+};
+
+use anyhow::{anyhow, bail, Context};
+use log::{debug, info, warn};
+use ndarray::{concatenate, ArrayD, ArrayView, Axis, IxDyn};
 use ring::rand::SecureRandom;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
+    calibration::FloatLayer,
+    invalid_node_error::InvalidNodeError,
     layer::{Layer, LayerShare},
     message::IO,
+    model_header::ModelHeader,
+    offline::{self, OfflineMaterial, OfflinePlan},
+    overflow_error::{Overflow, OverflowError},
+    quantization::{quantize_json, FixedPointFormat, QuantizationReport, Rounding},
+    shape_mismatch_error::ShapeMismatchError,
     split::Split,
     Com,
 };
 
-#[derive(Deserialize, Debug, Clone)]
+/// The name by which nodes refer to the input of the model.
+pub const INPUT: &str = "input";
+
+/// A node of the computation graph of a model.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Node<L> {
+    pub name: String,
+    /// The names of the nodes whose outputs this node takes, or `INPUT`
+    pub inputs: Vec<String>,
+    #[serde(flatten)]
+    pub operation: Operation<L>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Operation<L> {
+    /// Sums its inputs element-wise, e.g. for residual connections
+    Add,
+    /// Concatenates its inputs along an axis, e.g. to merge branches
+    Concat { axis: usize },
+    /// Applies a layer to its only input
+    #[serde(untagged)]
+    Layer(L),
+}
+
+impl<L> Operation<L> {
+    /// Evaluates the operations that combine several tensors. These are linear, so each party may
+    /// apply them to its shares.
+    pub(crate) fn combine<A: Clone + AddAssign>(
+        &self,
+        inputs: Vec<ArrayD<A>>,
+    ) -> anyhow::Result<ArrayD<A>> {
+        match self {
+            Operation::Add => {
+                let mut inputs = inputs.into_iter();
+                let mut sum = inputs.next().context("Nothing to add")?;
+                let shape: Vec<Option<usize>> = sum.shape().iter().copied().map(Some).collect();
+
+                for input in inputs {
+                    ShapeMismatchError::check(input.shape(), &shape)?;
+                    sum += &input;
+                }
+
+                Ok(sum)
+            }
+            Operation::Concat { axis } => {
+                let views: Vec<ArrayView<A, IxDyn>> = inputs.iter().map(|x| x.view()).collect();
+                Ok(concatenate(Axis(*axis), &views)
+                    .with_context(|| format!("Failed to concatenate along axis {}", axis))?)
+            }
+            Operation::Layer(_) => unreachable!("Layers are evaluated by the model"),
+        }
+    }
+
+    /// Computes the shape of the output of the operations that combine several tensors.
+    fn combined_shape(&self, input_shapes: &[Vec<usize>]) -> anyhow::Result<Vec<usize>> {
+        let (first, rest) = input_shapes.split_first().context("Nothing to combine")?;
+        match self {
+            Operation::Add => {
+                let shape: Vec<Option<usize>> = first.iter().copied().map(Some).collect();
+                for input_shape in rest {
+                    ShapeMismatchError::check(input_shape, &shape)?;
+                }
+                Ok(first.clone())
+            }
+            Operation::Concat { axis } => {
+                if *axis >= first.len() {
+                    bail!(
+                        "Cannot concatenate {}-D tensors along axis {}",
+                        first.len(),
+                        axis
+                    );
+                }
+                let mut shape: Vec<Option<usize>> = first.iter().copied().map(Some).collect();
+                shape[*axis] = None;
+
+                let mut output_shape = first.clone();
+                for input_shape in rest {
+                    ShapeMismatchError::check(input_shape, &shape)?;
+                    output_shape[*axis] += input_shape[*axis];
+                }
+                Ok(output_shape)
+            }
+            Operation::Layer(_) => unreachable!("Layers are evaluated by the model"),
+        }
+    }
+
+    /// Splits the layer of the operation, if any.
+    fn try_split_with<S, E>(
+        &self,
+        split_layer: impl FnOnce(&L) -> Result<(S, S), E>,
+    ) -> Result<(Operation<S>, Operation<S>), E> {
+        Ok(match self {
+            Operation::Add => (Operation::Add, Operation::Add),
+            Operation::Concat { axis } => (
+                Operation::Concat { axis: *axis },
+                Operation::Concat { axis: *axis },
+            ),
+            Operation::Layer(layer) => {
+                let shares = split_layer(layer)?;
+                (Operation::Layer(shares.0), Operation::Layer(shares.1))
+            }
+        })
+    }
+}
+
+/// How `Model::infer_checked` treats activations out of the range of `Com`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnOverflow {
+    /// Fail with an `OverflowError` at the first node which overflows
+    #[default]
+    Fail,
+    /// Clamp the overflowing outputs of a node to the range and carry on.
+    ///
+    /// The outputs are clamped after the node was evaluated, from their exact values, which is
+    /// not saturating arithmetic: `infer_locally` and the secure protocols still wrap around, so
+    /// the following nodes see other inputs than they would there.
+    Saturate,
+}
+
+/// The result of `Model::infer_checked`.
+#[derive(Debug, Clone)]
+pub struct CheckedInference {
+    pub output: ArrayD<Com>,
+    /// The overflows which were saturated, by node
+    pub saturated: Vec<OverflowError>,
+    /// The names of the nodes which were not checked, as they have no floating-point equivalent
+    pub unchecked: Vec<String>,
+}
+
+/// The outputs of the nodes evaluated so far.
+struct Activations(HashMap<String, ArrayD<Com>>);
+
+impl Activations {
+    fn new(input: ArrayD<Com>) -> Self {
+        Activations(HashMap::from([(INPUT.to_owned(), input)]))
+    }
+
+    fn gather(&self, names: &[String]) -> anyhow::Result<Vec<ArrayD<Com>>> {
+        names
+            .iter()
+            .map(|name| {
+                self.0
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Node '{}' has not been evaluated", name))
+            })
+            .collect()
+    }
+
+    fn single<A>(inputs: Vec<ArrayD<A>>) -> anyhow::Result<ArrayD<A>> {
+        match <[ArrayD<A>; 1]>::try_from(inputs) {
+            Ok([input]) => Ok(input),
+            Err(inputs) => bail!("A layer takes one input, got {}", inputs.len()),
+        }
+    }
+
+    fn take(mut self, name: &str) -> anyhow::Result<ArrayD<Com>> {
+        self.0
+            .remove(name)
+            .ok_or_else(|| anyhow!("Node '{}' has not been evaluated", name))
+    }
+}
+
+/// A model, as a computation graph whose nodes are in topological order.
+///
+/// Models are either a graph of named nodes or, for sequential models, a list of layers.
+/// Batch normalisations which follow a linear layer are folded into it as the model is loaded.
+///
+/// Tensors hold the bits of `Com`s, unless the model declares a `format`, in which case they are
+/// real numbers and are quantized as the model is loaded. The statistics of batch normalisations
+/// are always real numbers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "SerializedModel")]
 pub struct Model {
-    pub layers: Vec<Layer>,
+    pub nodes: Vec<Node<Layer>>,
+    /// The name of the node whose output is the output of the model
+    pub output: String,
+    /// Sent to clients, see `ModelHeader`
+    pub header: ModelHeader,
+}
+
+/// The fields which `Model` serializes come first and in its order, so that the binary format,
+/// which writes structs positionally, reads back.
+#[derive(Deserialize)]
+pub(crate) struct SerializedModel {
+    #[serde(default)]
+    pub(crate) nodes: Option<serde_json::Value>,
+    /// Defaults to the last node
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    pub(crate) header: ModelHeader,
+    #[serde(default)]
+    pub(crate) layers: Option<serde_json::Value>,
+    #[serde(default)]
+    format: Option<WeightFormat>,
+}
+
+impl SerializedModel {
+    /// Reads the nodes, in the order they are given, and the name of the output node.
+    pub(crate) fn graph<L: DeserializeOwned>(self) -> anyhow::Result<(Vec<Node<L>>, String)> {
+        let nodes = match (self.layers, self.nodes) {
+            (Some(layers), None) => sequential(serde_json::from_value(layers)?),
+            (None, Some(nodes)) => serde_json::from_value(nodes)?,
+            _ => bail!("A model should have either layers or nodes"),
+        };
+        let output = self
+            .output
+            .or_else(|| nodes.last().map(|node: &Node<L>| node.name.clone()))
+            .unwrap_or_else(|| INPUT.to_owned());
+
+        Ok((nodes, output))
+    }
+}
+
+/// Declares that the tensors of a model are real-valued.
+#[derive(Deserialize)]
+struct WeightFormat {
+    #[serde(flatten)]
+    fixed_point: FixedPointFormat,
+    #[serde(default)]
+    rounding: Rounding,
+}
+
+impl TryFrom<SerializedModel> for Model {
+    type Error = anyhow::Error;
+
+    fn try_from(mut model: SerializedModel) -> anyhow::Result<Self> {
+        model.header.check()?;
+
+        if let Some(format) = &model.format {
+            format.fixed_point.check()?;
+
+            let mut report = QuantizationReport::default();
+            for (path, value) in [("layers", &mut model.layers), ("nodes", &mut model.nodes)] {
+                if let Some(value) = value {
+                    quantize_json(value, path, format.rounding, &mut report)?;
+                }
+            }
+
+            info!(
+                "Quantized the weights to {}:\n{}",
+                format.fixed_point, report
+            );
+            if report.saturated() > 0 {
+                warn!(
+                    "Some weights are out of the range of {}",
+                    format.fixed_point
+                );
+            }
+        }
+
+        let header = std::mem::take(&mut model.header);
+        let (nodes, output) = model.graph()?;
+        let mut model = Model::new(nodes, output)?;
+        if let Some(input_shape) = &header.input_shape {
+            let output_shape = model.validate(input_shape)?;
+            debug!("The model maps {:?} to {:?}", input_shape, output_shape);
+
+            let outputs: usize = output_shape.iter().product();
+            if !header.labels.is_empty() && header.labels.len() != outputs {
+                bail!(
+                    "There are {} labels for {} outputs",
+                    header.labels.len(),
+                    outputs
+                );
+            }
+        }
+        model.header = header;
+
+        Ok(model)
+    }
+}
+
+/// Chains layers s.t. each one takes the output of the previous one.
+fn sequential<L>(layers: Vec<L>) -> Vec<Node<L>> {
+    let mut previous = INPUT.to_owned();
+
+    layers
+        .into_iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            let name = format!("layer{}", i + 1);
+            let inputs = vec![std::mem::replace(&mut previous, name.clone())];
+            Node {
+                name,
+                inputs,
+                operation: Operation::Layer(layer),
+            }
+        })
+        .collect()
+}
+
+/// Orders the nodes s.t. every node comes after its inputs, keeping the given order where possible.
+pub(crate) fn sort_topologically<L>(nodes: Vec<Node<L>>) -> anyhow::Result<Vec<Node<L>>> {
+    let mut names = HashSet::from([INPUT]);
+    for node in &nodes {
+        if !names.insert(node.name.as_str()) {
+            bail!("The node name '{}' is used more than once", node.name);
+        }
+    }
+
+    for node in &nodes {
+        if let Some(input) = node
+            .inputs
+            .iter()
+            .find(|input| !names.contains(input.as_str()))
+        {
+            bail!("Node '{}' takes the unknown node '{}'", node.name, input);
+        }
+        match &node.operation {
+            Operation::Layer(_) if node.inputs.len() != 1 => {
+                bail!(
+                    "Node '{}' is a layer, so it takes exactly one input",
+                    node.name
+                )
+            }
+            _ if node.inputs.is_empty() => bail!("Node '{}' takes no inputs", node.name),
+            _ => {}
+        }
+    }
+
+    let mut evaluated = HashSet::from([INPUT.to_owned()]);
+    let mut remaining: Vec<Option<Node<L>>> = nodes.into_iter().map(Some).collect();
+    let mut sorted = Vec::with_capacity(remaining.len());
+
+    while sorted.len() < remaining.len() {
+        let ready = remaining.iter().position(|node| {
+            node.as_ref()
+                .is_some_and(|node| node.inputs.iter().all(|input| evaluated.contains(input)))
+        });
+
+        match ready {
+            Some(i) => {
+                let node = remaining[i].take().unwrap();
+                evaluated.insert(node.name.clone());
+                sorted.push(node);
+            }
+            None => {
+                let node = remaining.iter().flatten().next().unwrap();
+                bail!("Node '{}' depends on itself", node.name);
+            }
+        }
+    }
+
+    Ok(sorted)
 }
 
 impl Model {
-    pub fn infer_locally(&self, input: Array1<Com>) -> Array1<Com> {
-        let mut activations = input;
+    /// Checks and sorts the graph, and folds batch normalisations.
+    pub fn new(nodes: Vec<Node<Layer>>, output: String) -> anyhow::Result<Self> {
+        let nodes = sort_topologically(nodes)?;
+        if output != INPUT && !nodes.iter().any(|node| node.name == output) {
+            bail!("The output node '{}' does not exist", output);
+        }
+
+        let mut model = Model {
+            nodes,
+            output,
+            header: ModelHeader::default(),
+        };
+        model.fold_batch_norms();
+        Ok(model)
+    }
 
-        for (i, layer) in self.layers.iter().enumerate() {
-            debug!("Evaluating layer {}", i);
-            activations = layer.infer_locally(activations);
+    /// Checks that every node fits its inputs, given the shape of the input of the model.
+    ///
+    /// # Returns
+    ///
+    /// The shape of the output of the model
+    pub fn validate(&self, input_shape: &[usize]) -> Result<Vec<usize>, InvalidNodeError> {
+        let mut shapes = self.shapes(input_shape)?;
+        Ok(shapes.remove(&self.output).unwrap())
+    }
+
+    /// Like `validate`, but returns the shape of the output of every node, and of `INPUT`.
+    pub fn shapes(
+        &self,
+        input_shape: &[usize],
+    ) -> Result<HashMap<String, Vec<usize>>, InvalidNodeError> {
+        let mut shapes = HashMap::from([(INPUT.to_owned(), input_shape.to_vec())]);
+
+        for node in &self.nodes {
+            // The graph is sorted, so every input has been visited
+            let input_shapes: Vec<Vec<usize>> = node
+                .inputs
+                .iter()
+                .map(|input| shapes[input].clone())
+                .collect();
+            let output_shape = match &node.operation {
+                Operation::Layer(layer) => match &input_shapes[..] {
+                    [input_shape] => layer.output_shape(input_shape),
+                    _ => Err(anyhow!(
+                        "A layer takes one input, got {}",
+                        input_shapes.len()
+                    )),
+                },
+                operation => operation.combined_shape(&input_shapes),
+            };
+
+            match output_shape {
+                Ok(output_shape) => shapes.insert(node.name.clone(), output_shape),
+                Err(source) => {
+                    return Err(InvalidNodeError {
+                        node: node.name.clone(),
+                        input_shapes,
+                        source,
+                    })
+                }
+            };
+        }
+
+        Ok(shapes)
+    }
+
+    pub fn infer_locally(&self, input: ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        let mut activations = Activations::new(input);
+
+        for node in &self.nodes {
+            debug!("Evaluating node {}", node.name);
+            let inputs = activations.gather(&node.inputs)?;
+            let output = match &node.operation {
+                Operation::Layer(layer) => layer.infer_locally(Activations::single(inputs)?),
+                operation => operation.combine(inputs),
+            }
+            .with_context(|| format!("Failed to evaluate node '{}'", node.name))?;
+            activations.0.insert(node.name.clone(), output);
+        }
+
+        activations.take(&self.output)
+    }
+
+    /// Like `infer_locally`, but checks every activation against the range of `Com`, which
+    /// `infer_locally` and the secure protocols silently wrap around. Every node is also evaluated
+    /// in floating-point on the same inputs, and compared to that.
+    ///
+    /// Layers with no floating-point equivalent, e.g. recurrent ones, are not checked, and the
+    /// result names them, so that callers can tell a clean run from an unchecked one.
+    pub fn infer_checked(
+        &self,
+        input: ArrayD<Com>,
+        on_overflow: OnOverflow,
+    ) -> anyhow::Result<CheckedInference> {
+        let mut unchecked = vec![];
+        let float_layers: Vec<Option<FloatLayer>> = self
+            .nodes
+            .iter()
+            .map(|node| match &node.operation {
+                Operation::Layer(layer) => {
+                    let float_layer = FloatLayer::from_layer(layer);
+                    if float_layer.is_none() {
+                        unchecked.push(node.name.clone());
+                    }
+                    float_layer
+                }
+                _ => None,
+            })
+            .collect();
+        let range = Com::MIN.0.to_num::<f64>()..=Com::MAX.0.to_num::<f64>();
+
+        let mut activations = Activations::new(input);
+        let mut saturated = vec![];
+        for (node, float_layer) in self.nodes.iter().zip(&float_layers) {
+            debug!("Evaluating node {}", node.name);
+            let inputs = activations.gather(&node.inputs)?;
+            let float_inputs: Vec<ArrayD<f64>> = inputs
+                .iter()
+                .map(|input| input.mapv(|x| x.0.to_num::<f64>()))
+                .collect();
+
+            let (output, exact) = match (&node.operation, float_layer) {
+                (Operation::Layer(layer), float_layer) => (
+                    layer.infer_locally(Activations::single(inputs)?),
+                    float_layer
+                        .as_ref()
+                        .map(|float_layer| float_layer.infer(Activations::single(float_inputs)?))
+                        .transpose(),
+                ),
+                (operation, _) => (
+                    operation.combine(inputs),
+                    operation.combine(float_inputs).map(Some),
+                ),
+            };
+            let mut output =
+                output.with_context(|| format!("Failed to evaluate node '{}'", node.name))?;
+
+            let overflows: Vec<Overflow> = exact?
+                .iter()
+                .flat_map(|exact| exact.indexed_iter())
+                .filter(|(_, value)| !range.contains(value))
+                .map(|(index, &value)| Overflow {
+                    index: index.slice().to_vec(),
+                    value,
+                })
+                .collect();
+            if !overflows.is_empty() {
+                let error = OverflowError {
+                    node: node.name.clone(),
+                    overflows,
+                };
+                match on_overflow {
+                    OnOverflow::Fail => return Err(error.into()),
+                    OnOverflow::Saturate => {
+                        for overflow in &error.overflows {
+                            output[IxDyn(&overflow.index)] = if overflow.value < 0.0 {
+                                Com::MIN
+                            } else {
+                                Com::MAX
+                            };
+                        }
+                        saturated.push(error);
+                    }
+                }
+            }
+
+            activations.0.insert(node.name.clone(), output);
         }
 
-        activations
+        Ok(CheckedInference {
+            output: activations.take(&self.output)?,
+            saturated,
+            unchecked,
+        })
+    }
+
+    /// Splits the model s.t. the client never receives anything derived from the weights. The
+    /// first share belongs to the server.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a layer cannot keep its weights private, see `Layer::split_private_weights`
+    pub fn split_private_weights(
+        &self,
+        rng: &dyn SecureRandom,
+    ) -> anyhow::Result<(ModelShare, ModelShare)> {
+        self.split_with(|node| {
+            node.operation
+                .try_split_with(|layer| layer.split_private_weights(rng))
+                .with_context(|| format!("Failed to split node '{}'", node.name))
+        })
+    }
+
+    fn split_with<E>(
+        &self,
+        split_operation: impl Fn(
+            &Node<Layer>,
+        ) -> Result<(Operation<LayerShare>, Operation<LayerShare>), E>,
+    ) -> Result<(ModelShare, ModelShare), E> {
+        let mut server_nodes = Vec::with_capacity(self.nodes.len());
+        let mut client_nodes = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let operations = split_operation(node)?;
+            server_nodes.push(Node {
+                name: node.name.clone(),
+                inputs: node.inputs.clone(),
+                operation: operations.0,
+            });
+            client_nodes.push(Node {
+                name: node.name.clone(),
+                inputs: node.inputs.clone(),
+                operation: operations.1,
+            });
+        }
+
+        Ok((
+            ModelShare {
+                nodes: server_nodes,
+                output: self.output.clone(),
+            },
+            ModelShare {
+                nodes: client_nodes,
+                output: self.output.clone(),
+            },
+        ))
+    }
+
+    /// Counts the nodes which take the output of the given node, including the output itself.
+    fn uses(&self, name: &str) -> usize {
+        self.nodes
+            .iter()
+            .flat_map(|node| &node.inputs)
+            .filter(|input| *input == name)
+            .count()
+            + usize::from(self.output == name)
+    }
+
+    /// Folds every batch normalisation into the linear layer it follows, as long as nothing else
+    /// takes the output of that layer.
+    fn fold_batch_norms(&mut self) {
+        let mut i = 0;
+
+        while i < self.nodes.len() {
+            let batch_norm = match &self.nodes[i].operation {
+                Operation::Layer(Layer::BatchNormLayer(batch_norm_layer)) => {
+                    batch_norm_layer.clone()
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let input = self.nodes[i].inputs[0].clone();
+            let sole_use = self.uses(&input) == 1;
+
+            let preceding_layer = self
+                .nodes
+                .iter_mut()
+                .find(|node| node.name == input)
+                .and_then(|node| match &mut node.operation {
+                    Operation::Layer(layer) => Some(layer),
+                    _ => None,
+                });
+            let folded =
+                sole_use && preceding_layer.is_some_and(|layer| layer.fold_batch_norm(&batch_norm));
+
+            if folded {
+                debug!("Folded a batch normalisation into node {}", input);
+                let name = self.nodes.remove(i).name;
+                for node_input in self.nodes.iter_mut().flat_map(|node| &mut node.inputs) {
+                    if *node_input == name {
+                        *node_input = input.clone();
+                    }
+                }
+                if self.output == name {
+                    self.output = input;
+                }
+            } else {
+                i += 1;
+            }
+        }
     }
 }
 
-#[derive(Default, Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ModelShare {
-    pub layer_shares: Vec<LayerShare>,
+    pub nodes: Vec<Node<LayerShare>>,
+    pub output: String,
 }
 
 impl ModelShare {
-    pub async fn infer<const PARTY: bool>(
+    /// Runs the offline phase, which does not depend on the input.
+    ///
+    /// The server generates the material of `plan`, see `offline::plan`, and tells the client,
+    /// which ignores its `plan`. See `offline` for which material the server deals, and hence
+    /// has to be trusted with.
+    pub async fn prepare<const PARTY: bool>(
         &self,
-        input_share: Array1<Com>,
+        plan: OfflinePlan,
         (sender, receiver): IO<'_>,
         rng: &dyn SecureRandom,
-    ) -> anyhow::Result<Array1<Com>> {
-        let mut activations_share = input_share;
+    ) -> anyhow::Result<OfflineMaterial> {
+        offline::prepare::<PARTY>(self, plan, (sender, receiver), rng).await
+    }
 
-        for (i, layer_share) in self.layer_shares.iter().enumerate() {
-            activations_share = layer_share
-                .infer::<PARTY>(activations_share, (sender, receiver), rng)
-                .await
-                .with_context(|| format!("Failed to infer layer {}", i + 1))?;
-        }
+    /// Runs the online phase, consuming the material of the offline phase.
+    pub async fn infer<const PARTY: bool>(
+        &self,
+        input_share: ArrayD<Com>,
+        material: &mut OfflineMaterial,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<ArrayD<Com>> {
+        let mut activations_share = Activations::new(input_share);
 
-        Ok(activations_share)
-    }
-}
+        for node in &self.nodes {
+            let inputs_share = activations_share.gather(&node.inputs)?;
+            let output_share = match &node.operation {
+                Operation::Layer(layer_share) => {
+                    layer_share
+                        .infer::<PARTY>(
+                            Activations::single(inputs_share)?,
+                            material,
+                            (sender, receiver),
+                        )
+                        .await
+                }
+                operation => operation.combine(inputs_share),
+            }
+            .with_context(|| format!("Failed to infer node '{}'", node.name))?;
+            activations_share.0.insert(node.name.clone(), output_share);
+        }
 
-impl Extend<LayerShare> for ModelShare {
-    fn extend<T: IntoIterator<Item = LayerShare>>(&mut self, iter: T) {
-        self.layer_shares.extend(iter)
+        activations_share.take(&self.output)
     }
 }
 
@@ -64,6 +714,194 @@ impl Split for Model {
     type Splitted = ModelShare;
 
     fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
-        self.layers.iter().map(|layer| layer.split(rng)).unzip()
+        let shares = self.split_with(|node| {
+            node.operation
+                .try_split_with(|layer| Ok::<_, Infallible>(layer.split(rng)))
+        });
+        match shares {
+            Ok(shares) => shares,
+            Err(never) => match never {},
+        }
     }
 }
+
+#[tokio::test]
+async fn test_residual_connection() {
+    use crate::{reconstruct::Reconstruct, testing};
+    use ndarray::array;
+
+    // Com has two fractional bits, so 4 is 1.0
+    let model: Model = serde_json::from_str(
+        r#"{"nodes": [
+            {"name": "sum", "inputs": ["input", "relu"], "type": "Add"},
+            {"name": "dense", "inputs": ["input"], "type": "DenseLayer",
+             "weights": {"v": 1, "dim": [2, 2], "data": [0, -4, 4, 0]},
+             "biases": {"v": 1, "dim": [2], "data": [0, 0]}},
+            {"name": "relu", "inputs": ["dense"], "type": "ReLULayer"}
+        ], "output": "sum"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        model
+            .nodes
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect::<Vec<_>>(),
+        ["dense", "relu", "sum"]
+    );
+
+    // x + ReLU([x₁, -x₀])
+    let input = array![2.0, 3.0].mapv(Com::from_num).into_dyn();
+    let expected = array![5.0, 3.0].mapv(Com::from_num).into_dyn();
+    assert_eq!(model.infer_locally(input.clone()).unwrap(), expected);
+
+    let rng = ring::rand::SystemRandom::new();
+    let model_shares = model.split(&rng);
+    let input_shares = input.split(&rng);
+    let plan = offline::plan((&model_shares.0, &model_shares.1), &[2])
+        .await
+        .unwrap();
+    assert_eq!((plan.bitxa_elements, plan.drelu_elements), (2, 2));
+
+    let (mut server, mut client, log) = testing::connect();
+    let (server_material, client_material) = tokio::join!(
+        model_shares.0.prepare::<true>(plan, server.io(), &rng),
+        model_shares
+            .1
+            .prepare::<false>(OfflinePlan::default(), client.io(), &rng),
+    );
+    let (mut server_material, mut client_material) =
+        (server_material.unwrap(), client_material.unwrap());
+    let offline_messages = log.len();
+    assert_eq!(log.count(true, "DReLUKey"), 1);
+
+    let (server_output_share, client_output_share) = tokio::join!(
+        model_shares
+            .0
+            .infer::<true>(input_shares.0, &mut server_material, server.io()),
+        model_shares
+            .1
+            .infer::<false>(input_shares.1, &mut client_material, client.io()),
+    );
+    let output =
+        ArrayD::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+
+    // Truncating the shares of the dense layer's products may be off by one unit in the last place
+    for (output, expected) in output.iter().zip(&expected) {
+        assert!((output - expected).0.to_bits().abs() <= 1);
+    }
+
+    // No keys are dealt online, where the dense layer, DReLU and BitXA take a round each
+    assert_eq!(log.count(true, "DReLUKey"), 1);
+    assert_eq!(log.len() - offline_messages, 2 * 3);
+}
+
+#[test]
+fn test_real_valued_weights() {
+    use ndarray::array;
+
+    let model: Model = serde_json::from_str(
+        r#"{"format": {"integer_bits": 30, "fractional_bits": 2, "rounding": "floor"},
+            "layers": [{"type": "DenseLayer",
+                        "weights": [[1.0, 0.3], [-0.5, 2.0]],
+                        "biases": {"v": 1, "dim": [2], "data": [0.6, 0.0]}}]}"#,
+    )
+    .unwrap();
+
+    // 0.3 and 0.6 are rounded down to 0.25 and 0.5
+    let input = array![1.0, 1.0].mapv(Com::from_num).into_dyn();
+    let expected = array![1.0, 2.25].mapv(Com::from_num).into_dyn();
+    assert_eq!(model.infer_locally(input).unwrap(), expected);
+
+    let mismatched = r#"{"format": {"integer_bits": 28, "fractional_bits": 4}, "layers": []}"#;
+    assert!(serde_json::from_str::<Model>(mismatched).is_err());
+}
+
+#[test]
+fn test_validation_names_the_invalid_node() {
+    let layers = r#""layers": [
+        {"type": "DenseLayer",
+         "weights": {"v": 1, "dim": [3, 2], "data": [0, 0, 0, 0, 0, 0]},
+         "biases": {"v": 1, "dim": [2], "data": [0, 0]}},
+        {"type": "ReLULayer"},
+        {"type": "DenseLayer",
+         "weights": {"v": 1, "dim": [3, 1], "data": [0, 0, 0]},
+         "biases": {"v": 1, "dim": [1], "data": [0]}}
+    ]"#;
+
+    let model: Model = serde_json::from_str(&format!("{{{}}}", layers)).unwrap();
+    assert_eq!(model.validate(&[2, 3]).unwrap_err().node, "layer1");
+    assert_eq!(model.validate(&[3]).unwrap_err().node, "layer3");
+
+    let declared = format!(r#"{{"header": {{"input_shape": [3]}}, {}}}"#, layers);
+    assert!(serde_json::from_str::<Model>(&declared)
+        .unwrap_err()
+        .to_string()
+        .contains("layer3"));
+}
+
+#[test]
+fn test_checked_inference_reports_overflows() {
+    use ndarray::array;
+
+    // The second output is 2·10⁹, out of the range of Com
+    let model: Model = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "DenseLayer",
+             "weights": {"v": 1, "dim": [1, 2], "data": [4, 2000000000]},
+             "biases": {"v": 1, "dim": [2], "data": [0, 0]}},
+            {"type": "ReLULayer"}
+        ]}"#,
+    )
+    .unwrap();
+    let input = array![4.0].mapv(Com::from_num).into_dyn();
+
+    // It wraps around to a negative number, which the ReLU zeroes
+    assert_eq!(model.infer_locally(input.clone()).unwrap()[1], Com::ZERO);
+
+    let error = model
+        .infer_checked(input.clone(), OnOverflow::Fail)
+        .unwrap_err()
+        .downcast::<OverflowError>()
+        .unwrap();
+    assert_eq!(error.node, "layer1");
+    assert_eq!(error.overflows.len(), 1);
+    assert_eq!(error.overflows[0].index, [1]);
+
+    let checked = model.infer_checked(input, OnOverflow::Saturate).unwrap();
+    assert_eq!(
+        checked.output,
+        array![Com::from_num(4.0), Com::MAX].into_dyn()
+    );
+    assert_eq!(checked.saturated.len(), 1);
+    assert!(checked.unchecked.is_empty());
+
+    // Embeddings have no floating-point equivalent
+    let model: Model = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "EmbeddingLayer", "table": {"v": 1, "dim": [2, 1], "data": [4, 8]}},
+            {"type": "ReLULayer"}
+        ]}"#,
+    )
+    .unwrap();
+    let input = array![1.0].mapv(Com::from_num).into_dyn();
+    let checked = model.infer_checked(input, OnOverflow::Fail).unwrap();
+    assert_eq!(checked.unchecked, ["layer1"]);
+}
+
+#[test]
+fn test_private_weights_refuse_leaking_layers() {
+    let rng = ring::rand::SystemRandom::new();
+    let model: Model = serde_json::from_str(
+        r#"{"layers": [
+            {"type": "Conv2DLayer",
+             "kernels": {"v": 1, "dim": [1, 1, 1, 1], "data": [4]},
+             "biases": {"v": 1, "dim": [1], "data": [0]}},
+            {"type": "ReLULayer"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let error = model.split_private_weights(&rng).unwrap_err();
+    assert!(format!("{:#}", error).contains("layer1"));
+}
@@ -0,0 +1,94 @@
+//! Metadata which lets clients prepare inputs and interpret outputs without knowing the model.
+
+use std::{error::Error, fmt};
+
+use ndarray::ArrayD;
+use serde::{Deserialize, Serialize};
+
+use crate::{preprocessing::Preprocessing, quantization::FixedPointFormat};
+
+/// The latest version of the model format.
+pub const VERSION: u32 = 1;
+
+fn default_version() -> u32 {
+    VERSION
+}
+
+fn default_output_scale() -> f32 {
+    1.0
+}
+
+/// Describes the inputs and the outputs of a model. The server sends it to the client before
+/// anything else.
+///
+/// Every field is serialized, even when it is the default, as the binary format writes structs
+/// positionally.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelHeader {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// If declared, the model is validated against it as it is loaded
+    #[serde(default)]
+    pub input_shape: Option<Vec<usize>>,
+    /// The names of the elements of the output, e.g. of classes
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// How to turn raw inputs into tensors, in order
+    #[serde(default)]
+    pub preprocessing: Vec<Preprocessing>,
+    /// The outputs of the model are the real outputs multiplied by this, e.g. after calibration
+    #[serde(default = "default_output_scale")]
+    pub output_scale: f32,
+    /// The format of `Com` which the model was quantized for
+    #[serde(default = "FixedPointFormat::of_com")]
+    pub format: FixedPointFormat,
+}
+
+impl Default for ModelHeader {
+    fn default() -> Self {
+        ModelHeader {
+            version: VERSION,
+            input_shape: None,
+            labels: vec![],
+            preprocessing: vec![],
+            output_scale: default_output_scale(),
+            format: FixedPointFormat::of_com(),
+        }
+    }
+}
+
+impl ModelHeader {
+    /// Checks that this build can evaluate the model.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.version > VERSION {
+            return Err(UnsupportedVersionError {
+                version: self.version,
+            }
+            .into());
+        }
+        self.format.check()?;
+        Ok(())
+    }
+
+    /// Undoes the scaling of the outputs of the model.
+    pub fn postprocess(&self, output: ArrayD<f32>) -> ArrayD<f32> {
+        output / self.output_scale
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UnsupportedVersionError {
+    pub version: u32,
+}
+
+impl Error for UnsupportedVersionError {}
+
+impl fmt::Display for UnsupportedVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The model format version {} is newer than the supported version {}",
+            self.version, VERSION
+        )
+    }
+}
@@ -1,16 +1,95 @@
+use std::num::Wrapping;
+
 use anyhow::bail;
-use ndarray::{Array, Array1, Array2, Dimension, Ix, Ix1, Ix2};
+use ndarray::{
+    Array, Array1, Array2, Array3, Array4, ArrayD, Dimension, Ix, Ix1, Ix2, Ix3, Ix4, IxDyn, Zip,
+};
+use num_bigint::BigUint;
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    message::{DotProductInteraction, HadamardProductInteraction, Message, IO},
+    com,
+    convolution::{self, convolve, depthwise_convolve, transposed_convolve},
+    message::{
+        ConvolutionInteraction, DotProductInteraction, EncryptedOperand, EncryptedProduct,
+        HadamardProductInteraction, MatrixProductInteraction, Message, IO,
+    },
+    paillier::{self, PaillierPrivateKey, PaillierPublicKey},
+    truncation,
     unexpected_message_error::UnexpectedMessageError,
     Com,
 };
 
-pub(crate) struct MultiplicationTripletShare<DimA: Dimension, DimB: Dimension> {
+/// Statistical security parameter for masking homomorphically computed products.
+const STATISTICAL_SECURITY_BITS: u64 = 40;
+
+pub(crate) struct MultiplicationTripletShare<
+    DimA: Dimension,
+    DimB: Dimension,
+    DimAB: Dimension = Ix1,
+> {
     a_share: Array<Com, DimA>,
     b_share: Array<Com, DimB>,
-    ab_share: Array1<Com>,
+    ab_share: Array<Com, DimAB>,
+}
+
+/// A bilinear operation, whose multiplication triplets the offline phase generates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BilinearOperation {
+    /// A vector by a matrix
+    DotProduct,
+    /// Two vectors, element-wise
+    Hadamard,
+    /// A matrix by a matrix
+    MatrixProduct,
+    /// An input by kernels, see `convolution::convolve`
+    Convolution { stride: usize },
+    /// See `convolution::depthwise_convolve`
+    DepthwiseConvolution { stride: usize },
+    /// See `convolution::transposed_convolve`
+    TransposedConvolution { stride: usize },
+}
+
+/// The operation of a multiplication triplet and the shapes of its operands.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TripletShape {
+    pub operation: BilinearOperation,
+    pub a_shape: Vec<usize>,
+    pub b_shape: Vec<usize>,
+}
+
+impl<DimA: Dimension, DimB: Dimension, DimAB: Dimension>
+    MultiplicationTripletShare<DimA, DimB, DimAB>
+{
+    /// Completes a multiplication once e = x - a and f = y - b are reconstructed.
+    ///
+    /// The products are computed in the ring and truncated once, at the end, so the triplet's
+    /// shares may wrap around, as random ones do.
+    fn complete<const PARTY: bool>(
+        &self,
+        e: &Array<Com, DimA>,
+        f: &Array<Com, DimB>,
+        product: impl Fn(&Array<Com, DimA>, &Array<Com, DimB>) -> Array<Com, DimAB>,
+    ) -> Array<Com, DimAB> {
+        let mut product_share =
+            product(&self.a_share, f) + product(e, &self.b_share) + &self.ab_share;
+        if PARTY {
+            product_share = product_share + product(e, f);
+        }
+        truncation::truncate::<PARTY, _>(&product_share, com::frac_bits())
+    }
+
+    /// Converts the shares to the given dimensions.
+    pub(crate) fn into_dimensionality<DimA2: Dimension, DimB2: Dimension, DimAB2: Dimension>(
+        self,
+    ) -> anyhow::Result<MultiplicationTripletShare<DimA2, DimB2, DimAB2>> {
+        Ok(MultiplicationTripletShare {
+            a_share: self.a_share.into_dimensionality()?,
+            b_share: self.b_share.into_dimensionality()?,
+            ab_share: self.ab_share.into_dimensionality()?,
+        })
+    }
 }
 
 impl MultiplicationTripletShare<Ix1, Ix1> {
@@ -57,12 +136,7 @@ impl MultiplicationTripletShare<Ix1, Ix1> {
         let f = our_ef_shares.f_share + their_ef_shares.f_share;
 
         // Complete the computation
-        // TODO take the common part out
-        Ok(if PARTY {
-            &e * &f + &self.a_share * &f + &e * &self.b_share + &self.ab_share
-        } else {
-            &self.a_share * &f + &e * &self.b_share + &self.ab_share
-        })
+        Ok(self.complete::<PARTY>(&e, &f, |x, y| from_ring(to_ring(x) * to_ring(y))))
     }
 
     pub(crate) fn new(n: Ix) -> Self {
@@ -110,18 +184,679 @@ impl MultiplicationTripletShare<Ix1, Ix2> {
         let f = our_ef_shares.f_share + their_ef_shares.f_share;
 
         // Complete the calculation
-        Ok(if PARTY {
-            e.dot(&f) + self.a_share.dot(&f) + e.dot(&self.b_share) + &self.ab_share
+        Ok(self.complete::<PARTY>(&e, &f, ring_dot))
+    }
+}
+
+impl MultiplicationTripletShare<Ix2, Ix2, Ix2> {
+    /// Like `dot_product`, but multiplies many vectors, the rows of x, by the same matrix at once.
+    ///
+    /// # Parameters
+    /// - `x_share`: a share of the first operand, (n, k)
+    /// - `y_share`: a share of the second operand, (k, m)
+    /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
+    ///
+    /// # Warnings
+    /// Multiplication triplets shall not be re-used. To multiply a new pair, generate a triplet.
+    pub(crate) async fn matrix_product<const PARTY: bool>(
+        &self,
+        x_share: &Array2<Com>,
+        y_share: &Array2<Com>,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<Array2<Com>> {
+        // 'Mask' x_share and y_share as e_share and f_share
+        let our_ef_shares = MatrixProductInteraction {
+            e_share: x_share - &self.a_share,
+            f_share: y_share - &self.b_share,
+        };
+
+        // Send our e and f shares to the other party
+        sender
+            .send(Message::MatrixProductInteraction(our_ef_shares.clone()))
+            .await?;
+
+        // Receive the e and f shares of the other party
+        let their_ef_shares: MatrixProductInteraction;
+        if let Some(Message::MatrixProductInteraction(shares)) = receiver.recv().await {
+            their_ef_shares = shares;
         } else {
-            self.a_share.dot(&f) + e.dot(&self.b_share) + &self.ab_share
-        })
+            bail!(UnexpectedMessageError {});
+        }
+
+        // Reconstruct e and f
+        let e = our_ef_shares.e_share + their_ef_shares.e_share;
+        let f = our_ef_shares.f_share + their_ef_shares.f_share;
+
+        // Complete the calculation
+        Ok(self.complete::<PARTY>(&e, &f, |x, y| from_ring(to_ring(x).dot(&to_ring(y)))))
     }
 
-    pub(crate) fn new(k: Ix, m: Ix) -> Self {
+    pub(crate) fn new(n: Ix, k: Ix, m: Ix) -> Self {
         MultiplicationTripletShare {
-            a_share: Array1::<Com>::zeros(k),
+            a_share: Array2::<Com>::zeros((n, k)),
             b_share: Array2::<Com>::zeros((k, m)),
-            ab_share: Array1::<Com>::zeros(m),
+            ab_share: Array2::<Com>::zeros((n, m)),
         }
     }
 }
+
+impl MultiplicationTripletShare<Ix3, Ix4, Ix3> {
+    /// Convolution using Beaver's triplets shaped for convolutions, so the masked input and the
+    /// masked kernels are exchanged as they are rather than as im2col-expanded matrices.
+    ///
+    /// # Parameters
+    /// - `x_share`: a share of the already-padded input, (input channels, height, width)
+    /// - `k_share`: a share of the kernels, (output channels, input channels, kernel height, kernel width)
+    /// - `stride`: the stride of the convolution
+    /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
+    ///
+    /// # Warnings
+    /// Multiplication triplets shall not be re-used. To multiply a new pair, generate a triplet.
+    pub(crate) async fn convolution<const PARTY: bool>(
+        &self,
+        x_share: &Array3<Com>,
+        k_share: &Array4<Com>,
+        stride: usize,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<Array3<Com>> {
+        self.bilinear::<PARTY>(
+            x_share,
+            k_share,
+            |x, k| from_ring(convolve(&to_ring(x).view(), &to_ring(k).view(), stride)),
+            (sender, receiver),
+        )
+        .await
+    }
+
+    /// Like `convolution`, but convolves every input channel with its own kernels only.
+    ///
+    /// Only the masked input and the masked per-channel kernels are exchanged, so the input is
+    /// sent once for all the kernels of its channel.
+    ///
+    /// # Parameters
+    /// - `x_share`: a share of the already-padded input, (channels, height, width)
+    /// - `k_share`: a share of the kernels, (channels, depth multiplier, kernel height, kernel width)
+    /// - `stride`: the stride of the convolution
+    /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
+    ///
+    /// # Warnings
+    /// Multiplication triplets shall not be re-used. To multiply a new pair, generate a triplet.
+    pub(crate) async fn depthwise_convolution<const PARTY: bool>(
+        &self,
+        x_share: &Array3<Com>,
+        k_share: &Array4<Com>,
+        stride: usize,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<Array3<Com>> {
+        self.bilinear::<PARTY>(
+            x_share,
+            k_share,
+            |x, k| {
+                from_ring(depthwise_convolve(
+                    &to_ring(x).view(),
+                    &to_ring(k).view(),
+                    stride,
+                ))
+            },
+            (sender, receiver),
+        )
+        .await
+    }
+
+    /// Like `convolution`, but computes a transposed convolution, whose output is larger than its
+    /// input.
+    ///
+    /// # Parameters
+    /// - `x_share`: a share of the input, (input channels, height, width)
+    /// - `k_share`: a share of the kernels, (input channels, output channels, kernel height, kernel width)
+    /// - `stride`: the stride of the convolution
+    /// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the server. Messages may arrive out-of-order.
+    ///
+    /// # Warnings
+    /// Multiplication triplets shall not be re-used. To multiply a new pair, generate a triplet.
+    pub(crate) async fn transposed_convolution<const PARTY: bool>(
+        &self,
+        x_share: &Array3<Com>,
+        k_share: &Array4<Com>,
+        stride: usize,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<Array3<Com>> {
+        self.bilinear::<PARTY>(
+            x_share,
+            k_share,
+            |x, k| {
+                from_ring(transposed_convolve(
+                    &to_ring(x).view(),
+                    &to_ring(k).view(),
+                    stride,
+                ))
+            },
+            (sender, receiver),
+        )
+        .await
+    }
+
+    /// Evaluates a bilinear operation, such as a convolution, of an input and kernels using the
+    /// triplet. The operation should be in the ring.
+    async fn bilinear<const PARTY: bool>(
+        &self,
+        x_share: &Array3<Com>,
+        k_share: &Array4<Com>,
+        operation: impl Fn(&Array3<Com>, &Array4<Com>) -> Array3<Com>,
+        (sender, receiver): IO<'_>,
+    ) -> anyhow::Result<Array3<Com>> {
+        // 'Mask' x_share and k_share as e_share and f_share
+        let our_ef_shares = ConvolutionInteraction {
+            e_share: x_share - &self.a_share,
+            f_share: k_share - &self.b_share,
+        };
+
+        // Send our e and f shares to the other party
+        sender
+            .send(Message::ConvolutionInteraction(our_ef_shares.clone()))
+            .await?;
+
+        // Receive the e and f shares of the other party
+        let their_ef_shares: ConvolutionInteraction;
+        if let Some(Message::ConvolutionInteraction(shares)) = receiver.recv().await {
+            their_ef_shares = shares;
+        } else {
+            bail!(UnexpectedMessageError {});
+        }
+
+        // Reconstruct e and f
+        let e = our_ef_shares.e_share + their_ef_shares.e_share;
+        let f = our_ef_shares.f_share + their_ef_shares.f_share;
+
+        // Complete the calculation, convolution is bilinear just like multiplication
+        Ok(self.complete::<PARTY>(&e, &f, operation))
+    }
+}
+
+impl MultiplicationTripletShare<IxDyn, IxDyn, IxDyn> {
+    /// A triplet whose shares are all 0, which is only good for planning the offline phase.
+    pub(crate) fn zeros(shape: &TripletShape) -> anyhow::Result<Self> {
+        Ok(MultiplicationTripletShare {
+            a_share: ArrayD::zeros(IxDyn(&shape.a_shape)),
+            b_share: ArrayD::zeros(IxDyn(&shape.b_shape)),
+            ab_share: ArrayD::zeros(IxDyn(&shape.output_shape()?)),
+        })
+    }
+
+    /// Deals a triplet locally, as a trusted dealer would, to test protocols without running the
+    /// offline phase.
+    #[cfg(test)]
+    pub(crate) fn deal(
+        shape: &TripletShape,
+        rng: &dyn SecureRandom,
+    ) -> anyhow::Result<(Self, Self)> {
+        use crate::split::Split;
+
+        let a = com::sample(IxDyn(&shape.a_shape), rng);
+        let b = com::sample(IxDyn(&shape.b_shape), rng);
+        let ab = shape.operation.evaluate(&a, &b)?;
+        let (a_shares, b_shares, ab_shares) = (a.split(rng), b.split(rng), ab.split(rng));
+
+        Ok((
+            MultiplicationTripletShare {
+                a_share: a_shares.0,
+                b_share: b_shares.0,
+                ab_share: ab_shares.0,
+            },
+            MultiplicationTripletShare {
+                a_share: a_shares.1,
+                b_share: b_shares.1,
+                ab_share: ab_shares.1,
+            },
+        ))
+    }
+
+    /// Generates a triplet without a dealer using the additively homomorphic Paillier cryptosystem.
+    ///
+    /// Each party samples its own shares of a and b, and the shares of the cross terms are
+    /// computed by [`cross_terms`]. Therefore, the communication is O(|a| + |ab|) ciphertexts
+    /// rather than O(|a| · |b|).
+    ///
+    /// The shares of ab are of the product in the ring, i.e. not truncated, as the
+    /// multiplications expect.
+    ///
+    /// # Parameters
+    /// - `shape`: the operation and the shapes of its operands
+    /// - `keys`: our Paillier key pair and the public key of the other party
+    /// - `io`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+    /// - `rng`: A secure random number generator.
+    pub(crate) async fn generate(
+        shape: &TripletShape,
+        keys: (&PaillierPrivateKey, &PaillierPublicKey),
+        io: IO<'_>,
+        rng: &dyn SecureRandom,
+    ) -> anyhow::Result<Self> {
+        let a_share = com::sample(IxDyn(&shape.a_shape), rng);
+        let b_share = com::sample(IxDyn(&shape.b_shape), rng);
+
+        // Add our local term operation(a_i, b_i)
+        let cross_terms_share =
+            cross_terms(&shape.operation, &a_share, &b_share, keys, io, rng).await?;
+        let ab_share = shape.operation.evaluate(&a_share, &b_share)? + &cross_terms_share;
+
+        Ok(MultiplicationTripletShare {
+            a_share,
+            b_share,
+            ab_share,
+        })
+    }
+}
+
+/// Computes shares of the cross terms operation(a_0, b_1) + operation(a_1, b_0) of a product of
+/// shared operands in the ring, without revealing the operands.
+///
+/// Our share of a is encrypted under our key, the other party applies the operation with its
+/// share of b homomorphically, which is possible as the operation is linear in a, and masks the
+/// result. Both parties do this at once, so it takes one round.
+///
+/// # Parameters
+/// - `operation`: the bilinear operation
+/// - `a_share`, `b_share`: our shares of the operands
+/// - `(our_key, their_key)`: our Paillier key pair and the public key of the other party
+/// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+/// - `rng`: A secure random number generator.
+pub(crate) async fn cross_terms(
+    operation: &BilinearOperation,
+    a_share: &ArrayD<Com>,
+    b_share: &ArrayD<Com>,
+    (our_key, their_key): (&PaillierPrivateKey, &PaillierPublicKey),
+    (sender, receiver): IO<'_>,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<ArrayD<Com>> {
+    // Send the other party our share of a, encrypted
+    sender
+        .send(Message::EncryptedOperand(encrypt_operand(
+            &Array1::from_iter(a_share.iter().copied()),
+            &our_key.public_key,
+            rng,
+        )))
+        .await?;
+
+    // Receive their share of a
+    let their_a_share = if let Some(Message::EncryptedOperand(contents)) = receiver.recv().await {
+        contents
+    } else {
+        bail!(UnexpectedMessageError {});
+    };
+
+    // Compute Enc(operation(their a share, our b share) + s) and keep -s
+    let columns = operation.linear_map(a_share.shape(), b_share)?;
+    let (encrypted_product, mut cross_terms_share) =
+        multiply_encrypted_columns(&their_a_share, a_share.len(), &columns, their_key, rng)?;
+    sender
+        .send(Message::EncryptedProduct(encrypted_product))
+        .await?;
+
+    // Receive Enc(operation(our a share, their b share) + their s)
+    let encrypted_product = if let Some(Message::EncryptedProduct(contents)) = receiver.recv().await
+    {
+        contents
+    } else {
+        bail!(UnexpectedMessageError {});
+    };
+    let their_cross_terms_share = decrypt_product(&encrypted_product, columns.len(), our_key)?;
+    Zip::from(&mut cross_terms_share)
+        .and(&their_cross_terms_share)
+        .for_each(|ours, theirs| *ours = ours.wrapping_add(*theirs));
+
+    // Only the lower 32 bits of the shares of the cross terms matter in the ring
+    Ok(ArrayD::from_shape_vec(
+        IxDyn(&operation.output_shape(a_share.shape(), b_share.shape())?),
+        cross_terms_share
+            .iter()
+            .map(|&x| Com::from_bits(x as i32))
+            .collect(),
+    )?)
+}
+
+impl BilinearOperation {
+    /// The shape of the product of operands of the given shapes.
+    pub(crate) fn output_shape(&self, a_shape: &[Ix], b_shape: &[Ix]) -> anyhow::Result<Vec<Ix>> {
+        let ranks = match self {
+            BilinearOperation::DotProduct => (1, 2),
+            BilinearOperation::Hadamard => (1, 1),
+            BilinearOperation::MatrixProduct => (2, 2),
+            _ => (3, 4),
+        };
+        if (a_shape.len(), b_shape.len()) != ranks {
+            bail!(
+                "The operands of {:?} should be {}- and {}-dimensional",
+                self,
+                ranks.0,
+                ranks.1
+            );
+        }
+        let matching = match self {
+            BilinearOperation::Hadamard => a_shape == b_shape,
+            BilinearOperation::MatrixProduct => a_shape[1] == b_shape[0],
+            BilinearOperation::Convolution { .. } => a_shape[0] == b_shape[1],
+            _ => a_shape[0] == b_shape[0],
+        };
+        if !matching {
+            bail!(
+                "The operands of {:?} of shapes {:?} and {:?} do not match",
+                self,
+                a_shape,
+                b_shape
+            );
+        }
+
+        Ok(match *self {
+            BilinearOperation::DotProduct => vec![b_shape[1]],
+            BilinearOperation::Hadamard => a_shape.to_vec(),
+            BilinearOperation::MatrixProduct => vec![a_shape[0], b_shape[1]],
+            BilinearOperation::Convolution { stride } => {
+                let (height, width) = convolution::output_size(
+                    (a_shape[1], a_shape[2]),
+                    (b_shape[2], b_shape[3]),
+                    stride,
+                );
+                vec![b_shape[0], height, width]
+            }
+            BilinearOperation::DepthwiseConvolution { stride } => {
+                let (height, width) = convolution::output_size(
+                    (a_shape[1], a_shape[2]),
+                    (b_shape[2], b_shape[3]),
+                    stride,
+                );
+                vec![b_shape[0] * b_shape[1], height, width]
+            }
+            BilinearOperation::TransposedConvolution { stride } => {
+                let (height, width) = convolution::transposed_output_size(
+                    (a_shape[1], a_shape[2]),
+                    (b_shape[2], b_shape[3]),
+                    stride,
+                );
+                vec![b_shape[1], height, width]
+            }
+        })
+    }
+
+    /// Evaluates the operation in the ring, i.e. on the bits of the operands, without truncating.
+    pub(crate) fn evaluate(&self, x: &ArrayD<Com>, y: &ArrayD<Com>) -> anyhow::Result<ArrayD<Com>> {
+        self.output_shape(x.shape(), y.shape())?;
+        let (x, y) = (to_ring(x), to_ring(y));
+        let product = match *self {
+            BilinearOperation::DotProduct => x
+                .into_dimensionality::<Ix1>()?
+                .dot(&y.into_dimensionality::<Ix2>()?)
+                .into_dyn(),
+            BilinearOperation::Hadamard => x * y,
+            BilinearOperation::MatrixProduct => x
+                .into_dimensionality::<Ix2>()?
+                .dot(&y.into_dimensionality::<Ix2>()?)
+                .into_dyn(),
+            BilinearOperation::Convolution { stride } => convolve(
+                &x.into_dimensionality::<Ix3>()?.view(),
+                &y.into_dimensionality::<Ix4>()?.view(),
+                stride,
+            )
+            .into_dyn(),
+            BilinearOperation::DepthwiseConvolution { stride } => depthwise_convolve(
+                &x.into_dimensionality::<Ix3>()?.view(),
+                &y.into_dimensionality::<Ix4>()?.view(),
+                stride,
+            )
+            .into_dyn(),
+            BilinearOperation::TransposedConvolution { stride } => transposed_convolve(
+                &x.into_dimensionality::<Ix3>()?.view(),
+                &y.into_dimensionality::<Ix4>()?.view(),
+                stride,
+            )
+            .into_dyn(),
+        };
+
+        Ok(from_ring(product))
+    }
+
+    /// The linear map a ↦ operation(a, b) for a fixed b, as the (index into a, coefficient) pairs
+    /// which sum to every element of the product, in order. Zero coefficients are left out.
+    fn linear_map(&self, a_shape: &[Ix], b: &ArrayD<Com>) -> anyhow::Result<Vec<Vec<(Ix, Com)>>> {
+        match self {
+            BilinearOperation::Hadamard => {
+                return Ok(b.iter().enumerate().map(|(i, &b)| vec![(i, b)]).collect());
+            }
+            BilinearOperation::DotProduct => {
+                return Ok(b
+                    .view()
+                    .into_dimensionality::<Ix2>()?
+                    .columns()
+                    .into_iter()
+                    .map(|column| column.iter().copied().enumerate().collect())
+                    .collect());
+            }
+            _ => {}
+        }
+
+        // Otherwise, apply the operation to every basis vector of a, the maps are sparse
+        let output_size = self.output_shape(a_shape, b.shape())?.iter().product();
+        let mut columns = vec![vec![]; output_size];
+        let mut basis = ArrayD::<Com>::zeros(IxDyn(a_shape));
+        for i in 0..basis.len() {
+            basis.as_slice_mut().expect("zeros are contiguous")[i] = Com::from_bits(1);
+            let product = self.evaluate(&basis, b)?;
+            basis.as_slice_mut().expect("zeros are contiguous")[i] = Com::ZERO;
+
+            for (column, &coefficient) in columns.iter_mut().zip(&product) {
+                if coefficient != Com::ZERO {
+                    column.push((i, coefficient));
+                }
+            }
+        }
+
+        Ok(columns)
+    }
+}
+
+impl TripletShape {
+    /// The shape of ab.
+    pub(crate) fn output_shape(&self) -> anyhow::Result<Vec<Ix>> {
+        self.operation.output_shape(&self.a_shape, &self.b_shape)
+    }
+}
+
+/// Multiplies a vector by a matrix in the ring, i.e. as integers, without truncating.
+pub(crate) fn ring_dot(x: &Array1<Com>, y: &Array2<Com>) -> Array1<Com> {
+    from_ring(to_ring(x).dot(&to_ring(y)))
+}
+
+/// Maps a tensor to the ring, i.e. to the bits of its elements, so products are not truncated.
+fn to_ring<D: Dimension>(x: &Array<Com, D>) -> Array<Wrapping<i32>, D> {
+    x.map(|x| Wrapping(x.0.to_bits()))
+}
+
+/// Reverts `to_ring`.
+fn from_ring<D: Dimension>(x: Array<Wrapping<i32>, D>) -> Array<Com, D> {
+    x.mapv(|x| Com::from_bits(x.0))
+}
+
+/// Encrypts a vector under our key, for the other party to multiply it by a matrix.
+pub(crate) fn encrypt_operand(
+    x: &Array1<Com>,
+    our_key: &PaillierPublicKey,
+    rng: &dyn SecureRandom,
+) -> EncryptedOperand {
+    EncryptedOperand {
+        ciphertexts: x
+            .iter()
+            .map(|x| our_key.encrypt(&to_residue(x, &our_key.n), rng))
+            .collect(),
+    }
+}
+
+/// Multiplies the other party's encrypted vector by our matrix homomorphically, in the ring, and
+/// masks every element of the product with a random s.
+///
+/// # Returns
+///
+/// The encrypted masked product, for the other party, and -s, our share of the product in
+/// Z_(2^64)
+pub(crate) fn multiply_encrypted(
+    operand: &EncryptedOperand,
+    y: &Array2<Com>,
+    their_key: &PaillierPublicKey,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<(EncryptedProduct, Array1<u64>)> {
+    let columns: Vec<Vec<(Ix, Com)>> = y
+        .columns()
+        .into_iter()
+        .map(|column| column.iter().copied().enumerate().collect())
+        .collect();
+    multiply_encrypted_columns(operand, y.nrows(), &columns, their_key, rng)
+}
+
+/// Like `multiply_encrypted`, but for any linear map of a vector of length k, given as the
+/// (index, coefficient) pairs which sum to every element of the product.
+fn multiply_encrypted_columns(
+    operand: &EncryptedOperand,
+    k: Ix,
+    columns: &[Vec<(Ix, Com)>],
+    their_key: &PaillierPublicKey,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<(EncryptedProduct, Array1<u64>)> {
+    if operand.ciphertexts.len() != k || columns.iter().flatten().any(|&(i, _)| i >= k) {
+        bail!("The encrypted operand does not match the matrix's dimensions");
+    }
+
+    let mask_bits = 2 * 32 + k.max(1).ilog2() as u64 + 1 + STATISTICAL_SECURITY_BITS;
+    let mut product_share = Array1::<u64>::zeros(columns.len());
+    let mut ciphertexts = Vec::with_capacity(columns.len());
+    for (j, column) in columns.iter().enumerate() {
+        let s = paillier::sample_bits(mask_bits, rng);
+        let encrypted_product =
+            column
+                .iter()
+                .fold(their_key.encrypt(&s, rng), |accumulator, (i, y)| {
+                    their_key.add(
+                        &accumulator,
+                        &their_key.multiply(&operand.ciphertexts[*i], &to_residue(y, &their_key.n)),
+                    )
+                });
+        product_share[j] = low_u64(&s).wrapping_neg();
+        ciphertexts.push(encrypted_product);
+    }
+
+    Ok((EncryptedProduct { ciphertexts }, product_share))
+}
+
+/// Decrypts a masked product from `multiply_encrypted`, which is our share of the product in
+/// Z_(2^64).
+pub(crate) fn decrypt_product(
+    product: &EncryptedProduct,
+    m: Ix,
+    our_key: &PaillierPrivateKey,
+) -> anyhow::Result<Array1<u64>> {
+    if product.ciphertexts.len() != m {
+        bail!("The encrypted product does not match the matrix's dimensions");
+    }
+
+    let n = &our_key.public_key.n;
+    product
+        .ciphertexts
+        .iter()
+        .map(|ciphertext| Ok(from_residue(&our_key.decrypt(ciphertext)?, n)))
+        .collect()
+}
+
+fn to_i64(x: &Com) -> i64 {
+    x.0.to_bits() as i64
+}
+
+/// Maps a signed fixed-point number to Z_n.
+fn to_residue(x: &Com, n: &BigUint) -> BigUint {
+    let value = to_i64(x);
+    if value >= 0 {
+        BigUint::from(value as u64)
+    } else {
+        n - value.unsigned_abs()
+    }
+}
+
+/// Maps an element of Z_n (which is assumed to be small in absolute value) to Z_(2^64).
+fn from_residue(x: &BigUint, n: &BigUint) -> u64 {
+    if x > &(n >> 1) {
+        low_u64(&(n - x)).wrapping_neg()
+    } else {
+        low_u64(x)
+    }
+}
+
+fn low_u64(x: &BigUint) -> u64 {
+    x.iter_u64_digits().next().unwrap_or(0)
+}
+
+/// Generates a Paillier key pair and exchanges public keys with the other party.
+pub(crate) async fn exchange_paillier_keys(
+    (sender, receiver): IO<'_>,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<(PaillierPrivateKey, PaillierPublicKey)> {
+    let our_key = PaillierPrivateKey::generate(paillier::KEY_BITS, rng);
+    sender
+        .send(Message::PaillierPublicKey(our_key.public_key.clone()))
+        .await?;
+
+    if let Some(Message::PaillierPublicKey(their_key)) = receiver.recv().await {
+        Ok((our_key, their_key))
+    } else {
+        bail!(UnexpectedMessageError {});
+    }
+}
+
+#[tokio::test]
+async fn test_generated_triplet_is_a_product() {
+    use crate::{reconstruct::Reconstruct, testing};
+
+    let rng = ring::rand::SystemRandom::new();
+    let (mut server, mut client, log) = testing::connect();
+    let (server_keys, client_keys) = tokio::join!(
+        exchange_paillier_keys(server.io(), &rng),
+        exchange_paillier_keys(client.io(), &rng),
+    );
+    let (server_keys, client_keys) = (server_keys.unwrap(), client_keys.unwrap());
+    let shapes = [
+        TripletShape {
+            operation: BilinearOperation::DotProduct,
+            a_shape: vec![3],
+            b_shape: vec![3, 2],
+        },
+        TripletShape {
+            operation: BilinearOperation::Convolution { stride: 2 },
+            a_shape: vec![2, 5, 5],
+            b_shape: vec![3, 2, 3, 3],
+        },
+    ];
+    for shape in &shapes {
+        let (server_triplet, client_triplet) = tokio::join!(
+            MultiplicationTripletShare::generate(
+                shape,
+                (&server_keys.0, &server_keys.1),
+                server.io(),
+                &rng
+            ),
+            MultiplicationTripletShare::generate(
+                shape,
+                (&client_keys.0, &client_keys.1),
+                client.io(),
+                &rng
+            ),
+        );
+        let (server_triplet, client_triplet) = (server_triplet.unwrap(), client_triplet.unwrap());
+
+        // c = operation(a, b) in the ring
+        let a = ArrayD::reconstruct((&server_triplet.a_share, &client_triplet.a_share));
+        let b = ArrayD::reconstruct((&server_triplet.b_share, &client_triplet.b_share));
+        let ab = ArrayD::reconstruct((&server_triplet.ab_share, &client_triplet.ab_share));
+        assert_eq!(ab, shape.operation.evaluate(&a, &b).unwrap());
+    }
+
+    // Only the public keys and one vector of ciphertexts each way per triplet, which does not
+    // grow with |a| · |b|
+    assert_eq!(log.count(true, "EncryptedOperand"), 2);
+    assert_eq!(log.count(true, "EncryptedProduct"), 2);
+    assert_eq!(log.len(), 10);
+}
@@ -0,0 +1,376 @@
+//! The offline phase, which generates the correlated randomness of the online phase before the
+//! input is known.
+//!
+//! Both parties run it on their shares of the same model, so the online phase consumes the
+//! material in the order it was generated.
+//!
+//! The multiplication triplets, the keys of BitXA and the correlations of the private dense
+//! layers are generated by both parties with Paillier, so neither learns the other's masks. The
+//! keys of DReLU, however, are dealt by the server, which therefore has to be trusted as a
+//! dealer: it knows the masks of every DReLU, and the signed comparison is only a placeholder
+//! which the server evaluates in the clear (see `signed_comparison`). Hence DReLU does not hide
+//! the signs of the activations from the server yet.
+
+use std::collections::VecDeque;
+
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, ArrayD, Dimension, Ix, Ix1, Ix2, Ix3, Ix4, IxDyn};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    bitxa::{generate_bitxa_key, BitXAKey},
+    layer::{
+        dense_layer::PrivateDenseCorrelation,
+        relu::drelu::{generate_drelu_keys, DReLUKey},
+        LayerShare,
+    },
+    message::{Message, IO},
+    model::{ModelShare, Operation},
+    multiplication_triplet_share::{
+        exchange_paillier_keys, BilinearOperation, MultiplicationTripletShare, TripletShape,
+    },
+    unexpected_message_error::UnexpectedMessageError,
+};
+
+/// What the offline phase generates for one inference, which only depends on the model and on
+/// the shape of the input.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OfflinePlan {
+    /// The number of elements of the BitXA keys
+    pub bitxa_elements: usize,
+    /// The number of elements of the DReLU keys
+    pub drelu_elements: usize,
+    /// The multiplication triplets, in the order the online phase takes them
+    pub triplets: Vec<TripletShape>,
+}
+
+/// The correlated randomness of one inference, which shall not be re-used.
+#[derive(Default)]
+pub struct OfflineMaterial {
+    triplets: VecDeque<(
+        TripletShape,
+        MultiplicationTripletShare<IxDyn, IxDyn, IxDyn>,
+    )>,
+    private_dense_correlations: VecDeque<PrivateDenseCorrelation>,
+    /// The keys of every BitXA of the inference, concatenated
+    bitxa_key: BitXAKey,
+    /// The number of elements of `bitxa_key` which were already taken
+    bitxa_offset: usize,
+    /// The keys of every DReLU of the inference, concatenated
+    drelu_key: DReLUKey,
+    /// The number of elements of `drelu_key` which were already taken
+    drelu_offset: usize,
+    /// Whether the material is trivial, to plan how much of it an inference takes
+    counting: bool,
+    /// The triplets which were already taken, when counting
+    counted_triplets: Vec<TripletShape>,
+}
+
+impl OfflineMaterial {
+    /// Takes the next triplet, which should be of the given shape.
+    fn take_triplet<DimA: Dimension, DimB: Dimension, DimAB: Dimension>(
+        &mut self,
+        shape: TripletShape,
+    ) -> anyhow::Result<MultiplicationTripletShare<DimA, DimB, DimAB>> {
+        let triplet = if self.counting {
+            let triplet = MultiplicationTripletShare::zeros(&shape)?;
+            self.counted_triplets.push(shape);
+            triplet
+        } else {
+            let (expected_shape, triplet) = self
+                .triplets
+                .pop_front()
+                .context("The offline phase generated too few triplets")?;
+            if expected_shape != shape {
+                bail!(
+                    "The next triplet is for {:?} rather than {:?}",
+                    expected_shape,
+                    shape
+                );
+            }
+            triplet
+        };
+        triplet.into_dimensionality()
+    }
+
+    /// Takes the next triplet, which should be for multiplying a vector by a (k, m) matrix.
+    pub(crate) fn take_dot_product_triplet(
+        &mut self,
+        k: Ix,
+        m: Ix,
+    ) -> anyhow::Result<MultiplicationTripletShare<Ix1, Ix2>> {
+        self.take_triplet(TripletShape {
+            operation: BilinearOperation::DotProduct,
+            a_shape: vec![k],
+            b_shape: vec![k, m],
+        })
+    }
+
+    /// Takes the next triplet, which should be for multiplying vectors of length n element-wise.
+    pub(crate) fn take_hadamard_triplet(
+        &mut self,
+        n: Ix,
+    ) -> anyhow::Result<MultiplicationTripletShare<Ix1, Ix1>> {
+        self.take_triplet(TripletShape {
+            operation: BilinearOperation::Hadamard,
+            a_shape: vec![n],
+            b_shape: vec![n],
+        })
+    }
+
+    /// Takes the next triplet, which should be for multiplying a (n, k) matrix by a (k, m) one.
+    pub(crate) fn take_matrix_product_triplet(
+        &mut self,
+        n: Ix,
+        k: Ix,
+        m: Ix,
+    ) -> anyhow::Result<MultiplicationTripletShare<Ix2, Ix2, Ix2>> {
+        self.take_triplet(TripletShape {
+            operation: BilinearOperation::MatrixProduct,
+            a_shape: vec![n, k],
+            b_shape: vec![k, m],
+        })
+    }
+
+    /// Takes the next triplet, which should be of a convolution-like operation of an input and
+    /// kernels of the given shapes.
+    pub(crate) fn take_convolution_triplet(
+        &mut self,
+        operation: BilinearOperation,
+        input_shape: (Ix, Ix, Ix),
+        kernels_shape: (Ix, Ix, Ix, Ix),
+    ) -> anyhow::Result<MultiplicationTripletShare<Ix3, Ix4, Ix3>> {
+        self.take_triplet(TripletShape {
+            operation,
+            a_shape: vec![input_shape.0, input_shape.1, input_shape.2],
+            b_shape: vec![
+                kernels_shape.0,
+                kernels_shape.1,
+                kernels_shape.2,
+                kernels_shape.3,
+            ],
+        })
+    }
+
+    /// Takes the randomness of the next private dense layer, which should be of the given size.
+    pub(crate) fn take_private_dense_correlation(
+        &mut self,
+        input_size: usize,
+        output_size: usize,
+    ) -> anyhow::Result<PrivateDenseCorrelation> {
+        if self.counting {
+            return Ok(PrivateDenseCorrelation {
+                r: Some(Array1::zeros(input_size)),
+                rw_share: Array1::zeros(output_size),
+            });
+        }
+
+        let correlation = self
+            .private_dense_correlations
+            .pop_front()
+            .context("The offline phase generated too few private dense layer correlations")?;
+        if correlation.rw_share.len() != output_size
+            || correlation
+                .r
+                .as_ref()
+                .is_some_and(|r| r.len() != input_size)
+        {
+            bail!("The next private dense layer correlation does not match the layer's dimensions");
+        }
+        Ok(correlation)
+    }
+
+    /// Takes the keys of the next `n` elements of BitXA.
+    pub(crate) fn take_bitxa_key(&mut self, n: usize) -> anyhow::Result<BitXAKey> {
+        let end = self.bitxa_offset + n;
+        if self.counting {
+            self.bitxa_offset = end;
+            return Ok(BitXAKey::zeros(n));
+        }
+
+        if end > self.bitxa_key.len() {
+            bail!(
+                "The offline phase dealt {} elements of BitXA keys, which are too few",
+                self.bitxa_key.len()
+            );
+        }
+        let key = self.bitxa_key.slice(self.bitxa_offset, end);
+        self.bitxa_offset = end;
+        Ok(key)
+    }
+
+    /// Takes the keys of the next `n` elements of DReLU.
+    pub(crate) fn take_drelu_key(&mut self, n: usize) -> anyhow::Result<DReLUKey> {
+        let end = self.drelu_offset + n;
+        if self.counting {
+            self.drelu_offset = end;
+            return Ok(DReLUKey::zeros(n));
+        }
+
+        if end > self.drelu_key.len() {
+            bail!(
+                "The offline phase dealt {} elements of DReLU keys, which are too few",
+                self.drelu_key.len()
+            );
+        }
+        let key = self.drelu_key.slice(self.drelu_offset, end);
+        self.drelu_offset = end;
+        Ok(key)
+    }
+
+    /// Trivial material, which records what an inference takes, see `plan`.
+    pub(crate) fn counting() -> Self {
+        OfflineMaterial {
+            counting: true,
+            ..Default::default()
+        }
+    }
+
+    /// What an inference took of counting material.
+    pub(crate) fn counted(self) -> OfflinePlan {
+        OfflinePlan {
+            bitxa_elements: self.bitxa_offset,
+            drelu_elements: self.drelu_offset,
+            triplets: self.counted_triplets,
+        }
+    }
+
+    /// Deals the material of `plan` locally, as a trusted dealer would, to test protocols without
+    /// running the offline phase.
+    #[cfg(test)]
+    pub(crate) fn deal(plan: &OfflinePlan, rng: &dyn SecureRandom) -> (Self, Self) {
+        let bitxa_keys = crate::bitxa::generate_bitxa_keys(plan.bitxa_elements, rng);
+        let drelu_keys = generate_drelu_keys(plan.drelu_elements, rng);
+        let mut materials = (
+            OfflineMaterial {
+                bitxa_key: bitxa_keys.0,
+                drelu_key: drelu_keys.0,
+                ..Default::default()
+            },
+            OfflineMaterial {
+                bitxa_key: bitxa_keys.1,
+                drelu_key: drelu_keys.1,
+                ..Default::default()
+            },
+        );
+        for shape in &plan.triplets {
+            let triplets = MultiplicationTripletShare::deal(shape, rng).unwrap();
+            materials.0.triplets.push_back((shape.clone(), triplets.0));
+            materials.1.triplets.push_back((shape.clone(), triplets.1));
+        }
+        materials
+    }
+}
+
+/// Plans the offline phase of an inference, by inferring the model on zeros in memory with
+/// trivial material. Only the shape of the input matters.
+pub async fn plan(
+    model_shares: (&ModelShare, &ModelShare),
+    input_shape: &[usize],
+) -> anyhow::Result<OfflinePlan> {
+    let (server_sender, mut client_receiver) = mpsc::channel(1024);
+    let (client_sender, mut server_receiver) = mpsc::channel(1024);
+    let mut server_material = OfflineMaterial::counting();
+    let mut client_material = OfflineMaterial::counting();
+
+    let input_share = ArrayD::zeros(IxDyn(input_shape));
+    let (server_output_share, client_output_share) = tokio::join!(
+        model_shares.0.infer::<true>(
+            input_share.clone(),
+            &mut server_material,
+            (&server_sender, &mut server_receiver)
+        ),
+        model_shares.1.infer::<false>(
+            input_share,
+            &mut client_material,
+            (&client_sender, &mut client_receiver)
+        ),
+    );
+    server_output_share?;
+    client_output_share?;
+
+    Ok(server_material.counted())
+}
+
+/// Runs the offline phase of a model share.
+///
+/// The server tells the client the plan (see `plan`), which only depends on shapes the client
+/// knows anyway; the client ignores `plan`. The server deals the keys of DReLU, and everything
+/// else is generated with Paillier, see the module's documentation for the trust assumption.
+pub(crate) async fn prepare<const PARTY: bool>(
+    model_share: &ModelShare,
+    plan: OfflinePlan,
+    (sender, receiver): IO<'_>,
+    rng: &dyn SecureRandom,
+) -> anyhow::Result<OfflineMaterial> {
+    let plan = if PARTY {
+        sender.send(Message::OfflinePlan(plan.clone())).await?;
+        plan
+    } else if let Some(Message::OfflinePlan(plan)) = receiver.recv().await {
+        plan
+    } else {
+        bail!(UnexpectedMessageError {});
+    };
+
+    let mut material = OfflineMaterial::default();
+
+    if plan.drelu_elements > 0 {
+        material.drelu_key = if PARTY {
+            let keys = generate_drelu_keys(plan.drelu_elements, rng);
+            sender.send(Message::DReLUKey(keys.1)).await?;
+            keys.0
+        } else if let Some(Message::DReLUKey(key)) = receiver.recv().await {
+            key
+        } else {
+            bail!(UnexpectedMessageError {});
+        };
+    }
+
+    let uses_private_dense = model_share.nodes.iter().any(|node| {
+        matches!(
+            node.operation,
+            Operation::Layer(LayerShare::PrivateDenseLayerShare(_))
+        )
+    });
+    if plan.bitxa_elements > 0 || uses_private_dense || !plan.triplets.is_empty() {
+        let (our_key, their_key) = exchange_paillier_keys((sender, receiver), rng)
+            .await
+            .context("Failed to exchange Paillier keys")?;
+        if plan.bitxa_elements > 0 {
+            material.bitxa_key = generate_bitxa_key::<PARTY>(
+                plan.bitxa_elements,
+                (&our_key, &their_key),
+                (sender, receiver),
+                rng,
+            )
+            .await
+            .context("Failed to generate the BitXA keys")?;
+        }
+        for node in &model_share.nodes {
+            if let Operation::Layer(LayerShare::PrivateDenseLayerShare(private_dense_layer_share)) =
+                &node.operation
+            {
+                let correlation = private_dense_layer_share
+                    .correlate((&our_key, &their_key), (sender, receiver), rng)
+                    .await
+                    .context("Failed to correlate randomness with the server's weights")?;
+                material.private_dense_correlations.push_back(correlation);
+            }
+        }
+        for shape in plan.triplets {
+            let triplet = MultiplicationTripletShare::generate(
+                &shape,
+                (&our_key, &their_key),
+                (sender, receiver),
+                rng,
+            )
+            .await
+            .with_context(|| format!("Failed to generate a triplet for {:?}", shape.operation))?;
+            material.triplets.push_back((shape, triplet));
+        }
+    }
+
+    Ok(material)
+}
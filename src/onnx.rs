@@ -0,0 +1,654 @@
+//! Imports ONNX models, e.g. as exported by PyTorch, quantizing their weights to `Com`.
+//!
+//! ONNX tensors have a leading batch axis, which neuronveil does not, so axes are shifted by one.
+//! Operators which cannot be mapped to layers are collected into an `UnsupportedOperatorsError`
+//! rather than failing on the first one.
+
+mod proto;
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, Array2, Array4, ArrayD, Ix1, Ix2, Ix4, IxDyn};
+use prost::Message as _;
+
+use crate::{
+    layer::{
+        avg_pool::{AvgPool2DLayer, GlobalAvgPoolLayer},
+        batch_norm::BatchNormLayer,
+        conv2d_layer::Conv2DLayer,
+        conv_transpose2d_layer::ConvTranspose2DLayer,
+        dense_layer::DenseLayer,
+        depthwise_conv2d_layer::DepthwiseConv2DLayer,
+        flatten::FlattenLayer,
+        leaky_relu::LeakyReLULayer,
+        max_pool::MaxPool2DLayer,
+        relu::ReLULayer,
+        relu6::ReLU6Layer,
+        Layer,
+    },
+    model::{Model, Node, Operation, INPUT},
+    quantization::quantize_array,
+};
+use proto::{data_type, AttributeProto, ModelProto, NodeProto, TensorProto};
+
+/// An ONNX node which could not be imported.
+#[derive(Debug, Clone)]
+pub struct UnsupportedOperator {
+    pub node: String,
+    pub op_type: String,
+    pub reason: String,
+}
+
+/// Every node of an ONNX model which could not be imported.
+#[derive(Debug)]
+pub struct UnsupportedOperatorsError {
+    pub operators: Vec<UnsupportedOperator>,
+}
+
+impl Error for UnsupportedOperatorsError {}
+
+impl fmt::Display for UnsupportedOperatorsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The model contains {} unsupported operator(s):",
+            self.operators.len()
+        )?;
+        for operator in &self.operators {
+            write!(
+                f,
+                "\n  - {} ({}): {}",
+                operator.node, operator.op_type, operator.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Imports a serialized ONNX model.
+pub fn import(bytes: &[u8]) -> anyhow::Result<Model> {
+    let model = ModelProto::decode(bytes).context("Failed to decode the ONNX model")?;
+    let graph = model.graph.context("The ONNX model has no graph")?;
+
+    let mut importer = Importer::default();
+    for tensor in graph.initializer {
+        importer.constants.insert(tensor.name.clone(), tensor);
+    }
+    for name in graph
+        .node
+        .iter()
+        .flat_map(|node| &node.input)
+        .chain(graph.output.iter().map(|output| &output.name))
+    {
+        *importer.uses.entry(name.clone()).or_default() += 1;
+    }
+
+    // Older exporters list the initializers as inputs too
+    let input = graph
+        .input
+        .iter()
+        .find(|input| !importer.constants.contains_key(&input.name))
+        .context("The ONNX graph has no input")?;
+    importer
+        .aliases
+        .insert(input.name.clone(), INPUT.to_owned());
+
+    for node in &graph.node {
+        if let Err(reason) = importer.import_node(node) {
+            importer.unsupported.push(UnsupportedOperator {
+                node: node.output.first().cloned().unwrap_or_default(),
+                op_type: node.op_type.clone(),
+                reason,
+            });
+        }
+    }
+
+    if !importer.unsupported.is_empty() {
+        bail!(UnsupportedOperatorsError {
+            operators: importer.unsupported,
+        });
+    }
+
+    let output = graph
+        .output
+        .first()
+        .context("The ONNX graph has no output")?;
+    let output = importer.resolve(&output.name);
+    Model::new(importer.nodes, output).context("The imported graph is invalid")
+}
+
+#[derive(Default)]
+struct Importer {
+    /// Initializers and the outputs of Constant nodes
+    constants: HashMap<String, TensorProto>,
+    /// ONNX tensors which are the output of another node, e.g. of an Identity
+    aliases: HashMap<String, String>,
+    /// The number of nodes (or graph outputs) which take each ONNX tensor
+    uses: HashMap<String, usize>,
+    nodes: Vec<Node<Layer>>,
+    unsupported: Vec<UnsupportedOperator>,
+}
+
+impl Importer {
+    fn resolve(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_owned())
+    }
+
+    fn constant(&self, name: &str) -> Option<&TensorProto> {
+        self.constants.get(name)
+    }
+
+    /// Gets the input at `index` of a node, which should be a constant.
+    fn constant_input(&self, node: &NodeProto, index: usize) -> Result<ArrayD<f32>, String> {
+        let name = node
+            .input
+            .get(index)
+            .ok_or_else(|| format!("Input {} is missing", index + 1))?;
+        let tensor = self
+            .constant(name)
+            .ok_or_else(|| format!("Input '{}' should be a constant", name))?;
+        to_f32_array(tensor)
+    }
+
+    /// Like `constant_input`, but allows optional inputs to be missing.
+    fn optional_constant_input(
+        &self,
+        node: &NodeProto,
+        index: usize,
+    ) -> Result<Option<ArrayD<f32>>, String> {
+        match node.input.get(index) {
+            Some(name) if !name.is_empty() => self.constant_input(node, index).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn push(&mut self, node: &NodeProto, inputs: Vec<String>, operation: Operation<Layer>) {
+        self.nodes.push(Node {
+            name: node.output[0].clone(),
+            inputs: inputs.iter().map(|input| self.resolve(input)).collect(),
+            operation,
+        });
+    }
+
+    fn push_layer(&mut self, node: &NodeProto, layer: Layer) {
+        self.push(node, vec![node.input[0].clone()], Operation::Layer(layer));
+    }
+
+    fn import_node(&mut self, node: &NodeProto) -> Result<(), String> {
+        if !node.domain.is_empty() && node.domain != "ai.onnx" {
+            return Err(format!("The domain '{}' is not supported", node.domain));
+        }
+        if node.output.len() != 1 && !matches!(node.op_type.as_str(), "Dropout") {
+            return Err("Only nodes with one output are supported".to_owned());
+        }
+        if node.input.is_empty() && node.op_type != "Constant" {
+            return Err("The node has no inputs".to_owned());
+        }
+
+        match node.op_type.as_str() {
+            "Constant" => {
+                let tensor = attribute(node, "value")
+                    .and_then(|attribute| attribute.t.clone())
+                    .ok_or("Only tensor constants are supported")?;
+                self.constants.insert(node.output[0].clone(), tensor);
+            }
+            "Identity" | "Dropout" => {
+                let input = self.resolve(&node.input[0]);
+                self.aliases.insert(node.output[0].clone(), input);
+            }
+            "Gemm" => {
+                if int_attribute(node, "transA", 0) != 0 {
+                    return Err("Transposing the activations is not supported".to_owned());
+                }
+                if float_attribute(node, "alpha", 1.0) != 1.0
+                    || float_attribute(node, "beta", 1.0) != 1.0
+                {
+                    return Err("Only alpha = beta = 1 is supported".to_owned());
+                }
+
+                let weights = into_matrix(self.constant_input(node, 1)?)?;
+                let weights = if int_attribute(node, "transB", 0) != 0 {
+                    weights.reversed_axes()
+                } else {
+                    weights
+                };
+                let biases = match self.optional_constant_input(node, 2)? {
+                    Some(biases) => into_biases(biases, weights.ncols())?,
+                    None => Array1::zeros(weights.ncols()),
+                };
+
+                self.push_layer(
+                    node,
+                    Layer::DenseLayer(DenseLayer::new(
+                        quantize_array(&weights),
+                        quantize_array(&biases),
+                    )),
+                );
+            }
+            "MatMul" => {
+                let weights = into_matrix(self.constant_input(node, 1)?)?;
+                let biases = Array1::zeros(weights.ncols());
+
+                self.push_layer(
+                    node,
+                    Layer::DenseLayer(DenseLayer::new(
+                        quantize_array(&weights),
+                        quantize_array(&biases),
+                    )),
+                );
+            }
+            "Add" | "Sum" => self.import_add(node)?,
+            "Relu" => self.push_layer(node, Layer::ReLULayer(ReLULayer {})),
+            "LeakyRelu" => self.push_layer(
+                node,
+                Layer::LeakyReLULayer(LeakyReLULayer {
+                    slope: float_attribute(node, "alpha", 0.01),
+                }),
+            ),
+            "Clip" => {
+                // Since opset 11, the bounds are inputs rather than attributes
+                let bound = |index, name| -> Result<Option<f32>, String> {
+                    Ok(match self.optional_constant_input(node, index)? {
+                        Some(bound) => bound.iter().next().copied(),
+                        None => attribute(node, name).map(|attribute| attribute.f),
+                    })
+                };
+                match (bound(1, "min")?, bound(2, "max")?) {
+                    (Some(min), Some(cap)) if min == 0.0 => {
+                        self.push_layer(node, Layer::ReLU6Layer(ReLU6Layer { cap }))
+                    }
+                    _ => return Err("Only clipping to [0, max] is supported".to_owned()),
+                }
+            }
+            "Conv" => {
+                let (stride, padding) = stride_and_padding(node)?;
+                let kernels = into_kernels(self.constant_input(node, 1)?)?;
+                let biases = match self.optional_constant_input(node, 2)? {
+                    Some(biases) => into_biases(biases, kernels.dim().0)?,
+                    None => Array1::zeros(kernels.dim().0),
+                };
+                let (output_channels, group_channels, kernel_height, kernel_width) = kernels.dim();
+
+                let layer = match int_attribute(node, "group", 1) {
+                    1 => Layer::Conv2DLayer(Conv2DLayer::new(
+                        quantize_array(&kernels),
+                        quantize_array(&biases),
+                        stride,
+                        padding,
+                    )),
+                    groups if group_channels == 1 && output_channels % groups as usize == 0 => {
+                        // (channels · multiplier, 1, ...) has the same layout as (channels, multiplier, ...)
+                        let groups = groups as usize;
+                        let kernels = kernels
+                            .into_shape((
+                                groups,
+                                output_channels / groups,
+                                kernel_height,
+                                kernel_width,
+                            ))
+                            .unwrap();
+                        Layer::DepthwiseConv2DLayer(DepthwiseConv2DLayer::new(
+                            quantize_array(&kernels),
+                            quantize_array(&biases),
+                            stride,
+                            padding,
+                        ))
+                    }
+                    _ => {
+                        return Err(
+                            "Only regular and depthwise convolutions are supported".to_owned()
+                        )
+                    }
+                };
+                self.push_layer(node, layer);
+            }
+            "ConvTranspose" => {
+                if int_attribute(node, "group", 1) != 1 {
+                    return Err("Grouped transposed convolutions are not supported".to_owned());
+                }
+                if ints_attribute(node, "output_padding")
+                    .is_some_and(|padding| padding.iter().any(|&p| p != 0))
+                {
+                    return Err("Output padding is not supported".to_owned());
+                }
+                let (stride, padding) = stride_and_padding(node)?;
+                let kernels = into_kernels(self.constant_input(node, 1)?)?;
+                let biases = match self.optional_constant_input(node, 2)? {
+                    Some(biases) => into_biases(biases, kernels.dim().1)?,
+                    None => Array1::zeros(kernels.dim().1),
+                };
+
+                self.push_layer(
+                    node,
+                    Layer::ConvTranspose2DLayer(ConvTranspose2DLayer::new(
+                        quantize_array(&kernels),
+                        quantize_array(&biases),
+                        stride,
+                        padding,
+                    )),
+                );
+            }
+            "MaxPool" | "AveragePool" => {
+                let (stride, padding) = stride_and_padding(node)?;
+                if padding != 0 {
+                    return Err("Padded pooling is not supported".to_owned());
+                }
+                if int_attribute(node, "ceil_mode", 0) != 0 {
+                    return Err("Ceiling mode is not supported".to_owned());
+                }
+                let pool_size = match ints_attribute(node, "kernel_shape") {
+                    Some([height, width]) if height == width => *height as usize,
+                    _ => return Err("Only square pooling windows are supported".to_owned()),
+                };
+
+                let layer = if node.op_type == "MaxPool" {
+                    Layer::MaxPool2DLayer(MaxPool2DLayer {
+                        pool_size,
+                        stride: Some(stride),
+                    })
+                } else {
+                    Layer::AvgPool2DLayer(AvgPool2DLayer {
+                        pool_size,
+                        stride: Some(stride),
+                    })
+                };
+                self.push_layer(node, layer);
+            }
+            // NOTE the output is (channels) rather than (channels, 1, 1), which a following
+            // Flatten does not mind
+            "GlobalAveragePool" => {
+                self.push_layer(node, Layer::GlobalAvgPoolLayer(GlobalAvgPoolLayer {}))
+            }
+            "Flatten" => {
+                if int_attribute(node, "axis", 1) != 1 {
+                    return Err("Only flattening all but the batch axis is supported".to_owned());
+                }
+                self.push_layer(node, Layer::FlattenLayer(FlattenLayer {}));
+            }
+            "Reshape" => {
+                let shape = node
+                    .input
+                    .get(1)
+                    .and_then(|name| self.constant(name))
+                    .ok_or_else(|| "The shape should be a constant".to_owned())
+                    .and_then(to_i64_vec)?;
+                match shape[..] {
+                    [-1 | 0 | 1, -1] => self.push_layer(node, Layer::FlattenLayer(FlattenLayer {})),
+                    _ => return Err("Only reshaping to (batch, -1) is supported".to_owned()),
+                }
+            }
+            "BatchNormalization" => {
+                // The statistics stay real-valued until they are folded
+                let parameter = |index| -> Result<Array1<f64>, String> {
+                    Ok(into_vector(self.constant_input(node, index)?)?.mapv(f64::from))
+                };
+                let batch_norm_layer = BatchNormLayer {
+                    gamma: parameter(1)?,
+                    beta: parameter(2)?,
+                    mean: parameter(3)?,
+                    variance: parameter(4)?,
+                    epsilon: float_attribute(node, "epsilon", 1e-5),
+                };
+                self.push_layer(node, Layer::BatchNormLayer(batch_norm_layer));
+            }
+            "Concat" => match int_attribute(node, "axis", 1) {
+                axis if axis >= 1 => self.push(
+                    node,
+                    node.input.clone(),
+                    Operation::Concat {
+                        axis: axis as usize - 1,
+                    },
+                ),
+                _ => {
+                    return Err(
+                        "Only concatenating along positive, non-batch axes is supported".to_owned(),
+                    )
+                }
+            },
+            _ => return Err("The operator is not supported".to_owned()),
+        }
+
+        Ok(())
+    }
+
+    /// Imports an addition, either of activations (e.g. a residual connection) or of a constant
+    /// to the output of a linear layer (e.g. the bias of a MatMul), which is folded into it.
+    fn import_add(&mut self, node: &NodeProto) -> Result<(), String> {
+        let (constants, activations): (Vec<&String>, Vec<&String>) = node
+            .input
+            .iter()
+            .partition(|input| self.constants.contains_key(*input));
+
+        match (&constants[..], &activations[..]) {
+            ([], _) => self.push(node, node.input.clone(), Operation::Add),
+            ([constant], [activation]) => {
+                let bias = to_f32_array(self.constant(constant).unwrap())?;
+                let bias: Array1<f64> = bias.iter().map(|&b| b as f64).collect();
+                let producer_name = self.resolve(activation);
+                let sole_use = self.uses.get(*activation).copied() == Some(1);
+
+                let folded = sole_use
+                    && self
+                        .nodes
+                        .iter_mut()
+                        .find(|producer| producer.name == producer_name)
+                        .is_some_and(|producer| match &mut producer.operation {
+                            Operation::Layer(layer) => {
+                                layer.fold_affine(&Array1::ones(bias.len()), &bias)
+                            }
+                            _ => false,
+                        });
+                if !folded {
+                    return Err(
+                        "Adding a constant is only supported right after a linear layer".to_owned(),
+                    );
+                }
+                self.aliases.insert(node.output[0].clone(), producer_name);
+            }
+            _ => return Err("Adding several constants is not supported".to_owned()),
+        }
+
+        Ok(())
+    }
+}
+
+fn attribute<'a>(node: &'a NodeProto, name: &str) -> Option<&'a AttributeProto> {
+    node.attribute
+        .iter()
+        .find(|attribute| attribute.name == name)
+}
+
+fn int_attribute(node: &NodeProto, name: &str, default: i64) -> i64 {
+    attribute(node, name).map_or(default, |attribute| attribute.i)
+}
+
+fn float_attribute(node: &NodeProto, name: &str, default: f32) -> f32 {
+    attribute(node, name).map_or(default, |attribute| attribute.f)
+}
+
+fn ints_attribute<'a>(node: &'a NodeProto, name: &str) -> Option<&'a [i64]> {
+    attribute(node, name).map(|attribute| &attribute.ints[..])
+}
+
+/// Reads the stride and the padding of a 2D convolution or pooling, which should be the same
+/// along both axes.
+fn stride_and_padding(node: &NodeProto) -> Result<(usize, usize), String> {
+    if attribute(node, "auto_pad").is_some_and(|attribute| attribute.s != b"NOTSET") {
+        return Err("Automatic padding is not supported".to_owned());
+    }
+    if ints_attribute(node, "dilations").is_some_and(|dilations| dilations.iter().any(|&d| d != 1))
+    {
+        return Err("Dilated windows are not supported".to_owned());
+    }
+
+    let stride = match ints_attribute(node, "strides") {
+        None => 1,
+        Some([vertical, horizontal]) if vertical == horizontal => *vertical as usize,
+        _ => return Err("Only equal strides along both axes are supported".to_owned()),
+    };
+    let padding = match ints_attribute(node, "pads") {
+        None => 0,
+        Some(pads @ [first, ..]) if pads.len() == 4 && pads.iter().all(|p| p == first) => {
+            *first as usize
+        }
+        _ => return Err("Only equal padding on all sides is supported".to_owned()),
+    };
+
+    Ok((stride, padding))
+}
+
+fn to_f32_array(tensor: &TensorProto) -> Result<ArrayD<f32>, String> {
+    if tensor.data_location == 1 {
+        return Err(format!(
+            "The tensor '{}' is stored externally, which is not supported",
+            tensor.name
+        ));
+    }
+
+    let data: Vec<f32> = match tensor.data_type {
+        data_type::FLOAT if !tensor.raw_data.is_empty() => tensor
+            .raw_data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect(),
+        data_type::FLOAT => tensor.float_data.clone(),
+        data_type::DOUBLE if !tensor.raw_data.is_empty() => tensor
+            .raw_data
+            .chunks_exact(8)
+            .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()) as f32)
+            .collect(),
+        data_type::DOUBLE => tensor.double_data.iter().map(|&x| x as f32).collect(),
+        data_type::INT64 => to_i64_vec(tensor)?.iter().map(|&x| x as f32).collect(),
+        other => {
+            return Err(format!(
+                "The tensor '{}' has data type {}, which is not supported",
+                tensor.name, other
+            ))
+        }
+    };
+
+    let shape: Vec<usize> = tensor.dims.iter().map(|&d| d as usize).collect();
+    ArrayD::from_shape_vec(IxDyn(&shape), data).map_err(|_| {
+        format!(
+            "The tensor '{}' has the wrong number of elements",
+            tensor.name
+        )
+    })
+}
+
+fn to_i64_vec(tensor: &TensorProto) -> Result<Vec<i64>, String> {
+    match tensor.data_type {
+        data_type::INT64 if !tensor.raw_data.is_empty() => Ok(tensor
+            .raw_data
+            .chunks_exact(8)
+            .map(|bytes| i64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()),
+        data_type::INT64 => Ok(tensor.int64_data.clone()),
+        other => Err(format!(
+            "The tensor '{}' has data type {}, expected INT64",
+            tensor.name, other
+        )),
+    }
+}
+
+fn into_matrix(x: ArrayD<f32>) -> Result<Array2<f32>, String> {
+    let shape = x.shape().to_vec();
+    x.into_dimensionality::<Ix2>()
+        .map_err(|_| format!("Expected a matrix, got shape {:?}", shape))
+}
+
+fn into_vector(x: ArrayD<f32>) -> Result<Array1<f32>, String> {
+    let shape = x.shape().to_vec();
+    x.into_dimensionality::<Ix1>()
+        .map_err(|_| format!("Expected a vector, got shape {:?}", shape))
+}
+
+fn into_kernels(x: ArrayD<f32>) -> Result<Array4<f32>, String> {
+    let shape = x.shape().to_vec();
+    x.into_dimensionality::<Ix4>()
+        .map_err(|_| format!("Expected 2D convolution kernels, got shape {:?}", shape))
+}
+
+/// Converts a bias which is broadcast along the outputs, e.g. of shape (channels, 1, 1), into a
+/// vector.
+fn into_biases(x: ArrayD<f32>, outputs: usize) -> Result<Array1<f32>, String> {
+    if x.len() == outputs {
+        Ok(Array1::from_iter(x.iter().copied()))
+    } else if x.len() == 1 {
+        Ok(Array1::from_elem(
+            outputs,
+            x.iter().next().copied().unwrap(),
+        ))
+    } else {
+        Err(format!(
+            "Expected {} biases, got shape {:?}",
+            outputs,
+            x.shape()
+        ))
+    }
+}
+
+#[test]
+fn test_import_folds_matmul_bias() {
+    use crate::Com;
+    use proto::{GraphProto, ValueInfoProto};
+
+    let tensor = |name: &str, dims: Vec<i64>, float_data: Vec<f32>| TensorProto {
+        name: name.to_owned(),
+        dims,
+        data_type: data_type::FLOAT,
+        float_data,
+        ..Default::default()
+    };
+    let node = |op_type: &str, input: &[&str], output: &str| NodeProto {
+        input: input.iter().map(|&name| name.to_owned()).collect(),
+        output: vec![output.to_owned()],
+        op_type: op_type.to_owned(),
+        ..Default::default()
+    };
+    let value = |name: &str| ValueInfoProto {
+        name: name.to_owned(),
+    };
+
+    let model = ModelProto {
+        graph: Some(GraphProto {
+            node: vec![
+                node("MatMul", &["x", "w"], "h"),
+                node("Add", &["h", "b"], "y"),
+                node("Relu", &["y"], "z"),
+                node("Erf", &["z"], "e"),
+            ],
+            initializer: vec![
+                tensor("w", vec![2, 2], vec![1.0, 0.0, 0.0, -1.0]),
+                tensor("b", vec![2], vec![0.5, 0.5]),
+            ],
+            input: vec![value("x")],
+            output: vec![value("e")],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let error = import(&model.encode_to_vec()).unwrap_err();
+    let error = error.downcast_ref::<UnsupportedOperatorsError>().unwrap();
+    assert_eq!(error.operators.len(), 1);
+    assert_eq!(error.operators[0].op_type, "Erf");
+
+    let mut model = model;
+    let graph = model.graph.as_mut().unwrap();
+    graph.node.pop();
+    graph.output = vec![value("z")];
+    let model = import(&model.encode_to_vec()).unwrap();
+
+    // The bias was folded into the MatMul
+    assert_eq!(model.nodes.len(), 2);
+    let input = ndarray::arr1(&[1.0, 1.0]).mapv(Com::from_num).into_dyn();
+    let output = model.infer_locally(input).unwrap();
+    assert_eq!(
+        output,
+        ndarray::arr1(&[1.5, 0.0]).mapv(Com::from_num).into_dyn()
+    );
+}
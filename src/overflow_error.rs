@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::Com;
+
+/// An element of a tensor which is out of the range of `Com`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overflow {
+    pub index: Vec<usize>,
+    /// The exact value, computed in floating-point
+    pub value: f64,
+}
+
+/// A node of a model whose output is out of the range of `Com`, where it wraps around.
+#[derive(Debug, Clone)]
+pub struct OverflowError {
+    pub node: String,
+    pub overflows: Vec<Overflow>,
+}
+
+impl Error for OverflowError {}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The node '{}' overflows at {} element(s)",
+            self.node,
+            self.overflows.len()
+        )?;
+        if let Some(overflow) = self.overflows.first() {
+            write!(
+                f,
+                ", e.g. {:?} is {}, out of [{}, {}]",
+                overflow.index,
+                overflow.value,
+                Com::MIN,
+                Com::MAX
+            )?;
+        }
+        Ok(())
+    }
+}
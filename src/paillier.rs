@@ -0,0 +1,225 @@
+use anyhow::bail;
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+use ring::rand::SecureRandom;
+use serde::{Deserialize, Serialize};
+
+/// The size of the Paillier modulus in bits. Tests use smaller keys, which are faster to generate.
+pub(crate) const KEY_BITS: u64 = if cfg!(test) { 512 } else { 2048 };
+
+/// The number of Miller-Rabin rounds to perform when sampling primes.
+const MILLER_RABIN_ROUNDS: usize = 40;
+
+const SMALL_PRIMES: [u32; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// A public key of the Paillier cryptosystem (Pascal Paillier. Public-Key Cryptosystems Based on
+/// Composite Degree Residuosity Classes. EUROCRYPT 1999.) with g = n + 1.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaillierPublicKey {
+    pub n: BigUint,
+}
+
+pub(crate) struct PaillierPrivateKey {
+    pub(crate) public_key: PaillierPublicKey,
+    lambda: BigUint,
+    mu: BigUint,
+}
+
+impl PaillierPublicKey {
+    fn n_squared(&self) -> BigUint {
+        &self.n * &self.n
+    }
+
+    /// Encrypts a plaintext in [0, n).
+    pub(crate) fn encrypt(&self, m: &BigUint, rng: &dyn SecureRandom) -> BigUint {
+        let n_squared = self.n_squared();
+
+        // r should be in Z*_n, the chance of sampling a non-invertible r is negligible
+        let r = loop {
+            let r = sample_below(&self.n, rng);
+            if !r.is_zero() {
+                break r;
+            }
+        };
+
+        // (n + 1)^m = 1 + mn (mod n^2)
+        ((BigUint::one() + m * &self.n) * r.modpow(&self.n, &n_squared)) % n_squared
+    }
+
+    /// Computes an encryption of the sum of the plaintexts.
+    pub(crate) fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a * b) % self.n_squared()
+    }
+
+    /// Computes an encryption of the plaintext multiplied by a scalar.
+    pub(crate) fn multiply(&self, c: &BigUint, k: &BigUint) -> BigUint {
+        c.modpow(k, &self.n_squared())
+    }
+}
+
+impl PaillierPrivateKey {
+    pub(crate) fn generate(bits: u64, rng: &dyn SecureRandom) -> Self {
+        loop {
+            let p = sample_prime(bits / 2, rng);
+            let q = sample_prime(bits / 2, rng);
+            if p == q {
+                continue;
+            }
+
+            let n = &p * &q;
+            let lambda = (&p - 1u32).lcm(&(&q - 1u32));
+
+            // With g = n + 1, L(g^λ mod n^2) = λ mod n
+            if let Some(mu) = lambda.modinv(&n) {
+                return PaillierPrivateKey {
+                    public_key: PaillierPublicKey { n },
+                    lambda,
+                    mu,
+                };
+            }
+        }
+    }
+
+    /// Decrypts a ciphertext, which should be in Z*_(n^2) of this key.
+    ///
+    /// A ciphertext of another key is only rejected if it is out of range, otherwise it decrypts
+    /// to garbage.
+    pub(crate) fn decrypt(&self, c: &BigUint) -> anyhow::Result<BigUint> {
+        let n = &self.public_key.n;
+        let n_squared = self.public_key.n_squared();
+        if c.is_zero() || c >= &n_squared {
+            bail!("The ciphertext is not modulo the key's n²");
+        }
+
+        let l = (c.modpow(&self.lambda, &n_squared) - 1u32) / n;
+        Ok((l * &self.mu) % n)
+    }
+}
+
+/// Samples a number of (at most) `bits` bits uniformly.
+pub(crate) fn sample_bits(bits: u64, rng: &dyn SecureRandom) -> BigUint {
+    let mut bytes = vec![0u8; bits.div_ceil(8) as usize];
+    rng.fill(&mut bytes).unwrap();
+
+    // Clear the excess bits of the most significant byte
+    if let Some(last) = bytes.last_mut() {
+        *last &= 0xFF >> ((8 - bits % 8) % 8);
+    }
+
+    BigUint::from_bytes_le(&bytes)
+}
+
+fn sample_below(bound: &BigUint, rng: &dyn SecureRandom) -> BigUint {
+    loop {
+        let candidate = sample_bits(bound.bits(), rng);
+        if &candidate < bound {
+            return candidate;
+        }
+    }
+}
+
+fn sample_prime(bits: u64, rng: &dyn SecureRandom) -> BigUint {
+    loop {
+        // Make sure the candidate is odd and exactly `bits` long. The second bit makes sure the
+        // product of two candidates is exactly twice as long
+        let mut candidate = sample_bits(bits, rng);
+        candidate.set_bit(bits - 1, true);
+        candidate.set_bit(bits - 2, true);
+        candidate.set_bit(0, true);
+
+        if is_probable_prime(&candidate, rng) {
+            return candidate;
+        }
+    }
+}
+
+/// The Miller-Rabin primality test, preceded by trial division.
+fn is_probable_prime(n: &BigUint, rng: &dyn SecureRandom) -> bool {
+    for p in SMALL_PRIMES {
+        if (n % p).is_zero() {
+            return *n == BigUint::from(p);
+        }
+    }
+
+    // Write n - 1 as d * 2^s
+    let n_minus_1 = n - 1u32;
+    let s = n_minus_1.trailing_zeros().unwrap();
+    let d = &n_minus_1 >> s;
+
+    let two = BigUint::from(2u32);
+    'witnesses: for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = sample_below(&(n - 3u32), rng) + 2u32;
+        let mut x = a.modpow(&d, n);
+        if x.is_one() || x == n_minus_1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_1 {
+                continue 'witnesses;
+            }
+        }
+        return false;
+    }
+
+    true
+}
+
+#[test]
+fn test_paillier_homomorphism() {
+    let rng = ring::rand::SystemRandom::new();
+    let private_key = PaillierPrivateKey::generate(512, &rng);
+    let public_key = &private_key.public_key;
+
+    let a = BigUint::from(1234u32);
+    let b = BigUint::from(5678u32);
+    let k = BigUint::from(9u32);
+
+    let encrypted_a = public_key.encrypt(&a, &rng);
+    let encrypted_b = public_key.encrypt(&b, &rng);
+    assert_eq!(private_key.decrypt(&encrypted_a).unwrap(), a);
+
+    let encrypted_sum = public_key.add(&encrypted_a, &encrypted_b);
+    assert_eq!(private_key.decrypt(&encrypted_sum).unwrap(), &a + &b);
+
+    let encrypted_product = public_key.multiply(&encrypted_b, &k);
+    assert_eq!(private_key.decrypt(&encrypted_product).unwrap(), &b * &k);
+}
+
+#[test]
+fn test_paillier_key_generation() {
+    let rng = ring::rand::SystemRandom::new();
+    let private_key = PaillierPrivateKey::generate(512, &rng);
+    let n = &private_key.public_key.n;
+
+    assert_eq!(n.bits(), 512);
+    assert!((&private_key.lambda * &private_key.mu % n).is_one());
+
+    // The extremes of the plaintext space survive a round trip
+    for m in [BigUint::zero(), n - 1u32] {
+        let c = private_key.public_key.encrypt(&m, &rng);
+        assert_eq!(private_key.decrypt(&c).unwrap(), m);
+    }
+}
+
+#[test]
+fn test_paillier_wrong_key() {
+    let rng = ring::rand::SystemRandom::new();
+    let private_key = PaillierPrivateKey::generate(512, &rng);
+    let other_private_key = PaillierPrivateKey::generate(512, &rng);
+
+    let m = BigUint::from(1234u32);
+    let c = private_key.public_key.encrypt(&m, &rng);
+    assert!(other_private_key
+        .decrypt(&c)
+        .map_or(true, |decrypted| decrypted != m));
+
+    // Ciphertexts out of Z_(n^2) are rejected
+    assert!(private_key
+        .decrypt(&private_key.public_key.n_squared())
+        .is_err());
+    assert!(private_key.decrypt(&BigUint::zero()).is_err());
+}
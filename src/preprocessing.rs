@@ -0,0 +1,114 @@
+//! Turns raw inputs, e.g. images, into the tensors a model takes, as declared by its header.
+
+use anyhow::{bail, Context as _};
+use image::{imageops::FilterType, DynamicImage};
+use ndarray::{Array3, ArrayD, IxDyn};
+use serde::{Deserialize, Serialize};
+
+/// A step of preprocessing. Image operations come first, then arithmetic on the pixel values,
+/// which start in [0, 255].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Preprocessing {
+    /// Resizes an image to exactly this size, sampling the nearest pixels
+    Resize { width: u32, height: u32 },
+    /// Converts an image to a single luminance channel
+    Grayscale,
+    /// Multiplies every value, e.g. by 1/255
+    Scale { factor: f32 },
+    /// Subtracts a mean and divides by a standard deviation
+    Normalize { mean: f32, std: f32 },
+}
+
+impl Preprocessing {
+    fn is_image_operation(&self) -> bool {
+        matches!(
+            self,
+            Preprocessing::Resize { .. } | Preprocessing::Grayscale
+        )
+    }
+}
+
+/// Preprocesses an image into a (channels, height, width) tensor, reshaped to the input shape if
+/// there is one.
+pub fn preprocess_image(
+    mut image: DynamicImage,
+    steps: &[Preprocessing],
+    input_shape: Option<&[usize]>,
+) -> anyhow::Result<ArrayD<f32>> {
+    let mut steps = steps.iter().peekable();
+    while let Some(step) = steps.next_if(|step| step.is_image_operation()) {
+        image = match step {
+            Preprocessing::Resize { width, height } => {
+                image.resize_exact(*width, *height, FilterType::Nearest)
+            }
+            Preprocessing::Grayscale => image.grayscale(),
+            _ => unreachable!(),
+        };
+    }
+
+    let mut tensor = into_tensor(&image);
+    for step in steps {
+        tensor = match step {
+            Preprocessing::Scale { factor } => tensor * *factor,
+            Preprocessing::Normalize { mean, std } => (tensor - *mean) / *std,
+            step => bail!("{:?} should come before the arithmetic steps", step),
+        };
+    }
+
+    match input_shape {
+        Some(input_shape) => {
+            let len = tensor.len();
+            tensor.into_shape(IxDyn(input_shape)).with_context(|| {
+                format!(
+                    "The preprocessed input has {} elements, but the model takes {:?}",
+                    len, input_shape
+                )
+            })
+        }
+        None => Ok(tensor.into_dyn()),
+    }
+}
+
+/// Converts an image into a (channels, height, width) tensor with one channel if it is greyscale,
+/// and three otherwise. Alpha is dropped.
+fn into_tensor(image: &DynamicImage) -> Array3<f32> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    if image.color().has_color() {
+        let image = image.to_rgb8();
+        Array3::from_shape_fn((3, height, width), |(c, i, j)| {
+            image.get_pixel(j as u32, i as u32).0[c] as f32
+        })
+    } else {
+        let image = image.to_luma8();
+        Array3::from_shape_fn((1, height, width), |(_, i, j)| {
+            image.get_pixel(j as u32, i as u32).0[0] as f32
+        })
+    }
+}
+
+#[test]
+fn test_preprocess_image() {
+    let image = DynamicImage::ImageLuma8(image::GrayImage::from_fn(4, 4, |x, y| {
+        image::Luma([(16 * (x + 4 * y)) as u8])
+    }));
+    let steps = [
+        Preprocessing::Resize {
+            width: 2,
+            height: 2,
+        },
+        Preprocessing::Scale { factor: 1.0 / 16.0 },
+    ];
+
+    let tensor = preprocess_image(image.clone(), &steps, Some(&[4])).unwrap();
+    assert_eq!(tensor.shape(), [4]);
+    assert_eq!(tensor[0], 0.0);
+
+    assert!(preprocess_image(image.clone(), &steps, Some(&[5])).is_err());
+    let misordered = [
+        Preprocessing::Scale { factor: 2.0 },
+        Preprocessing::Grayscale,
+    ];
+    assert!(preprocess_image(image, &misordered, None).is_err());
+}
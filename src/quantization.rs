@@ -0,0 +1,322 @@
+//! Conversion of floating-point weights to `Com`.
+
+use std::{error::Error, fmt};
+
+use fixed::{FixedI32, Wrapping};
+use ndarray::{Array, ArrayD, Dimension, IxDyn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{com, Com};
+
+/// How a float which falls between two `Com`s is rounded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// To the nearest `Com`, ties away from zero
+    #[default]
+    Nearest,
+    TowardZero,
+    Floor,
+}
+
+impl Rounding {
+    fn round(self, x: f64) -> f64 {
+        match self {
+            Rounding::Nearest => x.round(),
+            Rounding::TowardZero => x.trunc(),
+            Rounding::Floor => x.floor(),
+        }
+    }
+}
+
+/// A fixed-point format, e.g. I30F2 for 30 integer bits and 2 fractional bits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedPointFormat {
+    pub integer_bits: u32,
+    pub fractional_bits: u32,
+}
+
+impl FixedPointFormat {
+    /// The format of `Com`.
+    pub fn of_com() -> Self {
+        let fractional_bits = com::frac_bits();
+        FixedPointFormat {
+            integer_bits: (std::mem::size_of::<Com>() * 8) as u32 - fractional_bits,
+            fractional_bits,
+        }
+    }
+
+    /// Checks that this is the format of `Com`, which is fixed at compile time.
+    pub fn check(&self) -> Result<(), FormatMismatchError> {
+        if *self == Self::of_com() {
+            Ok(())
+        } else {
+            Err(FormatMismatchError { declared: *self })
+        }
+    }
+}
+
+impl fmt::Display for FixedPointFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "I{}F{}", self.integer_bits, self.fractional_bits)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatMismatchError {
+    pub declared: FixedPointFormat,
+}
+
+impl Error for FormatMismatchError {}
+
+impl fmt::Display for FormatMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The format {} is declared, but this build computes in {}",
+            self.declared,
+            FixedPointFormat::of_com()
+        )
+    }
+}
+
+/// Converts a float to a `Com`, saturating values out of its range rather than wrapping them
+/// around.
+///
+/// # Returns
+///
+/// The `Com` and whether it saturated
+pub fn quantize_with(x: f64, rounding: Rounding) -> (Com, bool) {
+    let bits = rounding.round(x * (1u64 << com::frac_bits()) as f64);
+    if bits.is_nan() {
+        (Com::ZERO, true)
+    } else if bits < i32::MIN as f64 {
+        (Com::MIN, true)
+    } else if bits > i32::MAX as f64 {
+        (Com::MAX, true)
+    } else {
+        (Wrapping(FixedI32::from_bits(bits as i32)), false)
+    }
+}
+
+/// Converts a float to the nearest `Com`, saturating values out of its range rather than
+/// wrapping them around.
+pub fn quantize(x: f32) -> Com {
+    quantize_with(x as f64, Rounding::Nearest).0
+}
+
+/// Applies `quantize` to every element.
+pub fn quantize_array<D: Dimension>(x: &Array<f32, D>) -> Array<Com, D> {
+    x.mapv(quantize)
+}
+
+/// The error introduced by quantizing a tensor.
+#[derive(Debug, Clone, Default)]
+pub struct TensorReport {
+    pub name: String,
+    pub elements: usize,
+    /// The number of elements out of the range of `Com`
+    pub saturated: usize,
+    /// The largest absolute difference between an element and its `Com`
+    pub max_error: f64,
+}
+
+/// The error introduced by quantizing every tensor of a model.
+#[derive(Debug, Clone, Default)]
+pub struct QuantizationReport {
+    pub tensors: Vec<TensorReport>,
+}
+
+impl QuantizationReport {
+    /// The total number of saturated elements.
+    pub fn saturated(&self) -> usize {
+        self.tensors.iter().map(|tensor| tensor.saturated).sum()
+    }
+
+    /// Quantizes a tensor with `quantize_with`, recording its error.
+    pub fn quantize<D: Dimension>(
+        &mut self,
+        name: &str,
+        x: &Array<f64, D>,
+        rounding: Rounding,
+    ) -> Array<Com, D> {
+        let mut tensor = TensorReport {
+            name: name.to_owned(),
+            elements: x.len(),
+            ..Default::default()
+        };
+
+        let quantized = x.mapv(|x| {
+            let (quantized, saturated) = quantize_with(x, rounding);
+            tensor.saturated += saturated as usize;
+            tensor.max_error = tensor
+                .max_error
+                .max((x - quantized.0.to_num::<f64>()).abs());
+            quantized
+        });
+
+        self.tensors.push(tensor);
+        quantized
+    }
+}
+
+/// Quantizes every real-valued tensor in a JSON model, naming each one by its path, e.g.
+/// `layers[0].weights`.
+///
+/// Tensors are either nested arrays of numbers or in ndarray's `{"v": 1, "dim": ..., "data": ...}`
+/// format, and are replaced by the latter with `Com` elements. The tensors of batch normalisations
+/// stay real numbers, see `map_json_tensors`.
+pub fn quantize_json(
+    value: &mut Value,
+    path: &str,
+    rounding: Rounding,
+    report: &mut QuantizationReport,
+) -> anyhow::Result<()> {
+    map_json_tensors(value, path, &mut |path, tensor| {
+        Ok(serde_json::to_value(
+            report.quantize(path, &tensor, rounding),
+        )?)
+    })
+}
+
+/// The layers whose tensors stay real numbers, since they are folded into other layers and only
+/// the results are quantized.
+const REAL_VALUED_LAYERS: [&str; 1] = ["BatchNormLayer"];
+
+/// Whether a JSON object is a layer whose tensors stay real numbers.
+pub(crate) fn is_real_valued_layer(object: &Map<String, Value>) -> bool {
+    object
+        .get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| REAL_VALUED_LAYERS.contains(&kind))
+}
+
+/// Replaces every real-valued tensor in a JSON model, as `quantize_json` does, with the JSON
+/// which `f` returns given the path and the tensor.
+///
+/// The tensors of the layers which stay real-valued (see `is_real_valued_layer`) are only brought
+/// into ndarray's format, with `f64` elements.
+pub(crate) fn map_json_tensors<F>(value: &mut Value, path: &str, f: &mut F) -> anyhow::Result<()>
+where
+    F: FnMut(&str, ArrayD<f64>) -> anyhow::Result<Value>,
+{
+    if let Some(tensor) = json_tensor(value) {
+        *value = f(path, tensor?)?;
+        return Ok(());
+    }
+
+    match value {
+        Value::Object(object) if is_real_valued_layer(object) => {
+            for field in object.values_mut() {
+                if let Some(tensor) = json_tensor(field) {
+                    *field = serde_json::to_value(tensor?)?;
+                }
+            }
+        }
+        Value::Array(elements) => {
+            for (i, element) in elements.iter_mut().enumerate() {
+                map_json_tensors(element, &format!("{}[{}]", path, i), f)?;
+            }
+        }
+        Value::Object(object) => {
+            for (key, field) in object.iter_mut() {
+                map_json_tensors(field, &format!("{}.{}", path, key), f)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Reads a JSON value as a tensor, if it is one.
+pub(crate) fn json_tensor(value: &Value) -> Option<anyhow::Result<ArrayD<f64>>> {
+    match value {
+        Value::Object(object)
+            if object.len() == 3
+                && ["v", "dim", "data"]
+                    .iter()
+                    .all(|key| object.contains_key(*key)) =>
+        {
+            Some((|| {
+                let shape: Vec<usize> = serde_json::from_value(object["dim"].clone())?;
+                let data: Vec<f64> = serde_json::from_value(object["data"].clone())?;
+                Ok(ArrayD::from_shape_vec(IxDyn(&shape), data)?)
+            })())
+        }
+        Value::Array(_) => {
+            // The shape is given by the first element along every axis
+            let mut shape = vec![];
+            let mut first = value;
+            while let Value::Array(elements) = first {
+                shape.push(elements.len());
+                first = elements.first()?;
+            }
+
+            let mut data = vec![];
+            flatten_json(value, &shape, &mut data)?;
+            Some(Ok(ArrayD::from_shape_vec(IxDyn(&shape), data).unwrap()))
+        }
+        _ => None,
+    }
+}
+
+/// Appends the numbers of a nested array of the given shape, or fails if it has another shape or
+/// contains anything but numbers.
+fn flatten_json(value: &Value, shape: &[usize], data: &mut Vec<f64>) -> Option<()> {
+    match (value, shape) {
+        (Value::Number(number), []) => data.push(number.as_f64()?),
+        (Value::Array(elements), [length, shape @ ..]) if elements.len() == *length => {
+            for element in elements {
+                flatten_json(element, shape, data)?;
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for tensor in &self.tensors {
+            writeln!(
+                f,
+                "{}: {} elements, {} saturated, max error {}",
+                tensor.name, tensor.elements, tensor.saturated, tensor.max_error
+            )?;
+        }
+        write!(
+            f,
+            "{} of {} elements saturated",
+            self.saturated(),
+            self.tensors
+                .iter()
+                .map(|tensor| tensor.elements)
+                .sum::<usize>()
+        )
+    }
+}
+
+#[test]
+fn test_quantize_rounding_and_saturation() {
+    // Com has 2 fractional bits, so 0.6 lies between 0.5 and 0.75
+    assert_eq!(
+        quantize_with(0.6, Rounding::Nearest),
+        (Com::from_num(0.5), false)
+    );
+    assert_eq!(
+        quantize_with(0.7, Rounding::Nearest),
+        (Com::from_num(0.75), false)
+    );
+    assert_eq!(
+        quantize_with(-0.6, Rounding::TowardZero),
+        (Com::from_num(-0.5), false)
+    );
+    assert_eq!(
+        quantize_with(-0.6, Rounding::Floor),
+        (Com::from_num(-0.75), false)
+    );
+    assert_eq!(quantize_with(1e12, Rounding::Nearest), (Com::MAX, true));
+    assert_eq!(quantize_with(-1e12, Rounding::Nearest), (Com::MIN, true));
+}
@@ -1,24 +1,27 @@
 use std::error::Error;
 
 use anyhow::Context as _;
-use ndarray::Array1;
+use ndarray::ArrayD;
 use ring::rand::SecureRandom;
 
 use crate::message::Message;
 use crate::message::IO;
 use crate::model::ModelShare;
+use crate::model_header::ModelHeader;
+use crate::offline;
 use crate::unexpected_message_error::UnexpectedMessageError;
 use crate::Com;
 
 pub async fn infer(
     (sender, receiver): IO<'_>,
+    header: &ModelHeader,
     model_shares: (ModelShare, ModelShare),
     rng: &dyn SecureRandom,
 ) -> Result<(), Box<dyn Error>> {
     // FIXME: this runs sequentially even though I can easily parallelise this
 
-    // Send the client a model share
-    sender.send(Message::ModelShare(model_shares.1)).await?;
+    // Tell the client how to prepare its input
+    sender.send(Message::ModelHeader(header.clone())).await?;
 
     // Wait for the input share
     let input_share_message: Message;
@@ -30,17 +33,32 @@ pub async fn infer(
 
     // Verify the message is indeed an input share
     // TODO this may be merged with on paragraph above
-    let input_share: Array1<Com>;
+    let input_share: ArrayD<Com>;
     if let Message::InputShare(contents) = input_share_message {
         input_share = contents;
     } else {
         return Err(Box::new(UnexpectedMessageError {}));
     }
 
+    // The amount of correlated randomness only depends on the shape of the input
+    let plan = offline::plan((&model_shares.0, &model_shares.1), input_share.shape())
+        .await
+        .context("Failed to plan the offline phase")?;
+
+    // Send the client a model share
+    sender.send(Message::ModelShare(model_shares.1)).await?;
+
+    // Generate the correlated randomness, which does not depend on the input
+    let mut material = model_shares
+        .0
+        .prepare::<true>(plan, (sender, receiver), rng)
+        .await
+        .context("Failed to run the offline phase")?;
+
     // Infer the model
     let output_share = model_shares
         .0
-        .infer::<true>(input_share, (sender, receiver), rng)
+        .infer::<true>(input_share, &mut material, (sender, receiver))
         .await
         .context("Failed to iterate over the model's layers")?;
 
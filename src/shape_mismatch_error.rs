@@ -0,0 +1,48 @@
+use std::error::Error;
+use std::fmt;
+
+/// A tensor whose shape doesn't fit the layer it is fed into.
+#[derive(Debug)]
+pub struct ShapeMismatchError {
+    /// The expected size of each axis, where `None` means any size
+    pub expected: Vec<Option<usize>>,
+    pub actual: Vec<usize>,
+}
+
+impl ShapeMismatchError {
+    /// Checks whether a shape matches the expected one.
+    pub fn check(actual: &[usize], expected: &[Option<usize>]) -> Result<(), Self> {
+        let matches = actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(size, expected_size)| expected_size.map_or(true, |e| e == *size));
+
+        if matches {
+            Ok(())
+        } else {
+            Err(ShapeMismatchError {
+                expected: expected.to_vec(),
+                actual: actual.to_vec(),
+            })
+        }
+    }
+}
+
+impl Error for ShapeMismatchError {}
+
+impl fmt::Display for ShapeMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected: Vec<String> = self
+            .expected
+            .iter()
+            .map(|size| size.map_or("_".to_owned(), |size| size.to_string()))
+            .collect();
+        write!(
+            f,
+            "Expected a tensor of shape [{}], got {:?}",
+            expected.join(", "),
+            self.actual
+        )
+    }
+}
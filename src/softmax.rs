@@ -0,0 +1,238 @@
+//! An approximation of the softmax which only needs comparisons, truncations and products.
+//!
+//! - The maximum of every row is subtracted, so that the exponents are at most 0
+//! - exp(x) is approximated by (1 + x/4)⁴, clipped to 0 below x = -4
+//! - The sum of every row is inverted by Newton's iteration, r ← r · (2 - d · r), starting from
+//!   1 / (row length). Since every row contains exp(0) = 1, its sum d is in [1, row length], so
+//!   this converges, in about log₂(row length) iterations.
+//!
+//! With only a few fractional bits, 1 / (row length) would round to 0 and never move. Hence the
+//! reciprocals are scaled up by 2^(L + `RECIPROCAL_BITS`), where 2^L is the row length rounded up
+//! to a power of two, and the errors 2 - d · r by 2^`RECIPROCAL_BITS`. The scales are truncated
+//! away after every product.
+
+use anyhow::{bail, Context as _};
+use ndarray::{Array1, Array2, Axis};
+
+use crate::{
+    bitxa, com, layer::relu::drelu::drelu, maximum::maximum, message::IO, offline::OfflineMaterial,
+    tensor, truncation, Com,
+};
+
+/// The number of Newton iterations for inverting the sums of the rows, on top of L.
+const RECIPROCAL_ITERATIONS: usize = 4;
+
+/// The precision of the reciprocals and of the errors of Newton's iteration.
+const RECIPROCAL_BITS: u32 = 8;
+
+/// The largest L before the products of the reciprocals and the errors risk overflowing.
+const MAX_ROW_LENGTH_BITS: u32 = 10;
+
+/// The number of bits L of the length of the rows, rounded up to a power of two.
+///
+/// # Errors
+///
+/// Fails if the rows are empty or too long for the approximation.
+pub(crate) fn row_length_bits(columns: usize) -> anyhow::Result<u32> {
+    if columns == 0 {
+        bail!("The softmax of empty rows is undefined");
+    }
+
+    let bits = columns.next_power_of_two().trailing_zeros();
+    if bits > MAX_ROW_LENGTH_BITS {
+        bail!(
+            "Rows of {} elements are too long for the approximate softmax, which supports at most {}",
+            columns,
+            1 << MAX_ROW_LENGTH_BITS
+        );
+    }
+    Ok(bits)
+}
+
+/// The initial guess 1 / `columns`, scaled up and rounded down s.t. Newton's iteration converges.
+fn initial_reciprocal(columns: usize, length_bits: u32) -> Com {
+    let scale_bits = length_bits + RECIPROCAL_BITS + com::frac_bits();
+    Com::from_bits(((1i64 << scale_bits) / columns as i64) as i32)
+}
+
+/// Divides by 2^`bits`, rounding down.
+fn shift_locally(x: Com, bits: u32) -> Com {
+    Com::from_bits(x.0.to_bits() >> bits)
+}
+
+/// Computes the approximate softmax of every row in plaintext, as the secure version does.
+///
+/// # Errors
+///
+/// Fails if the rows are empty or too long, see `row_length_bits`.
+pub(crate) fn softmax_locally(x: &Array2<Com>) -> anyhow::Result<Array2<Com>> {
+    let length_bits = row_length_bits(x.ncols())?;
+
+    let maxima = x.map_axis(Axis(1), |row| *row.iter().max().unwrap());
+    let y = (x - &maxima.insert_axis(Axis(1)))
+        .mapv(|x| (x / Com::from_num(4) + Com::from_num(1)).max(Com::ZERO));
+    let y_squared = &y * &y;
+    let exponents = &y_squared * &y_squared;
+
+    let sums = exponents.sum_axis(Axis(1));
+    let two = Com::from_num(2 << RECIPROCAL_BITS);
+    let mut reciprocals = Array1::from_elem(sums.len(), initial_reciprocal(x.ncols(), length_bits));
+    for _ in 0..length_bits as usize + RECIPROCAL_ITERATIONS {
+        let errors = (&sums * &reciprocals).mapv(|p| two - shift_locally(p, length_bits));
+        reciprocals = (&reciprocals * &errors).mapv(|r| shift_locally(r, RECIPROCAL_BITS));
+    }
+
+    Ok((exponents * &reciprocals.insert_axis(Axis(1)))
+        .mapv(|y| shift_locally(y, length_bits + RECIPROCAL_BITS)))
+}
+
+/// The secure counterpart of `softmax_locally`.
+///
+/// # Arguments
+///
+/// - `x_share`: A share of the rows
+/// - `material`: The material of the offline phase, of which DReLU and BitXA keys and triplets are taken
+/// - `(sender, receiver)`: A sender and a receiver for asynchronous communication with the other party. Messages may arrive out-of-order.
+pub(crate) async fn softmax<const PARTY: bool>(
+    x_share: &Array2<Com>,
+    material: &mut OfflineMaterial,
+    (sender, receiver): IO<'_>,
+) -> anyhow::Result<Array2<Com>> {
+    let (rows, columns) = x_share.dim();
+    let n = rows * columns;
+    let length_bits = row_length_bits(columns)?;
+
+    let maxima_share = maximum::<PARTY>(x_share.clone(), material, (sender, receiver))
+        .await
+        .context("Failed to find the maxima")?;
+    let shifted_share = x_share - &maxima_share.insert_axis(Axis(1));
+
+    // ReLU(x/4 + 1), where only one party should add the (public) 1
+    let mut y_share =
+        truncation::truncate::<PARTY, _>(&tensor::flatten(shifted_share.into_dyn()), 2);
+    if PARTY {
+        y_share += Com::from_num(1);
+    }
+    let drelu_output_share = drelu::<PARTY>(&y_share, material, (sender, receiver))
+        .await
+        .context("Failed to evaluate DReLU")?;
+    let key = material.take_bitxa_key(n)?;
+    let y_share = bitxa::<PARTY>(&y_share, &drelu_output_share, key, (sender, receiver))
+        .await
+        .context("Failed to evaluate BitXA")?;
+
+    let y_squared_share = material
+        .take_hadamard_triplet(n)?
+        .hadamard_product::<PARTY>(&y_share, &y_share, (sender, receiver))
+        .await?;
+    let exponents_share = material
+        .take_hadamard_triplet(n)?
+        .hadamard_product::<PARTY>(&y_squared_share, &y_squared_share, (sender, receiver))
+        .await?
+        .into_shape((rows, columns))
+        .unwrap();
+
+    let sums_share = exponents_share.sum_axis(Axis(1));
+    let two = Com::from_num(2 << RECIPROCAL_BITS);
+    let mut reciprocals_share = if PARTY {
+        Array1::from_elem(rows, initial_reciprocal(columns, length_bits))
+    } else {
+        Array1::zeros(rows)
+    };
+    for i in 0..length_bits as usize + RECIPROCAL_ITERATIONS {
+        let products_share = material
+            .take_hadamard_triplet(rows)?
+            .hadamard_product::<PARTY>(&sums_share, &reciprocals_share, (sender, receiver))
+            .await
+            .with_context(|| format!("Failed Newton's iteration {}", i + 1))?;
+        let products_share = truncation::truncate::<PARTY, _>(&products_share, length_bits);
+        let errors_share = if PARTY {
+            products_share.mapv(|p| two - p)
+        } else {
+            products_share.mapv(|p| -p)
+        };
+        let scaled_reciprocals_share = material
+            .take_hadamard_triplet(rows)?
+            .hadamard_product::<PARTY>(&reciprocals_share, &errors_share, (sender, receiver))
+            .await
+            .with_context(|| format!("Failed Newton's iteration {}", i + 1))?;
+        reciprocals_share =
+            truncation::truncate::<PARTY, _>(&scaled_reciprocals_share, RECIPROCAL_BITS);
+    }
+
+    // Normalise every row
+    let repeated_reciprocals_share = Array1::from_iter(
+        reciprocals_share
+            .iter()
+            .flat_map(|&r| std::iter::repeat(r).take(columns)),
+    );
+    let output_share = material
+        .take_hadamard_triplet(n)?
+        .hadamard_product::<PARTY>(
+            &tensor::flatten(exponents_share.into_dyn()),
+            &repeated_reciprocals_share,
+            (sender, receiver),
+        )
+        .await
+        .context("Failed to normalise the rows")?;
+    let output_share =
+        truncation::truncate::<PARTY, _>(&output_share, length_bits + RECIPROCAL_BITS);
+
+    Ok(output_share.into_shape((rows, columns)).unwrap())
+}
+
+#[tokio::test]
+async fn test_long_rows_match_softmax() {
+    use ndarray::{array, concatenate};
+
+    use crate::{reconstruct::Reconstruct, split::Split, testing};
+
+    let rng = ring::rand::SystemRandom::new();
+    // Far below the maxima, exp(x) is clipped to 0
+    let low = |n| Array2::from_elem((1, n), Com::from_num(-8));
+    let rows = [
+        concatenate![Axis(1), array![[Com::from_num(3)]], low(7)],
+        concatenate![Axis(1), Array2::zeros((1, 2)), low(6)],
+        concatenate![Axis(1), Array2::zeros((1, 4)), low(12)],
+        Array2::zeros((1, 32)),
+    ];
+
+    for x in rows {
+        let expected = x.mapv(|x| x.0.to_num::<f64>().exp());
+        let expected = &expected / expected.sum();
+
+        let output = softmax_locally(&x).unwrap();
+        for (o, e) in output.iter().zip(&expected) {
+            assert!((o.0.to_num::<f64>() - e).abs() <= 0.25, "{} vs {}", o, e);
+        }
+
+        // Count the material on zeros, then deal it
+        let zeros = Array2::zeros(x.dim());
+        let mut counting = (OfflineMaterial::counting(), OfflineMaterial::counting());
+        let (mut server, mut client, _) = testing::connect();
+        let (server_output_share, client_output_share) = tokio::join!(
+            softmax::<true>(&zeros, &mut counting.0, server.io()),
+            softmax::<false>(&zeros, &mut counting.1, client.io()),
+        );
+        server_output_share.unwrap();
+        client_output_share.unwrap();
+        let plan = counting.0.counted();
+        // The maxima take a comparison per column but one, the ReLUs one per element
+        let (rows, columns) = x.dim();
+        assert_eq!(plan.bitxa_elements, rows * (columns - 1) + rows * columns);
+        assert_eq!(plan.drelu_elements, plan.bitxa_elements);
+        let mut materials = OfflineMaterial::deal(&plan, &rng);
+
+        let x_shares = x.split(&rng);
+        let (mut server, mut client, _) = testing::connect();
+        let (server_output_share, client_output_share) = tokio::join!(
+            softmax::<true>(&x_shares.0, &mut materials.0, server.io()),
+            softmax::<false>(&x_shares.1, &mut materials.1, client.io()),
+        );
+        let secure_output =
+            Array2::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+        testing::assert_close(&secure_output, &output, 1);
+    }
+
+    assert!(row_length_bits(2048).is_err());
+}
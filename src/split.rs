@@ -1,4 +1,4 @@
-use ndarray::{Array1, Array2};
+use ndarray::{Array, Dimension};
 use ring::rand::SecureRandom;
 
 use crate::{bit, com, Com};
@@ -11,12 +11,12 @@ pub trait Split {
     fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted);
 }
 
-impl Split for Array1<Com> {
-    type Splitted = Array1<Com>;
+impl<D: Dimension> Split for Array<Com, D> {
+    type Splitted = Array<Com, D>;
 
     fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
         // Generate a random array
-        let first_share = com::sample(self.len(), rng);
+        let first_share = com::sample(self.raw_dim(), rng);
 
         // Choose the second array s.t. the sum of both share is the original value
         let second_share = self - &first_share;
@@ -25,27 +25,14 @@ impl Split for Array1<Com> {
     }
 }
 
-impl Split for Array2<Com> {
-    type Splitted = Array2<Com>;
-
-    fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
-        // Generate a random array
-        let first_share = com::sample((self.shape()[0], self.shape()[1]), rng);
-
-        // Choose the second array s.t. the sum of both share is the original value
-        let second_share = self - &first_share;
-
-        (first_share, second_share)
-    }
-}
-
-impl Split for Array1<bool> {
-    type Splitted = Array1<bool>;
+impl<D: Dimension> Split for Array<bool, D> {
+    type Splitted = Array<bool, D>;
 
     fn split(&self, rng: &dyn SecureRandom) -> (Self::Splitted, Self::Splitted) {
         // Generate a random array
         // TODO implement RandomlyConstructable to avoid copying
-        let first_share = bit::sample(self.len(), rng);
+        let first_share =
+            Array::from_shape_vec(self.raw_dim(), bit::sample(self.len(), rng).to_vec()).unwrap();
 
         // Choose the second array s.t. the sum of both share is the original value
         let second_share = self ^ &first_share;
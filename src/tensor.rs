@@ -0,0 +1,20 @@
+use ndarray::{Array1, ArrayD, Ix1, IxDyn};
+
+use crate::shape_mismatch_error::ShapeMismatchError;
+
+/// Converts a tensor into a vector of the given length.
+pub(crate) fn into_vector<A>(x: ArrayD<A>, len: usize) -> Result<Array1<A>, ShapeMismatchError> {
+    ShapeMismatchError::check(x.shape(), &[Some(len)])?;
+    Ok(x.into_dimensionality::<Ix1>().unwrap())
+}
+
+/// Flattens a tensor of any shape, e.g. for the element-wise protocols which operate on vectors.
+pub(crate) fn flatten<A>(x: ArrayD<A>) -> Array1<A> {
+    Array1::from_iter(x)
+}
+
+/// Reverts `flatten`.
+pub(crate) fn unflatten<A>(x: Array1<A>, shape: &[usize]) -> ArrayD<A> {
+    ArrayD::from_shape_vec(IxDyn(shape), Vec::from_iter(x))
+        .expect("The shape should match the number of elements")
+}
@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+
+use ndarray::{Array, ArrayD, Dimension};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    layer::{Layer, LayerShare},
+    message::{Message, IO},
+    model::{Model, ModelShare, Node, Operation, INPUT},
+    offline,
+    reconstruct::Reconstruct,
+    split::Split,
+    Com,
+};
+
+/// One party's end of an in-memory connection.
+pub(crate) struct Endpoint {
+    sender: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+}
+
+impl Endpoint {
+    pub(crate) fn io(&mut self) -> IO<'_> {
+        (&self.sender, &mut self.receiver)
+    }
+}
+
+/// Records every message sent through a connection, along with its sender.
+#[derive(Clone, Default)]
+pub(crate) struct MessageLog(Arc<Mutex<Vec<(bool, String, Value)>>>);
+
+impl MessageLog {
+    /// Counts the messages of the given type sent by the given party.
+    pub(crate) fn count(&self, party: bool, message_type: &str) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(sender, name, _)| *sender == party && name == message_type)
+            .count()
+    }
+
+    /// The messages sent by the given party, i.e. what the other party saw.
+    pub(crate) fn sent(&self, party: bool) -> Vec<Message> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(sender, _, _)| *sender == party)
+            .map(|(_, _, contents)| serde_json::from_value(contents.clone()).unwrap())
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+/// Connects two parties in memory. The first endpoint belongs to the server (`PARTY = true`).
+pub(crate) fn connect() -> (Endpoint, Endpoint, MessageLog) {
+    let log = MessageLog::default();
+
+    let (server_sender, server_outbox) = mpsc::channel(1024);
+    let (client_sender, client_outbox) = mpsc::channel(1024);
+    let (to_client, client_receiver) = mpsc::channel(1024);
+    let (to_server, server_receiver) = mpsc::channel(1024);
+
+    tokio::spawn(relay(true, server_outbox, to_client, log.clone()));
+    tokio::spawn(relay(false, client_outbox, to_server, log.clone()));
+
+    (
+        Endpoint {
+            sender: server_sender,
+            receiver: server_receiver,
+        },
+        Endpoint {
+            sender: client_sender,
+            receiver: client_receiver,
+        },
+        log,
+    )
+}
+
+/// Evaluates a layer securely, with both parties in memory: splits the layer and the input, and
+/// runs the offline phase and then the online phase.
+///
+/// # Returns
+///
+/// The reconstructed output, and the messages of both phases
+pub(crate) async fn run_secure(layer: Layer, input: &ArrayD<Com>) -> (ArrayD<Com>, MessageLog) {
+    let rng = ring::rand::SystemRandom::new();
+    run_secure_shares(layer.split(&rng), input.split(&rng)).await
+}
+
+/// Like `run_secure`, but for a layer and an input which are already split, e.g. with private
+/// weights.
+pub(crate) async fn run_secure_shares(
+    layer_shares: (LayerShare, LayerShare),
+    input_shares: (ArrayD<Com>, ArrayD<Com>),
+) -> (ArrayD<Com>, MessageLog) {
+    let rng = ring::rand::SystemRandom::new();
+    let model_share = |layer_share| ModelShare {
+        nodes: vec![Node {
+            name: "layer".to_owned(),
+            inputs: vec![INPUT.to_owned()],
+            operation: Operation::Layer(layer_share),
+        }],
+        output: "layer".to_owned(),
+    };
+    let model_shares = (model_share(layer_shares.0), model_share(layer_shares.1));
+    run_secure_model_shares(model_shares, input_shares).await
+}
+
+/// Like `run_secure`, but for a whole model.
+pub(crate) async fn run_secure_model(
+    model: &Model,
+    input: &ArrayD<Com>,
+) -> (ArrayD<Com>, MessageLog) {
+    let rng = ring::rand::SystemRandom::new();
+    run_secure_model_shares(model.split(&rng), input.split(&rng)).await
+}
+
+async fn run_secure_model_shares(
+    model_shares: (ModelShare, ModelShare),
+    input_shares: (ArrayD<Com>, ArrayD<Com>),
+) -> (ArrayD<Com>, MessageLog) {
+    let rng = ring::rand::SystemRandom::new();
+    let plan = offline::plan((&model_shares.0, &model_shares.1), input_shares.0.shape())
+        .await
+        .unwrap();
+    let (mut server, mut client, log) = connect();
+    let (server_material, client_material) = tokio::join!(
+        model_shares.0.prepare::<true>(plan, server.io(), &rng),
+        model_shares
+            .1
+            .prepare::<false>(offline::OfflinePlan::default(), client.io(), &rng),
+    );
+    let (mut server_material, mut client_material) =
+        (server_material.unwrap(), client_material.unwrap());
+
+    let (server_output_share, client_output_share) = tokio::join!(
+        model_shares
+            .0
+            .infer::<true>(input_shares.0, &mut server_material, server.io()),
+        model_shares
+            .1
+            .infer::<false>(input_shares.1, &mut client_material, client.io()),
+    );
+    let output =
+        ArrayD::reconstruct((&server_output_share.unwrap(), &client_output_share.unwrap()));
+    (output, log)
+}
+
+/// Asserts that two tensors are of the same shape and differ by at most `ulps` units in the last
+/// place, element-wise.
+pub(crate) fn assert_close<D: Dimension>(
+    actual: &Array<Com, D>,
+    expected: &Array<Com, D>,
+    ulps: i32,
+) {
+    assert_eq!(actual.shape(), expected.shape());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).0.to_bits().abs() <= ulps, "{} vs {}", a, e);
+    }
+}
+
+async fn relay(
+    party: bool,
+    mut outbox: mpsc::Receiver<Message>,
+    inbox: mpsc::Sender<Message>,
+    log: MessageLog,
+) {
+    while let Some(message) = outbox.recv().await {
+        // The variant's name is whatever comes before its contents
+        let debug = format!("{:?}", message);
+        let name = debug.split('(').next().unwrap().to_owned();
+        let contents = serde_json::to_value(&message).unwrap();
+        log.0.lock().unwrap().push((party, name, contents));
+
+        if inbox.send(message).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use ndarray::{Array, Dimension};
+
+use crate::{com, Com};
+
+/// Truncates the fractional bits of a product locally, as in Mohassel & Zhang. SecureML: A System
+/// for Scalable Privacy-Preserving Machine Learning. IEEE S&P 2017.
+///
+/// The result is off by at most one unit in the last place, and is wrong with probability
+/// proportional to the magnitude of the shared value.
+pub(crate) fn truncate<const PARTY: bool, D: Dimension>(
+    x_share: &Array<Com, D>,
+    bits: u32,
+) -> Array<Com, D> {
+    x_share.mapv(|x| {
+        let x = x.0.to_bits();
+        Com::from_bits(if PARTY {
+            x >> bits
+        } else {
+            (x.wrapping_neg() >> bits).wrapping_neg()
+        })
+    })
+}
+
+/// Multiplies a share by a public fixed-point constant.
+///
+/// Unlike `x_share * c`, which truncates each share on its own and therefore breaks when the
+/// shares wrap around, this multiplies in the ring and only then truncates.
+pub(crate) fn scale<const PARTY: bool, D: Dimension>(
+    x_share: &Array<Com, D>,
+    c: Com,
+) -> Array<Com, D> {
+    let c = c.0.to_bits();
+    let product_share = x_share.mapv(|x| Com::from_bits(x.0.to_bits().wrapping_mul(c)));
+    truncate::<PARTY, D>(&product_share, com::frac_bits())
+}
+
+/// The precision of the public constants used by `scale_precisely`, e.g. reciprocals.
+const CONSTANT_BITS: u32 = 12;
+
+/// Multiplies a share by a public real constant, which need not be representable as a `Com`.
+///
+/// Unlike `scale`, the constant is rounded to `CONSTANT_BITS` fractional bits rather than to
+/// those of `Com`, e.g. 0.01 does not become 0. It multiplies in the ring and truncates the scale
+/// away again, so this is local.
+pub(crate) fn scale_precisely<const PARTY: bool, D: Dimension>(
+    x_share: &Array<Com, D>,
+    c: f64,
+) -> Array<Com, D> {
+    let c = (c * (1u64 << CONSTANT_BITS) as f64).round() as i32;
+    let product_share = x_share.mapv(|x| Com::from_bits(x.0.to_bits().wrapping_mul(c)));
+    truncate::<PARTY, D>(&product_share, CONSTANT_BITS)
+}
+
+/// Divides a share by a public integer.
+///
+/// Powers of two are a plain truncation. Other divisors multiply by the reciprocal, see
+/// `scale_precisely`. Either way, this is local.
+pub(crate) fn divide<const PARTY: bool, D: Dimension>(
+    x_share: &Array<Com, D>,
+    divisor: usize,
+) -> Array<Com, D> {
+    assert!(divisor > 0, "The divisor should be positive");
+
+    if divisor.is_power_of_two() {
+        truncate::<PARTY, D>(x_share, divisor.trailing_zeros())
+    } else {
+        scale_precisely::<PARTY, D>(x_share, 1.0 / divisor as f64)
+    }
+}
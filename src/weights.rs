@@ -0,0 +1,191 @@
+//! Builds models from raw weight tensors, e.g. a PyTorch `state_dict` saved as `.npz` or
+//! `.safetensors`, and a separate architecture.
+//!
+//! The architecture is a model in the usual JSON format, in which every tensor is replaced by a
+//! reference such as `{"tensor": "fc1.weight", "transpose": true}`. Only matrices may be
+//! transposed, as the order of the axes of higher-dimensional tensors would be ambiguous.
+
+#[cfg(feature = "npz")]
+mod npz;
+#[cfg(feature = "safetensors")]
+mod safetensors;
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context as _};
+use ndarray::ArrayD;
+use serde_json::Value;
+
+use crate::{
+    model::Model,
+    quantization::{is_real_valued_layer, QuantizationReport, Rounding},
+};
+
+#[cfg(feature = "npz")]
+pub use npz::NpzFile;
+#[cfg(feature = "safetensors")]
+pub use safetensors::SafeTensorsFile;
+
+/// A collection of named floating-point tensors.
+pub trait TensorSource {
+    fn tensor(&mut self, name: &str) -> anyhow::Result<ArrayD<f64>>;
+}
+
+impl TensorSource for HashMap<String, ArrayD<f64>> {
+    fn tensor(&mut self, name: &str) -> anyhow::Result<ArrayD<f64>> {
+        self.get(name)
+            .cloned()
+            .with_context(|| format!("The tensor '{}' does not exist", name))
+    }
+}
+
+/// Builds a model from an architecture by quantizing the tensors it references.
+pub fn load(
+    mut architecture: Value,
+    source: &mut dyn TensorSource,
+    rounding: Rounding,
+) -> anyhow::Result<(Model, QuantizationReport)> {
+    let mut report = QuantizationReport::default();
+    substitute(&mut architecture, source, rounding, &mut report, false)?;
+
+    let model = serde_json::from_value(architecture).context("Invalid architecture")?;
+    Ok((model, report))
+}
+
+/// Replaces every tensor reference in `value` with the quantized tensor, or with the tensor as
+/// it is within layers whose tensors stay real-valued (see `is_real_valued_layer`).
+fn substitute(
+    value: &mut Value,
+    source: &mut dyn TensorSource,
+    rounding: Rounding,
+    report: &mut QuantizationReport,
+    real_valued: bool,
+) -> anyhow::Result<()> {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(name)) = object.get("tensor") {
+                let mut tensor = source
+                    .tensor(name)
+                    .with_context(|| format!("Failed to read the tensor '{}'", name))?;
+                if object.get("transpose") == Some(&Value::Bool(true)) {
+                    if tensor.ndim() != 2 {
+                        bail!(
+                            "The tensor '{}' is of {} dimensions, but only matrices may be transposed",
+                            name,
+                            tensor.ndim()
+                        );
+                    }
+                    tensor = tensor.reversed_axes();
+                }
+
+                *value = if real_valued {
+                    serde_json::to_value(tensor)?
+                } else {
+                    serde_json::to_value(report.quantize(name, &tensor, rounding))?
+                };
+            } else {
+                let real_valued = real_valued || is_real_valued_layer(object);
+                for field in object.values_mut() {
+                    substitute(field, source, rounding, report, real_valued)?;
+                }
+            }
+        }
+        Value::Array(elements) => {
+            for element in elements {
+                substitute(element, source, rounding, report, real_valued)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_load_transposes_and_reports() {
+    use crate::Com;
+
+    let mut tensors = HashMap::from([
+        (
+            "fc.weight".to_owned(),
+            ndarray::arr2(&[[1.0, 0.3], [-2.0, 1e12]]).into_dyn(),
+        ),
+        ("fc.bias".to_owned(), ndarray::arr1(&[0.5, 0.0]).into_dyn()),
+    ]);
+    let architecture = serde_json::json!({
+        "layers": [{
+            "type": "DenseLayer",
+            "weights": {"tensor": "fc.weight", "transpose": true},
+            "biases": {"tensor": "fc.bias"}
+        }]
+    });
+
+    let (model, report) = load(architecture, &mut tensors, Rounding::Nearest).unwrap();
+    assert_eq!(report.saturated(), 1);
+    let weights_report = report
+        .tensors
+        .iter()
+        .find(|tensor| tensor.name == "fc.weight")
+        .unwrap();
+    assert_eq!(weights_report.elements, 4);
+    assert!(weights_report.max_error > 1e11);
+
+    // PyTorch stores (out, in), so the first output is 1 · x₁ + 0.25 · x₂ + 0.5
+    let input = ndarray::arr1(&[1.0, 1.0]).mapv(Com::from_num).into_dyn();
+    let output = model.infer_locally(input).unwrap();
+    assert_eq!(output[0], Com::from_num(1.75));
+}
+
+#[test]
+fn test_load_transposes_only_matrices() {
+    let mut tensors = HashMap::from([(
+        "conv.weight".to_owned(),
+        ArrayD::<f64>::zeros(ndarray::IxDyn(&[2, 1, 3, 3])),
+    )]);
+    let architecture = serde_json::json!({
+        "layers": [{
+            "type": "Conv2DLayer",
+            "kernels": {"tensor": "conv.weight", "transpose": true}
+        }]
+    });
+
+    let error = load(architecture, &mut tensors, Rounding::Nearest).unwrap_err();
+    assert!(format!("{:#}", error).contains("only matrices may be transposed"));
+}
+
+#[test]
+fn test_load_keeps_batch_norms_real_valued() {
+    use crate::Com;
+
+    let mut tensors = HashMap::from([
+        ("fc.weight".to_owned(), ndarray::arr2(&[[1.0]]).into_dyn()),
+        ("fc.bias".to_owned(), ndarray::arr1(&[0.0]).into_dyn()),
+        ("bn.weight".to_owned(), ndarray::arr1(&[0.5]).into_dyn()),
+        ("bn.bias".to_owned(), ndarray::arr1(&[0.0]).into_dyn()),
+        (
+            "bn.running_mean".to_owned(),
+            ndarray::arr1(&[0.0]).into_dyn(),
+        ),
+        (
+            "bn.running_var".to_owned(),
+            ndarray::arr1(&[0.0625]).into_dyn(),
+        ),
+    ]);
+    let architecture = serde_json::json!({
+        "layers": [
+            {"type": "DenseLayer", "weights": {"tensor": "fc.weight"}, "biases": {"tensor": "fc.bias"}},
+            {"type": "BatchNormLayer",
+             "mean": {"tensor": "bn.running_mean"},
+             "variance": {"tensor": "bn.running_var"},
+             "gamma": {"tensor": "bn.weight"},
+             "beta": {"tensor": "bn.bias"},
+             "epsilon": 0.0}
+        ]
+    });
+
+    // The variance would round to 0, but only the folded weights are quantized
+    let (model, report) = load(architecture, &mut tensors, Rounding::Nearest).unwrap();
+    assert_eq!(report.tensors.len(), 2);
+    let input = ndarray::arr1(&[1.0]).mapv(Com::from_num).into_dyn();
+    assert_eq!(model.infer_locally(input).unwrap()[0], Com::from_num(2.0));
+}
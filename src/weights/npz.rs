@@ -0,0 +1,87 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::{bail, Context as _};
+use ndarray::{ArrayD, IxDyn, ShapeBuilder};
+use npyz::{npz::NpzArchive, DType, Order};
+
+use super::TensorSource;
+
+/// A NumPy `.npz` archive, as written by `numpy.savez`.
+pub struct NpzFile(NpzArchive<BufReader<File>>);
+
+impl NpzFile {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(NpzFile(NpzArchive::open(path)?))
+    }
+}
+
+impl TensorSource for NpzFile {
+    fn tensor(&mut self, name: &str) -> anyhow::Result<ArrayD<f64>> {
+        let array = self
+            .0
+            .by_name(name)?
+            .with_context(|| format!("The array '{}' does not exist", name))?;
+
+        let shape: Vec<usize> = array.shape().iter().map(|&d| d as usize).collect();
+        let fortran_order = array.order() == Order::Fortran;
+        let data: Vec<f64> = match array.dtype() {
+            DType::Plain(ty) if ty.to_string() == "<f4" => array
+                .into_vec::<f32>()?
+                .into_iter()
+                .map(|x| x as f64)
+                .collect(),
+            DType::Plain(ty) if ty.to_string() == "<f8" => array.into_vec::<f64>()?,
+            dtype => bail!(
+                "The array '{}' has dtype {}, expected float32 or float64",
+                name,
+                dtype.descr()
+            ),
+        };
+
+        Ok(ArrayD::from_shape_vec(
+            IxDyn(&shape).set_f(fortran_order),
+            data,
+        )?)
+    }
+}
+
+#[test]
+fn test_npz_round_trip() {
+    use npyz::npz::NpzWriter;
+
+    let path = std::env::temp_dir().join(format!("neuronveil-{}.npz", std::process::id()));
+    {
+        let mut npz = NpzWriter::create(&path).unwrap();
+        let mut writer = npz
+            .array("single", Default::default())
+            .unwrap()
+            .default_dtype()
+            .shape(&[2, 3])
+            .begin_nd()
+            .unwrap();
+        writer.extend([1.0f32, 2.0, 3.0, -4.0, 0.5, 6.0]).unwrap();
+        writer.finish().unwrap();
+        let mut writer = npz
+            .array("double", Default::default())
+            .unwrap()
+            .default_dtype()
+            .shape(&[3])
+            .begin_nd()
+            .unwrap();
+        writer.extend([0.1f64, -0.2, 1e12]).unwrap();
+        writer.finish().unwrap();
+    }
+
+    let mut file = NpzFile::open(&path).unwrap();
+    assert_eq!(
+        file.tensor("single").unwrap(),
+        ndarray::arr2(&[[1.0, 2.0, 3.0], [-4.0, 0.5, 6.0]]).into_dyn()
+    );
+    assert_eq!(
+        file.tensor("double").unwrap(),
+        ndarray::arr1(&[0.1, -0.2, 1e12]).into_dyn()
+    );
+    assert!(file.tensor("missing").is_err());
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use anyhow::bail;
+use ndarray::{ArrayD, IxDyn};
+use safetensors::{Dtype, SafeTensors};
+
+use super::TensorSource;
+
+/// A `.safetensors` file, as written by the safetensors library.
+pub struct SafeTensorsFile(Vec<u8>);
+
+impl SafeTensorsFile {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        // Fail early on a malformed header
+        SafeTensors::deserialize(&bytes)?;
+        Ok(SafeTensorsFile(bytes))
+    }
+}
+
+impl TensorSource for SafeTensorsFile {
+    fn tensor(&mut self, name: &str) -> anyhow::Result<ArrayD<f64>> {
+        let tensors = SafeTensors::deserialize(&self.0)?;
+        let tensor = tensors.tensor(name)?;
+
+        // Tensors are stored little-endian and in row-major order
+        let data: Vec<f64> = match tensor.dtype() {
+            Dtype::F32 => tensor
+                .data()
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+                .collect(),
+            Dtype::F64 => tensor
+                .data()
+                .chunks_exact(8)
+                .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+            dtype => bail!(
+                "The tensor '{}' has dtype {:?}, expected F32 or F64",
+                name,
+                dtype
+            ),
+        };
+
+        Ok(ArrayD::from_shape_vec(IxDyn(tensor.shape()), data)?)
+    }
+}
+
+#[test]
+fn test_safetensors_round_trip() {
+    use safetensors::tensor::TensorView;
+
+    let single: Vec<u8> = [1.0f32, 2.0, 3.0, -4.0, 0.5, 6.0]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let double: Vec<u8> = [0.1f64, -0.2, 1e12]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let bytes = safetensors::serialize(
+        [
+            (
+                "single",
+                TensorView::new(Dtype::F32, vec![2, 3], &single).unwrap(),
+            ),
+            (
+                "double",
+                TensorView::new(Dtype::F64, vec![3], &double).unwrap(),
+            ),
+        ],
+        &None,
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("neuronveil-{}.safetensors", std::process::id()));
+    std::fs::write(&path, bytes).unwrap();
+
+    let mut file = SafeTensorsFile::open(&path).unwrap();
+    assert_eq!(
+        file.tensor("single").unwrap(),
+        ndarray::arr2(&[[1.0, 2.0, 3.0], [-4.0, 0.5, 6.0]]).into_dyn()
+    );
+    assert_eq!(
+        file.tensor("double").unwrap(),
+        ndarray::arr1(&[0.1, -0.2, 1e12]).into_dyn()
+    );
+    assert!(file.tensor("missing").is_err());
+
+    std::fs::remove_file(path).unwrap();
+}